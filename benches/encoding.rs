@@ -0,0 +1,116 @@
+//! Wallet file size and codec speed for each encoding.
+//!
+//! Run with `cargo bench --bench encoding`. Each wallet has three owners of
+//! one scheme and a proposal signed by all of them, so signatures dominate
+//! the size the way they do in practice.
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use quantum_safe_multisig::armor::{self, WALLET_LABEL};
+use quantum_safe_multisig::format::{self, Encoding};
+use quantum_safe_multisig::signer::MemorySigner;
+use quantum_safe_multisig::{Algorithm, OwnerKey, QuantumSafeWallet, Signer, Transaction};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const OWNERS: [&str; 3] = ["alice", "bob", "carol"];
+const ITERATIONS: u32 = 50;
+
+fn main() {
+    println!(
+        "{:<20} {:>12} {:>12} {:>12} {:>12} {:>12} {:>12}",
+        "algorithm", "json (v1)", "json", "cbor", "cbor armor", "cbor enc", "cbor dec"
+    );
+    for algorithm in [
+        Algorithm::MlDsa65,
+        Algorithm::Falcon512,
+        Algorithm::SphincsShake128s,
+        Algorithm::SphincsSha2_256f,
+    ] {
+        let wallet = signed_wallet(algorithm);
+        let json = format::encode(&wallet, Encoding::Json).unwrap();
+        let cbor = format::encode(&wallet, Encoding::Cbor).unwrap();
+        let armored = armor::armor(WALLET_LABEL, &cbor);
+        let encode = time(|| format::encode(&wallet, Encoding::Cbor).unwrap());
+        let decode = time(|| format::decode(&cbor).unwrap());
+        println!(
+            "{:<20} {:>12} {:>12} {:>12} {:>12} {:>12.2?} {:>12.2?}",
+            algorithm.name(),
+            legacy_json_len(&json),
+            json.len(),
+            cbor.len(),
+            armored.len(),
+            encode,
+            decode,
+        );
+    }
+}
+
+fn signed_wallet(algorithm: Algorithm) -> QuantumSafeWallet {
+    let signers: Vec<MemorySigner> = OWNERS
+        .iter()
+        .map(|_| MemorySigner::generate(algorithm))
+        .collect();
+    let owners: HashMap<String, OwnerKey> = OWNERS
+        .iter()
+        .zip(&signers)
+        .map(|(name, signer)| {
+            (
+                name.to_string(),
+                OwnerKey::Single(signer.public_key().unwrap()),
+            )
+        })
+        .collect();
+    let mut wallet = QuantumSafeWallet::new("mainnet", owners, 2).unwrap();
+    let id = wallet
+        .propose(
+            "alice",
+            Transaction {
+                recipient: "qsc1recipient".to_string(),
+                amount: 1_000,
+                asset: "QSC".to_string(),
+                memo: "benchmark".to_string(),
+                expiry: None,
+                nonce: 0,
+            },
+        )
+        .unwrap();
+    for (name, signer) in OWNERS.iter().zip(&signers) {
+        wallet.sign_transaction(&id, name, signer).unwrap();
+    }
+    wallet
+}
+
+/// Size of the same document with bytes as JSON integer arrays, as
+/// version 1 files stored them.
+fn legacy_json_len(json: &[u8]) -> usize {
+    fn expand(value: &mut Value) {
+        match value {
+            Value::Object(map) => {
+                if map.contains_key("algorithm") {
+                    if let Some(Value::String(bytes)) = map.get("bytes") {
+                        let bytes = STANDARD.decode(bytes).unwrap();
+                        map.insert("bytes".to_string(), bytes.into());
+                    }
+                }
+                map.values_mut().for_each(expand);
+            }
+            Value::Array(items) => items.iter_mut().for_each(expand),
+            _ => {}
+        }
+    }
+    let mut document: Value = serde_json::from_slice(json).unwrap();
+    expand(&mut document);
+    serde_json::to_vec(&document).unwrap().len()
+}
+
+/// Mean time of one call of `f`.
+fn time<T>(mut f: impl FnMut() -> T) -> Duration {
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        black_box(f());
+    }
+    start.elapsed() / ITERATIONS
+}
@@ -0,0 +1,142 @@
+//! ASCII armor for pasting binary documents into text channels.
+//!
+//! ```text
+//! -----BEGIN QUANTUM SAFE MULTISIG WALLET-----
+//! o2Zmb3JtYXR4HHF1YW50dW1fc2FmZV9tdWx0aXNpZy93YWxsZXRndmVyc2lvbgJm
+//! ...
+//! -----END QUANTUM SAFE MULTISIG WALLET-----
+//! ```
+//!
+//! The body is standard base64 in 64-column lines. Bech32m was considered,
+//! but its checksum is only specified for strings far shorter than a single
+//! SPHINCS+ signature.
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+
+/// Label of armored wallets.
+pub const WALLET_LABEL: &str = "QUANTUM SAFE MULTISIG WALLET";
+
+/// Label of armored proposals.
+pub const PROPOSAL_LABEL: &str = "QUANTUM SAFE MULTISIG PROPOSAL";
+
+/// Label of armored signing requests.
+pub const SIGNING_REQUEST_LABEL: &str = "QUANTUM SAFE MULTISIG SIGNING REQUEST";
+
+/// Label of armored partial signatures.
+pub const PARTIAL_SIGNATURES_LABEL: &str = "QUANTUM SAFE MULTISIG PARTIAL SIGNATURES";
+
+/// Every label this crate writes.
+pub const LABELS: [&str; 4] = [
+    WALLET_LABEL,
+    PROPOSAL_LABEL,
+    SIGNING_REQUEST_LABEL,
+    PARTIAL_SIGNATURES_LABEL,
+];
+
+const LINE_WIDTH: usize = 64;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ArmorError {
+    #[error("missing \"-----BEGIN {0}-----\" line")]
+    MissingBegin(&'static str),
+    #[error("missing \"-----END {0}-----\" line")]
+    MissingEnd(&'static str),
+    #[error("invalid base64 in armor: {0}")]
+    Base64(#[from] base64::DecodeError),
+}
+
+pub fn armor(label: &str, bytes: &[u8]) -> String {
+    let body = STANDARD.encode(bytes);
+    let mut out = format!("-----BEGIN {}-----\n", label);
+    for line in body.as_bytes().chunks(LINE_WIDTH) {
+        // base64 output is ASCII, so any split is on a character boundary.
+        out.push_str(std::str::from_utf8(line).unwrap_or_default());
+        out.push('\n');
+    }
+    out.push_str(&format!("-----END {}-----\n", label));
+    out
+}
+
+/// Extract the bytes armored under `label` from `text`, ignoring anything
+/// before the BEGIN line and after the END line.
+pub fn dearmor(label: &'static str, text: &str) -> Result<Vec<u8>, ArmorError> {
+    let begin = format!("-----BEGIN {}-----", label);
+    let end = format!("-----END {}-----", label);
+    let mut lines = text
+        .lines()
+        .map(str::trim)
+        .skip_while(|line| *line != begin);
+    if lines.next().is_none() {
+        return Err(ArmorError::MissingBegin(label));
+    }
+    let mut body = String::new();
+    for line in lines {
+        if line == end {
+            return Ok(STANDARD.decode(body)?);
+        }
+        body.push_str(line);
+    }
+    Err(ArmorError::MissingEnd(label))
+}
+
+/// The known label `text` is armored under, if any.
+pub fn label_of(text: &str) -> Option<&'static str> {
+    LABELS
+        .into_iter()
+        .find(|label| text.contains(&format!("-----BEGIN {}-----", label)))
+}
+
+/// Whether `bytes` starts with an armor header of any label.
+pub fn is_armored(bytes: &[u8]) -> bool {
+    bytes.trim_ascii_start().starts_with(b"-----BEGIN ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_through_surrounding_text() {
+        let bytes: Vec<u8> = (0..=255).collect();
+        let armored = armor(PROPOSAL_LABEL, &bytes);
+        assert!(armored
+            .lines()
+            .skip(1)
+            .take_while(|line| !line.starts_with("-----"))
+            .all(|line| line.len() <= LINE_WIDTH));
+        let pasted = format!(
+            "Please sign:\n\n  {}\nThanks\n",
+            armored.replace('\n', "\n  ")
+        );
+        assert_eq!(dearmor(PROPOSAL_LABEL, &pasted).unwrap(), bytes);
+        assert_eq!(label_of(&pasted), Some(PROPOSAL_LABEL));
+        assert!(is_armored(armored.as_bytes()));
+        assert!(!is_armored(b"{\"format\": \"...\"}"));
+    }
+
+    #[test]
+    fn missing_or_garbled_armor_is_refused() {
+        let armored = armor(WALLET_LABEL, b"wallet");
+        assert!(matches!(
+            dearmor(PROPOSAL_LABEL, &armored),
+            Err(ArmorError::MissingBegin(PROPOSAL_LABEL))
+        ));
+        let unterminated: String = armored.lines().take(2).collect::<Vec<_>>().join("\n");
+        assert!(matches!(
+            dearmor(WALLET_LABEL, &unterminated),
+            Err(ArmorError::MissingEnd(WALLET_LABEL))
+        ));
+        let garbled = armored.replacen("d2FsbGV0", "d2F!bGV0", 1);
+        assert!(matches!(
+            dearmor(WALLET_LABEL, &garbled),
+            Err(ArmorError::Base64(_))
+        ));
+        let mislabelled = armored.replace("-----END QUANTUM SAFE MULTISIG WALLET", "-----END X");
+        assert!(matches!(
+            dearmor(WALLET_LABEL, &mislabelled),
+            Err(ArmorError::MissingEnd(_))
+        ));
+        assert_eq!(label_of("no armor here"), None);
+    }
+}
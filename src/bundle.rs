@@ -0,0 +1,448 @@
+//! Portable signing bundles for owners whose keys never leave an offline
+//! machine.
+//!
+//! Signing offline takes three steps, and only the first and last need the
+//! wallet file:
+//!
+//! 1. [`QuantumSafeWallet::signing_request`] exports a [`SigningRequest`]:
+//!    the proposal, the wallet ID and chain it is bound to, the owners' keys
+//!    and a hash of the payload they sign.
+//! 2. [`SigningRequest::sign`] signs it wherever the owner's key lives and
+//!    returns [`PartialSignatures`].
+//! 3. [`QuantumSafeWallet::combine`] checks the partial signatures and adds
+//!    them to the pending proposal.
+//!
+//! Every step recomputes the signing payload from what it holds and refuses
+//! a bundle whose wallet ID, nonce or payload hash disagree with it, so a
+//! request cannot be edited in transit and partial signatures cannot land on
+//! the wrong wallet or proposal.
+//!
+//! [`QuantumSafeWallet::signing_request`]: crate::QuantumSafeWallet::signing_request
+//! [`QuantumSafeWallet::combine`]: crate::QuantumSafeWallet::combine
+
+use crate::crypto::{self, OwnerKey, Signature};
+use crate::error::WalletError;
+use crate::format::{self, Encoding};
+use crate::hash::Hash256;
+use crate::payload::{SigningPayload, WalletId};
+use crate::proposal::{Action, Proposal, ProposalId, TimeWindow};
+use crate::signer::Signer;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+
+/// Format name in the header of signing requests.
+pub const SIGNING_REQUEST_FORMAT: &str = "quantum_safe_multisig/signing-request";
+
+/// Format name in the header of partial-signature files.
+pub const PARTIAL_SIGNATURES_FORMAT: &str = "quantum_safe_multisig/partial-signatures";
+
+/// Schema version of both bundle formats. Version 2 added the time window
+/// to signing requests.
+pub const BUNDLE_VERSION: u64 = 2;
+
+/// Digest of a signing payload, carried in bundles so each stage can check
+/// it is signing or combining for the same bytes.
+pub fn payload_hash(payload: &[u8]) -> Hash256 {
+    Hash256::of("signing-payload", &[payload])
+}
+
+/// Everything an offline owner needs to sign one proposal.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SigningRequest {
+    wallet_id: WalletId,
+    chain_id: String,
+    proposal_id: ProposalId,
+    creator: String,
+    created_at: u64,
+    nonce: u64,
+    action: Action,
+    #[serde(default, skip_serializing_if = "TimeWindow::is_unbounded")]
+    window: TimeWindow,
+    owners: BTreeMap<String, OwnerKey>,
+    payload_hash: Hash256,
+}
+
+impl SigningRequest {
+    pub(crate) fn new(
+        wallet_id: WalletId,
+        chain_id: &str,
+        proposal: &Proposal,
+        owners: BTreeMap<String, OwnerKey>,
+    ) -> Self {
+        let payload = SigningPayload {
+            chain_id,
+            wallet_id: &wallet_id,
+            nonce: proposal.nonce(),
+            message: &proposal.action().encode(),
+            window: proposal.window(),
+        }
+        .to_bytes();
+        Self {
+            wallet_id,
+            chain_id: chain_id.to_string(),
+            proposal_id: *proposal.id(),
+            creator: proposal.creator().to_string(),
+            created_at: proposal.created_at(),
+            nonce: proposal.nonce(),
+            action: proposal.action().clone(),
+            window: *proposal.window(),
+            owners,
+            payload_hash: payload_hash(&payload),
+        }
+    }
+
+    pub fn wallet_id(&self) -> &WalletId {
+        &self.wallet_id
+    }
+
+    pub fn chain_id(&self) -> &str {
+        &self.chain_id
+    }
+
+    pub fn proposal_id(&self) -> &ProposalId {
+        &self.proposal_id
+    }
+
+    pub fn nonce(&self) -> u64 {
+        self.nonce
+    }
+
+    pub fn action(&self) -> &Action {
+        &self.action
+    }
+
+    pub fn window(&self) -> &TimeWindow {
+        &self.window
+    }
+
+    pub fn payload_hash(&self) -> &Hash256 {
+        &self.payload_hash
+    }
+
+    pub fn owner_key(&self, owner: &str) -> Option<&OwnerKey> {
+        self.owners.get(owner)
+    }
+
+    /// The bytes owners sign, rebuilt from the request's own fields.
+    pub fn payload(&self) -> Vec<u8> {
+        SigningPayload {
+            chain_id: &self.chain_id,
+            wallet_id: &self.wallet_id,
+            nonce: self.action.nonce(),
+            message: &self.action.encode(),
+            window: &self.window,
+        }
+        .to_bytes()
+    }
+
+    /// Check that the proposal ID, nonce and payload hash all match the
+    /// action the request carries.
+    pub fn validate(&self) -> Result<(), WalletError> {
+        let computed =
+            Proposal::compute_id(&self.creator, &self.action, &self.window, self.created_at);
+        if computed != self.proposal_id {
+            return Err(WalletError::TamperedProposal(self.proposal_id));
+        }
+        self.action.validate()?;
+        if self.nonce != self.action.nonce() {
+            return Err(WalletError::BundleMismatch {
+                field: "nonce",
+                expected: self.action.nonce().to_string(),
+                found: self.nonce.to_string(),
+            });
+        }
+        let computed = payload_hash(&self.payload());
+        if computed != self.payload_hash {
+            return Err(WalletError::BundleMismatch {
+                field: "payload hash",
+                expected: computed.to_string(),
+                found: self.payload_hash.to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Sign the request as `owner`. The request is validated first, the
+    /// signer's key must be the one registered for `owner`, and the
+    /// signature is verified before it is returned.
+    pub fn sign(&self, owner: &str, signer: &dyn Signer) -> Result<PartialSignatures, WalletError> {
+        self.validate()?;
+        let algorithm = signer.algorithm();
+        let key = self
+            .owners
+            .get(owner)
+            .ok_or_else(|| WalletError::UnknownOwner(owner.to_string()))?
+            .components()
+            .into_iter()
+            .find(|key| key.algorithm() == algorithm)
+            .ok_or_else(|| WalletError::AlgorithmMismatch {
+                owner: owner.to_string(),
+                algorithm,
+            })?;
+        let actual = signer.public_key()?.fingerprint();
+        if actual != key.fingerprint() {
+            return Err(WalletError::KeyMismatch {
+                owner: owner.to_string(),
+                expected: key.fingerprint(),
+                actual,
+            });
+        }
+        let payload = self.payload();
+        let signature = signer.sign(&payload)?;
+        crypto::verify(&payload, &signature, key).map_err(|source| WalletError::BadSignature {
+            owner: owner.to_string(),
+            source,
+        })?;
+        Ok(PartialSignatures {
+            wallet_id: self.wallet_id,
+            proposal_id: self.proposal_id,
+            nonce: self.nonce,
+            payload_hash: self.payload_hash,
+            owner: owner.to_string(),
+            signatures: vec![signature],
+        })
+    }
+
+    pub fn encode(&self, encoding: Encoding) -> Result<Vec<u8>, WalletError> {
+        format::to_vec(
+            &RequestEnvelope {
+                format: SIGNING_REQUEST_FORMAT,
+                version: BUNDLE_VERSION,
+                request: self,
+            },
+            encoding,
+        )
+    }
+
+    /// Parse a signing request and [validate](Self::validate) it.
+    pub fn decode(bytes: &[u8]) -> Result<Self, WalletError> {
+        let request: Self = format::tagged(
+            parse(bytes)?,
+            SIGNING_REQUEST_FORMAT,
+            BUNDLE_VERSION,
+            "request",
+        )?;
+        request.validate()?;
+        Ok(request)
+    }
+}
+
+/// Signatures one owner made offline on a [`SigningRequest`], with enough of
+/// the request to tell which wallet, proposal and payload they are for.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PartialSignatures {
+    wallet_id: WalletId,
+    proposal_id: ProposalId,
+    nonce: u64,
+    payload_hash: Hash256,
+    owner: String,
+    signatures: Vec<Signature>,
+}
+
+impl PartialSignatures {
+    pub fn wallet_id(&self) -> &WalletId {
+        &self.wallet_id
+    }
+
+    pub fn proposal_id(&self) -> &ProposalId {
+        &self.proposal_id
+    }
+
+    pub fn nonce(&self) -> u64 {
+        self.nonce
+    }
+
+    pub fn payload_hash(&self) -> &Hash256 {
+        &self.payload_hash
+    }
+
+    pub fn owner(&self) -> &str {
+        &self.owner
+    }
+
+    pub fn signatures(&self) -> &[Signature] {
+        &self.signatures
+    }
+
+    pub fn encode(&self, encoding: Encoding) -> Result<Vec<u8>, WalletError> {
+        format::to_vec(
+            &PartialEnvelope {
+                format: PARTIAL_SIGNATURES_FORMAT,
+                version: BUNDLE_VERSION,
+                signatures: self,
+            },
+            encoding,
+        )
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, WalletError> {
+        format::tagged(
+            parse(bytes)?,
+            PARTIAL_SIGNATURES_FORMAT,
+            BUNDLE_VERSION,
+            "signatures",
+        )
+    }
+}
+
+/// Parse a bundle, reading version 1 as the current version: requests from
+/// before time windows have none, which reads as open-ended, and partial
+/// signatures have not changed.
+fn parse(bytes: &[u8]) -> Result<Value, WalletError> {
+    let mut document = format::parse(bytes)?;
+    if document.get("version").and_then(Value::as_u64) == Some(1) {
+        document["version"] = json!(BUNDLE_VERSION);
+    }
+    Ok(document)
+}
+
+#[derive(Serialize)]
+struct RequestEnvelope<'a> {
+    format: &'static str,
+    version: u64,
+    request: &'a SigningRequest,
+}
+
+#[derive(Serialize)]
+struct PartialEnvelope<'a> {
+    format: &'static str,
+    version: u64,
+    signatures: &'a PartialSignatures,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::Algorithm;
+    use crate::signer::MemorySigner;
+    use crate::transaction::Transaction;
+    use crate::wallet::QuantumSafeWallet;
+    use std::collections::HashMap;
+
+    struct Setup {
+        wallet: QuantumSafeWallet,
+        proposal_id: ProposalId,
+        signer: MemorySigner,
+        owners: HashMap<String, OwnerKey>,
+    }
+
+    fn setup() -> Setup {
+        let signers: Vec<MemorySigner> = (0..2)
+            .map(|_| MemorySigner::generate(Algorithm::MlDsa44))
+            .collect();
+        let owners: HashMap<String, OwnerKey> = ["alice", "bob"]
+            .iter()
+            .zip(&signers)
+            .map(|(name, signer)| {
+                let key = OwnerKey::single(signer.public_key().unwrap()).unwrap();
+                (name.to_string(), key)
+            })
+            .collect();
+        let mut wallet = QuantumSafeWallet::new("testnet", owners.clone(), 2).unwrap();
+        let proposal_id = wallet
+            .propose(
+                "alice",
+                Transaction {
+                    recipient: "qsc1recipient".to_string(),
+                    amount: 5,
+                    asset: "QSC".to_string(),
+                    memo: String::new(),
+                    expiry: None,
+                    nonce: 0,
+                },
+            )
+            .unwrap();
+        Setup {
+            wallet,
+            proposal_id,
+            signer: signers.into_iter().next().unwrap(),
+            owners,
+        }
+    }
+
+    fn sign(setup: &Setup) -> PartialSignatures {
+        let request = setup.wallet.signing_request(&setup.proposal_id).unwrap();
+        let request = SigningRequest::decode(&request.encode(Encoding::Cbor).unwrap()).unwrap();
+        let partial = request.sign("alice", &setup.signer).unwrap();
+        PartialSignatures::decode(&partial.encode(Encoding::Json).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn request_sign_combine() {
+        let mut setup = setup();
+        let partial = sign(&setup);
+        assert_eq!(setup.wallet.combine(&partial).unwrap(), setup.proposal_id);
+        assert_eq!(
+            setup.wallet.approvals(&setup.proposal_id).unwrap(),
+            ["alice"]
+        );
+    }
+
+    #[test]
+    fn combine_refuses_another_wallet() {
+        let setup = setup();
+        let partial = sign(&setup);
+        let mut other = QuantumSafeWallet::new("testnet", setup.owners, 1).unwrap();
+        assert!(matches!(
+            other.combine(&partial),
+            Err(WalletError::BundleMismatch {
+                field: "wallet ID",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn combine_refuses_a_mismatched_nonce_or_payload() {
+        let mut setup = setup();
+        let mut partial = sign(&setup);
+        partial.nonce += 1;
+        assert!(matches!(
+            setup.wallet.combine(&partial),
+            Err(WalletError::BundleMismatch { field: "nonce", .. })
+        ));
+        let mut partial = sign(&setup);
+        partial.payload_hash = payload_hash(b"something else");
+        assert!(matches!(
+            setup.wallet.combine(&partial),
+            Err(WalletError::BundleMismatch {
+                field: "payload hash",
+                ..
+            })
+        ));
+        assert!(setup
+            .wallet
+            .approvals(&setup.proposal_id)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn edited_requests_are_refused() {
+        let setup = setup();
+        let mut request = setup.wallet.signing_request(&setup.proposal_id).unwrap();
+        request.nonce += 1;
+        assert!(matches!(
+            request.sign("alice", &setup.signer),
+            Err(WalletError::BundleMismatch { field: "nonce", .. })
+        ));
+        let mut request = setup.wallet.signing_request(&setup.proposal_id).unwrap();
+        request.wallet_id = Hash256::of("wallet", &[b"elsewhere"]);
+        assert!(matches!(
+            request.sign("alice", &setup.signer),
+            Err(WalletError::BundleMismatch {
+                field: "payload hash",
+                ..
+            })
+        ));
+        let mut request = setup.wallet.signing_request(&setup.proposal_id).unwrap();
+        if let Action::Transfer(transaction) = &mut request.action {
+            transaction.amount = 5_000;
+        }
+        assert!(matches!(
+            request.sign("alice", &setup.signer),
+            Err(WalletError::TamperedProposal(_))
+        ));
+    }
+}
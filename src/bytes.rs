@@ -0,0 +1,110 @@
+//! Serde representation of raw byte fields such as keys and signatures.
+//!
+//! Use with `#[serde(with = "crate::bytes")]`. Binary formats get a native
+//! byte string; human-readable ones get standard base64 rather than serde's
+//! default array of integers, which is four times the size. Integer arrays
+//! are still accepted so files written before this existed keep loading.
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::de::{self, SeqAccess, Visitor};
+use serde::{Deserializer, Serializer};
+use std::fmt;
+
+pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    if serializer.is_human_readable() {
+        serializer.serialize_str(&STANDARD.encode(bytes))
+    } else {
+        serializer.serialize_bytes(bytes)
+    }
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    if deserializer.is_human_readable() {
+        deserializer.deserialize_any(BytesVisitor)
+    } else {
+        deserializer.deserialize_byte_buf(BytesVisitor)
+    }
+}
+
+struct BytesVisitor;
+
+impl<'de> Visitor<'de> for BytesVisitor {
+    type Value = Vec<u8>;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a byte string, a base64 string or an array of bytes")
+    }
+
+    fn visit_str<E: de::Error>(self, s: &str) -> Result<Vec<u8>, E> {
+        STANDARD.decode(s).map_err(E::custom)
+    }
+
+    fn visit_bytes<E: de::Error>(self, bytes: &[u8]) -> Result<Vec<u8>, E> {
+        Ok(bytes.to_vec())
+    }
+
+    fn visit_byte_buf<E: de::Error>(self, bytes: Vec<u8>) -> Result<Vec<u8>, E> {
+        Ok(bytes)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Vec<u8>, A::Error> {
+        // The length comes from the input, so only trust it so far.
+        let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0).min(4096));
+        while let Some(byte) = seq.next_element()? {
+            bytes.push(byte);
+        }
+        Ok(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Key {
+        #[serde(with = "crate::bytes")]
+        bytes: Vec<u8>,
+    }
+
+    fn cbor(key: &Key) -> Vec<u8> {
+        let mut out = Vec::new();
+        ciborium::into_writer(key, &mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn json_holds_base64_and_cbor_a_byte_string() {
+        let key = Key {
+            bytes: vec![0, 1, 254, 255],
+        };
+        let json = serde_json::to_string(&key).unwrap();
+        assert_eq!(json, r#"{"bytes":"AAH+/w=="}"#);
+        assert_eq!(serde_json::from_str::<Key>(&json).unwrap(), key);
+        let cbor = cbor(&key);
+        assert!(cbor.ends_with(&[0x44, 0, 1, 254, 255]));
+        assert_eq!(
+            ciborium::from_reader::<Key, _>(cbor.as_slice()).unwrap(),
+            key
+        );
+    }
+
+    #[test]
+    fn legacy_integer_arrays_still_load() {
+        let key: Key = serde_json::from_str(r#"{"bytes":[0,1,254,255]}"#).unwrap();
+        assert_eq!(key.bytes, [0, 1, 254, 255]);
+        assert!(serde_json::from_str::<Key>(r#"{"bytes":[256]}"#).is_err());
+        assert!(serde_json::from_str::<Key>(r#"{"bytes":"not base64!"}"#).is_err());
+    }
+
+    #[test]
+    fn claimed_lengths_are_not_trusted() {
+        // A map with one entry, "bytes", holding an array that claims
+        // 2^32 - 1 elements but has only one.
+        let mut input = vec![0xa1, 0x65];
+        input.extend_from_slice(b"bytes");
+        input.extend_from_slice(&[0x9a, 0xff, 0xff, 0xff, 0xff, 0x01]);
+        assert!(ciborium::from_reader::<Key, _>(input.as_slice()).is_err());
+    }
+}
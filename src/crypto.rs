@@ -0,0 +1,676 @@
+//! Signature schemes and algorithm-tagged keys and signatures.
+//!
+//! Every key and signature carries the [`Algorithm`] it belongs to, so one
+//! wallet can mix owners on different schemes and verification dispatches on
+//! the tag instead of assuming a single scheme. Classical schemes only appear
+//! as one half of a hybrid [`OwnerKey::Composite`].
+
+use crate::hash::Hash256;
+use pqcrypto_traits::sign::{DetachedSignature as _, PublicKey as _, SecretKey as _};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use zeroize::Zeroizing;
+
+/// Supported signature schemes.
+///
+/// The SPHINCS+ variants are the round-3 "simple" parameter sets that
+/// SLH-DSA (FIPS 205) was standardised from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(into = "&'static str", try_from = "String")]
+pub enum Algorithm {
+    Ed25519,
+    EcdsaP256,
+    SphincsSha2_128s,
+    SphincsSha2_128f,
+    SphincsSha2_192s,
+    SphincsSha2_192f,
+    SphincsSha2_256s,
+    SphincsSha2_256f,
+    SphincsShake128s,
+    SphincsShake128f,
+    SphincsShake192s,
+    SphincsShake192f,
+    SphincsShake256s,
+    SphincsShake256f,
+    MlDsa44,
+    MlDsa65,
+    MlDsa87,
+    Falcon512,
+    Falcon1024,
+}
+
+/// Run `$body` with `$m` bound to the pqcrypto module implementing `$alg`,
+/// or `$classical` if `$alg` is not a post-quantum scheme.
+macro_rules! with_scheme {
+    ($alg:expr, $m:ident => $body:expr, classical => $classical:expr) => {
+        match $alg {
+            Algorithm::Ed25519 | Algorithm::EcdsaP256 => $classical,
+            Algorithm::SphincsSha2_128s => {
+                use pqcrypto_sphincsplus::sphincssha2128ssimple as $m;
+                $body
+            }
+            Algorithm::SphincsSha2_128f => {
+                use pqcrypto_sphincsplus::sphincssha2128fsimple as $m;
+                $body
+            }
+            Algorithm::SphincsSha2_192s => {
+                use pqcrypto_sphincsplus::sphincssha2192ssimple as $m;
+                $body
+            }
+            Algorithm::SphincsSha2_192f => {
+                use pqcrypto_sphincsplus::sphincssha2192fsimple as $m;
+                $body
+            }
+            Algorithm::SphincsSha2_256s => {
+                use pqcrypto_sphincsplus::sphincssha2256ssimple as $m;
+                $body
+            }
+            Algorithm::SphincsSha2_256f => {
+                use pqcrypto_sphincsplus::sphincssha2256fsimple as $m;
+                $body
+            }
+            Algorithm::SphincsShake128s => {
+                use pqcrypto_sphincsplus::sphincsshake128ssimple as $m;
+                $body
+            }
+            Algorithm::SphincsShake128f => {
+                use pqcrypto_sphincsplus::sphincsshake128fsimple as $m;
+                $body
+            }
+            Algorithm::SphincsShake192s => {
+                use pqcrypto_sphincsplus::sphincsshake192ssimple as $m;
+                $body
+            }
+            Algorithm::SphincsShake192f => {
+                use pqcrypto_sphincsplus::sphincsshake192fsimple as $m;
+                $body
+            }
+            Algorithm::SphincsShake256s => {
+                use pqcrypto_sphincsplus::sphincsshake256ssimple as $m;
+                $body
+            }
+            Algorithm::SphincsShake256f => {
+                use pqcrypto_sphincsplus::sphincsshake256fsimple as $m;
+                $body
+            }
+            Algorithm::MlDsa44 => {
+                use pqcrypto_mldsa::mldsa44 as $m;
+                $body
+            }
+            Algorithm::MlDsa65 => {
+                use pqcrypto_mldsa::mldsa65 as $m;
+                $body
+            }
+            Algorithm::MlDsa87 => {
+                use pqcrypto_mldsa::mldsa87 as $m;
+                $body
+            }
+            Algorithm::Falcon512 => {
+                use pqcrypto_falcon::falcon512 as $m;
+                $body
+            }
+            Algorithm::Falcon1024 => {
+                use pqcrypto_falcon::falcon1024 as $m;
+                $body
+            }
+        }
+    };
+}
+
+impl Algorithm {
+    pub const ALL: [Algorithm; 19] = [
+        Algorithm::Ed25519,
+        Algorithm::EcdsaP256,
+        Algorithm::SphincsSha2_128s,
+        Algorithm::SphincsSha2_128f,
+        Algorithm::SphincsSha2_192s,
+        Algorithm::SphincsSha2_192f,
+        Algorithm::SphincsSha2_256s,
+        Algorithm::SphincsSha2_256f,
+        Algorithm::SphincsShake128s,
+        Algorithm::SphincsShake128f,
+        Algorithm::SphincsShake192s,
+        Algorithm::SphincsShake192f,
+        Algorithm::SphincsShake256s,
+        Algorithm::SphincsShake256f,
+        Algorithm::MlDsa44,
+        Algorithm::MlDsa65,
+        Algorithm::MlDsa87,
+        Algorithm::Falcon512,
+        Algorithm::Falcon1024,
+    ];
+
+    /// Stable name used in wallet files and on the command line.
+    pub fn name(self) -> &'static str {
+        match self {
+            Algorithm::Ed25519 => "ed25519",
+            Algorithm::EcdsaP256 => "ecdsa-p256",
+            Algorithm::SphincsSha2_128s => "sphincs+-sha2-128s",
+            Algorithm::SphincsSha2_128f => "sphincs+-sha2-128f",
+            Algorithm::SphincsSha2_192s => "sphincs+-sha2-192s",
+            Algorithm::SphincsSha2_192f => "sphincs+-sha2-192f",
+            Algorithm::SphincsSha2_256s => "sphincs+-sha2-256s",
+            Algorithm::SphincsSha2_256f => "sphincs+-sha2-256f",
+            Algorithm::SphincsShake128s => "sphincs+-shake-128s",
+            Algorithm::SphincsShake128f => "sphincs+-shake-128f",
+            Algorithm::SphincsShake192s => "sphincs+-shake-192s",
+            Algorithm::SphincsShake192f => "sphincs+-shake-192f",
+            Algorithm::SphincsShake256s => "sphincs+-shake-256s",
+            Algorithm::SphincsShake256f => "sphincs+-shake-256f",
+            Algorithm::MlDsa44 => "ml-dsa-44",
+            Algorithm::MlDsa65 => "ml-dsa-65",
+            Algorithm::MlDsa87 => "ml-dsa-87",
+            Algorithm::Falcon512 => "falcon-512",
+            Algorithm::Falcon1024 => "falcon-1024",
+        }
+    }
+
+    pub fn is_post_quantum(self) -> bool {
+        !matches!(self, Algorithm::Ed25519 | Algorithm::EcdsaP256)
+    }
+}
+
+impl fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Algorithm {
+    type Err = UnknownAlgorithm;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Algorithm::ALL
+            .into_iter()
+            .find(|alg| alg.name() == s)
+            .ok_or_else(|| UnknownAlgorithm(s.to_string()))
+    }
+}
+
+impl From<Algorithm> for &'static str {
+    fn from(alg: Algorithm) -> &'static str {
+        alg.name()
+    }
+}
+
+impl TryFrom<String> for Algorithm {
+    type Error = UnknownAlgorithm;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("unknown signature algorithm {0:?}")]
+pub struct UnknownAlgorithm(pub String);
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PublicKey {
+    algorithm: Algorithm,
+    #[serde(with = "crate::bytes")]
+    bytes: Vec<u8>,
+}
+
+impl PublicKey {
+    pub fn new(algorithm: Algorithm, bytes: Vec<u8>) -> Self {
+        Self { algorithm, bytes }
+    }
+
+    pub fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Digest identifying this key, for comparing keys held in different places.
+    pub fn fingerprint(&self) -> Hash256 {
+        Hash256::of(
+            "public-key",
+            &[self.algorithm.name().as_bytes(), &self.bytes],
+        )
+    }
+}
+
+pub struct SecretKey {
+    algorithm: Algorithm,
+    bytes: Zeroizing<Vec<u8>>,
+}
+
+impl SecretKey {
+    pub fn from_bytes(algorithm: Algorithm, bytes: Zeroizing<Vec<u8>>) -> Self {
+        Self { algorithm, bytes }
+    }
+
+    pub fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+impl fmt::Debug for SecretKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SecretKey({}, <redacted>)", self.algorithm)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+#[error("malformed {0} secret key")]
+pub struct MalformedSecretKey(pub Algorithm);
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Signature {
+    algorithm: Algorithm,
+    #[serde(with = "crate::bytes")]
+    bytes: Vec<u8>,
+}
+
+impl Signature {
+    pub fn new(algorithm: Algorithm, bytes: Vec<u8>) -> Self {
+        Self { algorithm, bytes }
+    }
+
+    pub fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+/// The key an owner is registered with: a single post-quantum key, or a
+/// classical and a post-quantum key that must both sign every approval.
+///
+/// Serialized with a `type` tag and per-component algorithm tags, so wallet
+/// files describe exactly which schemes an owner uses.
+/// Deserializing checks the schemes the same way [`OwnerKey::single`] and
+/// [`OwnerKey::composite`] do.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(
+    tag = "type",
+    rename_all = "snake_case",
+    try_from = "UncheckedOwnerKey"
+)]
+pub enum OwnerKey {
+    Single(PublicKey),
+    Composite {
+        classical: PublicKey,
+        post_quantum: PublicKey,
+    },
+}
+
+/// An [`OwnerKey`] as read from a file, before its schemes are checked.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum UncheckedOwnerKey {
+    Single(PublicKey),
+    Composite {
+        classical: PublicKey,
+        post_quantum: PublicKey,
+    },
+}
+
+impl TryFrom<UncheckedOwnerKey> for OwnerKey {
+    type Error = OwnerKeyError;
+
+    fn try_from(key: UncheckedOwnerKey) -> Result<Self, Self::Error> {
+        match key {
+            UncheckedOwnerKey::Single(key) => OwnerKey::single(key),
+            UncheckedOwnerKey::Composite {
+                classical,
+                post_quantum,
+            } => OwnerKey::composite(classical, post_quantum),
+        }
+    }
+}
+
+impl OwnerKey {
+    /// Register a post-quantum key on its own. A classical key is refused:
+    /// it is only accepted paired with a post-quantum one.
+    pub fn single(key: PublicKey) -> Result<Self, OwnerKeyError> {
+        let key = OwnerKey::Single(key);
+        key.validate()?;
+        Ok(key)
+    }
+
+    /// Pair a classical key with a post-quantum one.
+    pub fn composite(classical: PublicKey, post_quantum: PublicKey) -> Result<Self, OwnerKeyError> {
+        let key = OwnerKey::Composite {
+            classical,
+            post_quantum,
+        };
+        key.validate()?;
+        Ok(key)
+    }
+
+    /// Check that a single key is post-quantum, and that a composite pairs a
+    /// classical key with a post-quantum one.
+    pub fn validate(&self) -> Result<(), OwnerKeyError> {
+        match self {
+            OwnerKey::Single(key) if !key.algorithm.is_post_quantum() => {
+                Err(OwnerKeyError::ClassicalOnly(key.algorithm))
+            }
+            OwnerKey::Single(_) => Ok(()),
+            OwnerKey::Composite {
+                classical,
+                post_quantum,
+            } => {
+                if classical.algorithm.is_post_quantum() {
+                    return Err(OwnerKeyError::NotClassical(classical.algorithm));
+                }
+                if !post_quantum.algorithm.is_post_quantum() {
+                    return Err(OwnerKeyError::NotPostQuantum(post_quantum.algorithm));
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Component keys, each of which must sign for the owner's approval to count.
+    pub fn components(&self) -> Vec<&PublicKey> {
+        match self {
+            OwnerKey::Single(key) => vec![key],
+            OwnerKey::Composite {
+                classical,
+                post_quantum,
+            } => vec![classical, post_quantum],
+        }
+    }
+
+    pub fn post_quantum(&self) -> &PublicKey {
+        match self {
+            OwnerKey::Single(key) => key,
+            OwnerKey::Composite { post_quantum, .. } => post_quantum,
+        }
+    }
+
+    /// Check that every component key has a valid signature over `message`
+    /// among `signatures`.
+    pub fn verify(&self, message: &[u8], signatures: &[Signature]) -> Result<(), VerifyError> {
+        for key in self.components() {
+            let signature = signatures
+                .iter()
+                .find(|signature| signature.algorithm == key.algorithm)
+                .ok_or(VerifyError::MissingComponent(key.algorithm))?;
+            verify(message, signature, key)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+pub enum OwnerKeyError {
+    #[error("a {0} key on its own is not quantum-safe; pair it with a post-quantum key")]
+    ClassicalOnly(Algorithm),
+    #[error("{0} is not a classical scheme")]
+    NotClassical(Algorithm),
+    #[error("{0} is not a post-quantum scheme")]
+    NotPostQuantum(Algorithm),
+}
+
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+pub enum VerifyError {
+    #[error("{signature} signature cannot be checked against a {key} key")]
+    AlgorithmMismatch {
+        key: Algorithm,
+        signature: Algorithm,
+    },
+    #[error("malformed {0} public key")]
+    MalformedKey(Algorithm),
+    #[error("malformed {0} signature")]
+    MalformedSignature(Algorithm),
+    #[error("{0} signature does not verify")]
+    Invalid(Algorithm),
+    #[error("no {0} signature for a composite key component")]
+    MissingComponent(Algorithm),
+}
+
+/// Generate a fresh keypair for `algorithm` in process memory.
+pub fn keypair(algorithm: Algorithm) -> (PublicKey, SecretKey) {
+    let (public, secret) = with_scheme!(algorithm, scheme => {
+        let (pk, sk) = scheme::keypair();
+        (pk.as_bytes().to_vec(), sk.as_bytes().to_vec())
+    }, classical => classical_keypair(algorithm));
+    (
+        PublicKey::new(algorithm, public),
+        SecretKey {
+            algorithm,
+            bytes: Zeroizing::new(secret),
+        },
+    )
+}
+
+fn classical_keypair(algorithm: Algorithm) -> (Vec<u8>, Vec<u8>) {
+    let mut seed = [0u8; 32];
+    loop {
+        getrandom::getrandom(&mut seed).expect("system RNG unavailable");
+        match algorithm {
+            Algorithm::Ed25519 => {
+                let key = ed25519_dalek::SigningKey::from_bytes(&seed);
+                return (key.verifying_key().to_bytes().to_vec(), seed.to_vec());
+            }
+            // A seed outside the scalar range is rejected; draw again.
+            Algorithm::EcdsaP256 => {
+                if let Ok(key) = p256::ecdsa::SigningKey::from_slice(&seed) {
+                    let public = key.verifying_key().to_encoded_point(true);
+                    return (public.as_bytes().to_vec(), seed.to_vec());
+                }
+            }
+            other => unreachable!("{other} is not a classical scheme"),
+        }
+    }
+}
+
+/// Sign `message` with a secret key held in process memory.
+pub fn sign(message: &[u8], secret_key: &SecretKey) -> Result<Signature, MalformedSecretKey> {
+    let algorithm = secret_key.algorithm;
+    let bytes = with_scheme!(algorithm, scheme => {
+        let sk = scheme::SecretKey::from_bytes(&secret_key.bytes)
+            .map_err(|_| MalformedSecretKey(algorithm))?;
+        scheme::detached_sign(message, &sk).as_bytes().to_vec()
+    }, classical => sign_classical(message, secret_key)?);
+    Ok(Signature::new(algorithm, bytes))
+}
+
+fn sign_classical(message: &[u8], secret_key: &SecretKey) -> Result<Vec<u8>, MalformedSecretKey> {
+    use p256::ecdsa::signature::Signer;
+
+    let algorithm = secret_key.algorithm;
+    let seed: &[u8; 32] = secret_key
+        .bytes
+        .as_slice()
+        .try_into()
+        .map_err(|_| MalformedSecretKey(algorithm))?;
+    match algorithm {
+        Algorithm::Ed25519 => {
+            let key = ed25519_dalek::SigningKey::from_bytes(seed);
+            Ok(key.sign(message).to_bytes().to_vec())
+        }
+        Algorithm::EcdsaP256 => {
+            let key = p256::ecdsa::SigningKey::from_slice(seed)
+                .map_err(|_| MalformedSecretKey(algorithm))?;
+            let signature: p256::ecdsa::Signature = key.sign(message);
+            Ok(signature.to_bytes().to_vec())
+        }
+        other => unreachable!("{other} is not a classical scheme"),
+    }
+}
+
+/// Check `signature` over `message` against `public_key`, rejecting pairs
+/// whose algorithm tags differ before any scheme-specific parsing.
+pub fn verify(
+    message: &[u8],
+    signature: &Signature,
+    public_key: &PublicKey,
+) -> Result<(), VerifyError> {
+    let algorithm = public_key.algorithm;
+    if signature.algorithm != algorithm {
+        return Err(VerifyError::AlgorithmMismatch {
+            key: algorithm,
+            signature: signature.algorithm,
+        });
+    }
+    with_scheme!(algorithm, scheme => {
+        let pk = scheme::PublicKey::from_bytes(&public_key.bytes)
+            .map_err(|_| VerifyError::MalformedKey(algorithm))?;
+        let sig = scheme::DetachedSignature::from_bytes(&signature.bytes)
+            .map_err(|_| VerifyError::MalformedSignature(algorithm))?;
+        scheme::verify_detached_signature(&sig, message, &pk)
+            .map_err(|_| VerifyError::Invalid(algorithm))
+    }, classical => verify_classical(message, signature, public_key))
+}
+
+fn verify_classical(
+    message: &[u8],
+    signature: &Signature,
+    public_key: &PublicKey,
+) -> Result<(), VerifyError> {
+    use p256::ecdsa::signature::Verifier;
+
+    let algorithm = public_key.algorithm;
+    let invalid = |_| VerifyError::Invalid(algorithm);
+    match algorithm {
+        Algorithm::Ed25519 => {
+            let key_bytes = public_key
+                .bytes
+                .as_slice()
+                .try_into()
+                .map_err(|_| VerifyError::MalformedKey(algorithm))?;
+            let key = ed25519_dalek::VerifyingKey::from_bytes(key_bytes)
+                .map_err(|_| VerifyError::MalformedKey(algorithm))?;
+            let sig = ed25519_dalek::Signature::from_slice(&signature.bytes)
+                .map_err(|_| VerifyError::MalformedSignature(algorithm))?;
+            key.verify_strict(message, &sig).map_err(invalid)
+        }
+        Algorithm::EcdsaP256 => {
+            let key = p256::ecdsa::VerifyingKey::from_sec1_bytes(&public_key.bytes)
+                .map_err(|_| VerifyError::MalformedKey(algorithm))?;
+            let sig = p256::ecdsa::Signature::from_slice(&signature.bytes)
+                .map_err(|_| VerifyError::MalformedSignature(algorithm))?;
+            key.verify(message, &sig).map_err(invalid)
+        }
+        other => unreachable!("{other} is not a classical scheme"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MESSAGE: &[u8] = b"approve proposal";
+
+    #[test]
+    fn names_round_trip() {
+        for algorithm in Algorithm::ALL {
+            assert_eq!(algorithm.name().parse::<Algorithm>().unwrap(), algorithm);
+        }
+        assert!("ml-dsa-99".parse::<Algorithm>().is_err());
+    }
+
+    #[test]
+    fn each_family_signs_and_verifies() {
+        for algorithm in [
+            Algorithm::MlDsa44,
+            Algorithm::Falcon512,
+            Algorithm::SphincsSha2_128f,
+            Algorithm::Ed25519,
+            Algorithm::EcdsaP256,
+        ] {
+            let (public, secret) = keypair(algorithm);
+            let signature = sign(MESSAGE, &secret).unwrap();
+            assert_eq!(signature.algorithm(), algorithm);
+            assert_eq!(verify(MESSAGE, &signature, &public), Ok(()));
+            assert_eq!(
+                verify(b"another message", &signature, &public),
+                Err(VerifyError::Invalid(algorithm))
+            );
+        }
+    }
+
+    #[test]
+    fn signatures_only_verify_under_their_own_algorithm() {
+        let (ml_dsa, ml_dsa_secret) = keypair(Algorithm::MlDsa44);
+        let (falcon, _) = keypair(Algorithm::Falcon512);
+        let signature = sign(MESSAGE, &ml_dsa_secret).unwrap();
+        assert_eq!(
+            verify(MESSAGE, &signature, &falcon),
+            Err(VerifyError::AlgorithmMismatch {
+                key: Algorithm::Falcon512,
+                signature: Algorithm::MlDsa44,
+            })
+        );
+        let relabelled = Signature::new(Algorithm::MlDsa65, signature.as_bytes().to_vec());
+        assert!(verify(MESSAGE, &relabelled, &ml_dsa).is_err());
+    }
+
+    #[test]
+    fn single_keys_must_be_post_quantum() {
+        let (classical, _) = keypair(Algorithm::Ed25519);
+        let (post_quantum, _) = keypair(Algorithm::MlDsa44);
+        assert_eq!(
+            OwnerKey::single(classical),
+            Err(OwnerKeyError::ClassicalOnly(Algorithm::Ed25519))
+        );
+        assert!(OwnerKey::single(post_quantum).is_ok());
+    }
+
+    #[test]
+    fn composite_keys_pair_classical_with_post_quantum() {
+        let (ed25519, _) = keypair(Algorithm::Ed25519);
+        let (p256, _) = keypair(Algorithm::EcdsaP256);
+        let (ml_dsa, _) = keypair(Algorithm::MlDsa44);
+        assert_eq!(
+            OwnerKey::composite(ml_dsa.clone(), ml_dsa.clone()),
+            Err(OwnerKeyError::NotClassical(Algorithm::MlDsa44))
+        );
+        assert_eq!(
+            OwnerKey::composite(ed25519.clone(), p256),
+            Err(OwnerKeyError::NotPostQuantum(Algorithm::EcdsaP256))
+        );
+        assert!(OwnerKey::composite(ed25519, ml_dsa).is_ok());
+    }
+
+    #[test]
+    fn deserializing_checks_schemes() {
+        let (classical, _) = keypair(Algorithm::EcdsaP256);
+        let json = serde_json::to_string(&OwnerKey::Single(classical)).unwrap();
+        let err = serde_json::from_str::<OwnerKey>(&json).unwrap_err();
+        assert!(err.to_string().contains("not quantum-safe"), "{err}");
+    }
+
+    #[test]
+    fn composite_keys_need_both_signatures() {
+        let (classical, classical_secret) = keypair(Algorithm::Ed25519);
+        let (post_quantum, post_quantum_secret) = keypair(Algorithm::MlDsa44);
+        let key = OwnerKey::composite(classical, post_quantum).unwrap();
+        let classical_sig = sign(MESSAGE, &classical_secret).unwrap();
+        let post_quantum_sig = sign(MESSAGE, &post_quantum_secret).unwrap();
+
+        assert_eq!(
+            key.verify(MESSAGE, std::slice::from_ref(&classical_sig)),
+            Err(VerifyError::MissingComponent(Algorithm::MlDsa44))
+        );
+        assert_eq!(
+            key.verify(MESSAGE, std::slice::from_ref(&post_quantum_sig)),
+            Err(VerifyError::MissingComponent(Algorithm::Ed25519))
+        );
+        assert_eq!(
+            key.verify(
+                b"another message",
+                &[classical_sig.clone(), post_quantum_sig.clone()]
+            ),
+            Err(VerifyError::Invalid(Algorithm::Ed25519))
+        );
+        assert_eq!(
+            key.verify(MESSAGE, &[classical_sig, post_quantum_sig]),
+            Ok(())
+        );
+    }
+}
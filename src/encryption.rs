@@ -0,0 +1,255 @@
+//! Encryption of wallet files at rest.
+//!
+//! An encrypted wallet file wraps the ordinary versioned document (see
+//! [`crate::format`]) in its own versioned header, in the same encoding as
+//! the document inside:
+//!
+//! ```json
+//! { "format": "quantum_safe_multisig/encrypted-wallet", "version": 1,
+//!   "key": "passphrase", "kdf": { ... }, "nonce": "...", "ciphertext": "..." }
+//! ```
+//!
+//! The document is encrypted with XChaCha20-Poly1305 under either a key
+//! derived from a passphrase with Argon2id, whose parameters travel in the
+//! header, or a random data key wrapped by an AES key on a PKCS#11 token.
+
+use crate::error::WalletError;
+use crate::format::{self, Encoding};
+use crate::hsm::{KeySelector, TokenSelector};
+use crate::seal::{self, KdfParams, KEY_LEN};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use zeroize::Zeroizing;
+
+#[cfg(feature = "pkcs11")]
+use crate::hsm::{self, Ctx};
+#[cfg(feature = "pkcs11")]
+use crate::pin::Pin;
+
+/// Format name in the header of encrypted wallet files.
+pub const ENCRYPTED_FORMAT: &str = "quantum_safe_multisig/encrypted-wallet";
+
+/// Version of the encrypted container this build reads and writes.
+pub const ENCRYPTION_VERSION: u64 = 1;
+
+/// How a wallet file should be encrypted when it is saved.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Encryption {
+    /// Argon2id over a passphrase from [`Unlocker::passphrase`].
+    Passphrase,
+    /// A data key wrapped by the AES key `wrapping_key` on `token`.
+    Hsm {
+        token: TokenSelector,
+        wrapping_key: KeySelector,
+    },
+}
+
+/// Supplies the secrets needed to open or write an encrypted wallet, when
+/// and only if they are needed.
+pub trait Unlocker {
+    fn passphrase(&self) -> Result<Zeroizing<String>, WalletError>;
+
+    /// The passphrase to encrypt with. Interactive unlockers should have a
+    /// passphrase they have not been given before entered twice.
+    fn new_passphrase(&self) -> Result<Zeroizing<String>, WalletError> {
+        self.passphrase()
+    }
+
+    #[cfg(feature = "pkcs11")]
+    fn hsm(&self) -> Result<(&Ctx, Pin), WalletError>;
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct EncryptedFile {
+    format: String,
+    version: u64,
+    #[serde(flatten)]
+    key: KeyProtection,
+    #[serde(with = "crate::bytes")]
+    nonce: Vec<u8>,
+    #[serde(with = "crate::bytes")]
+    ciphertext: Vec<u8>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "key", rename_all = "snake_case")]
+enum KeyProtection {
+    Passphrase {
+        kdf: KdfParams,
+    },
+    Hsm {
+        token: TokenSelector,
+        wrapping_key: KeySelector,
+        #[serde(with = "crate::bytes")]
+        wrapped_key: Vec<u8>,
+    },
+}
+
+impl KeyProtection {
+    fn encryption(&self) -> Encryption {
+        match self {
+            KeyProtection::Passphrase { .. } => Encryption::Passphrase,
+            KeyProtection::Hsm {
+                token,
+                wrapping_key,
+                ..
+            } => Encryption::Hsm {
+                token: token.clone(),
+                wrapping_key: wrapping_key.clone(),
+            },
+        }
+    }
+}
+
+/// The header is authenticated through its format and version; the key
+/// parameters are authenticated implicitly, since changing them changes the
+/// key.
+fn aad() -> Vec<u8> {
+    let mut aad = ENCRYPTED_FORMAT.as_bytes().to_vec();
+    aad.extend_from_slice(&ENCRYPTION_VERSION.to_be_bytes());
+    aad
+}
+
+/// How `bytes` is encrypted, or `None` for a plaintext wallet file.
+pub fn detect(bytes: &[u8]) -> Result<Option<Encryption>, WalletError> {
+    let document = format::parse(bytes)?;
+    if document.get("format").and_then(Value::as_str) != Some(ENCRYPTED_FORMAT) {
+        return Ok(None);
+    }
+    Ok(Some(parse(document)?.key.encryption()))
+}
+
+fn parse(document: Value) -> Result<EncryptedFile, WalletError> {
+    let version = document.get("version").and_then(Value::as_u64);
+    match version {
+        Some(ENCRYPTION_VERSION) => Ok(serde_json::from_value(document)?),
+        Some(found) if found > ENCRYPTION_VERSION => Err(WalletError::UnsupportedVersion {
+            found,
+            supported: ENCRYPTION_VERSION,
+        }),
+        _ => Err(WalletError::UnsupportedFormat(format!(
+            "{} with version {:?}",
+            ENCRYPTED_FORMAT, version
+        ))),
+    }
+}
+
+/// Encrypt a serialized wallet document.
+pub fn encrypt(
+    plaintext: &[u8],
+    encryption: &Encryption,
+    encoding: Encoding,
+    unlocker: &dyn Unlocker,
+) -> Result<Vec<u8>, WalletError> {
+    let (key, protection) = match encryption {
+        Encryption::Passphrase => {
+            let kdf = KdfParams::generate();
+            let key = kdf.derive_key(unlocker.new_passphrase()?.as_bytes())?;
+            (key, KeyProtection::Passphrase { kdf })
+        }
+        Encryption::Hsm {
+            token,
+            wrapping_key,
+        } => {
+            let mut key = Zeroizing::new([0u8; KEY_LEN]);
+            key.copy_from_slice(&seal::random_bytes(KEY_LEN));
+            let wrapped_key = wrap_data_key(&key, token, wrapping_key, unlocker)?;
+            (
+                key,
+                KeyProtection::Hsm {
+                    token: token.clone(),
+                    wrapping_key: wrapping_key.clone(),
+                    wrapped_key,
+                },
+            )
+        }
+    };
+    let (nonce, ciphertext) = seal::encrypt(&key, plaintext, &aad())?;
+    format::to_vec(
+        &EncryptedFile {
+            format: ENCRYPTED_FORMAT.to_string(),
+            version: ENCRYPTION_VERSION,
+            key: protection,
+            nonce,
+            ciphertext,
+        },
+        encoding,
+    )
+}
+
+/// Decrypt `bytes` if it is an encrypted wallet file; plaintext files are
+/// returned unchanged.
+pub fn decrypt(bytes: &[u8], unlocker: &dyn Unlocker) -> Result<Zeroizing<Vec<u8>>, WalletError> {
+    let document = format::parse(bytes)?;
+    if document.get("format").and_then(Value::as_str) != Some(ENCRYPTED_FORMAT) {
+        return Ok(Zeroizing::new(bytes.to_vec()));
+    }
+    let file = parse(document)?;
+    let key = match &file.key {
+        KeyProtection::Passphrase { kdf } => kdf.derive_key(unlocker.passphrase()?.as_bytes())?,
+        KeyProtection::Hsm {
+            token,
+            wrapping_key,
+            wrapped_key,
+        } => unwrap_data_key(wrapped_key, token, wrapping_key, unlocker)?,
+    };
+    Ok(seal::decrypt(&key, &file.nonce, &file.ciphertext, &aad())?)
+}
+
+#[cfg(feature = "pkcs11")]
+fn wrap_data_key(
+    key: &[u8; KEY_LEN],
+    token: &TokenSelector,
+    wrapping_key: &KeySelector,
+    unlocker: &dyn Unlocker,
+) -> Result<Vec<u8>, WalletError> {
+    let (ctx, pin) = unlocker.hsm()?;
+    let slot = token.find_slot(ctx)?;
+    Ok(hsm::with_session(ctx, slot, pin.as_str(), |session| {
+        hsm::wrap_secret(ctx, session, wrapping_key, key)
+    })?)
+}
+
+#[cfg(feature = "pkcs11")]
+fn unwrap_data_key(
+    wrapped_key: &[u8],
+    token: &TokenSelector,
+    wrapping_key: &KeySelector,
+    unlocker: &dyn Unlocker,
+) -> Result<Zeroizing<[u8; KEY_LEN]>, WalletError> {
+    let (ctx, pin) = unlocker.hsm()?;
+    let slot = token.find_slot(ctx)?;
+    let key = hsm::with_session(ctx, slot, pin.as_str(), |session| {
+        hsm::unwrap_secret(ctx, session, wrapping_key, wrapped_key)
+    })?;
+    let mut data_key = Zeroizing::new([0u8; KEY_LEN]);
+    if key.len() != KEY_LEN {
+        return Err(seal::SealError::Decrypt.into());
+    }
+    data_key.copy_from_slice(&key);
+    Ok(data_key)
+}
+
+#[cfg(not(feature = "pkcs11"))]
+fn wrap_data_key(
+    _key: &[u8; KEY_LEN],
+    _token: &TokenSelector,
+    _wrapping_key: &KeySelector,
+    _unlocker: &dyn Unlocker,
+) -> Result<Vec<u8>, WalletError> {
+    Err(WalletError::Usage(
+        "HSM wallet encryption needs the pkcs11 feature".to_string(),
+    ))
+}
+
+#[cfg(not(feature = "pkcs11"))]
+fn unwrap_data_key(
+    _wrapped_key: &[u8],
+    _token: &TokenSelector,
+    _wrapping_key: &KeySelector,
+    _unlocker: &dyn Unlocker,
+) -> Result<Zeroizing<[u8; KEY_LEN]>, WalletError> {
+    Err(WalletError::Usage(
+        "HSM wallet encryption needs the pkcs11 feature".to_string(),
+    ))
+}
@@ -0,0 +1,129 @@
+//! The error type every fallible wallet operation returns.
+//!
+//! Lower layers keep their own focused error enums; `WalletError` wraps them
+//! so callers embedding the wallet have one type to match on.
+
+use crate::armor::ArmorError;
+use crate::crypto::{Algorithm, OwnerKeyError, UnknownAlgorithm, VerifyError};
+use crate::hash::Hash256;
+use crate::hsm::HsmError;
+use crate::pin::PinError;
+use crate::policy::PolicyError;
+use crate::proposal::ProposalId;
+use crate::rules::{Denial, RulesError};
+use crate::seal::SealError;
+use crate::signer::SignerError;
+use crate::transaction::TransactionError;
+use std::path::PathBuf;
+
+#[derive(Debug, thiserror::Error)]
+pub enum WalletError {
+    #[error("{0}")]
+    Usage(String),
+    #[error("{0:?} is not an owner of this wallet")]
+    UnknownOwner(String),
+    #[error("{0:?} is already an owner of this wallet")]
+    OwnerExists(String),
+    #[error("invalid owner name {0:?}: use letters, digits, '-', '_', '.' and '@'")]
+    InvalidOwnerName(String),
+    #[error("no proposal with ID {0}")]
+    UnknownProposal(ProposalId),
+    #[error("threshold {threshold} is invalid for a total owner weight of {total_weight}")]
+    InvalidThreshold {
+        threshold: usize,
+        total_weight: usize,
+    },
+    #[error("{owner}'s key is not accepted: {source}")]
+    InvalidOwnerKey {
+        owner: String,
+        #[source]
+        source: OwnerKeyError,
+    },
+    #[error("{owner}'s weight {reason}")]
+    InvalidWeight { owner: String, reason: &'static str },
+    #[error(transparent)]
+    Policy(#[from] PolicyError),
+    #[error("invalid transfer rules: {0}")]
+    Rules(#[from] RulesError),
+    #[error("{owner}'s key has no {algorithm} component")]
+    AlgorithmMismatch { owner: String, algorithm: Algorithm },
+    #[error("signer key {actual} does not match {owner}'s registered key {expected}")]
+    KeyMismatch {
+        owner: String,
+        expected: Hash256,
+        actual: Hash256,
+    },
+    #[error("signature from {owner} does not verify: {source}")]
+    BadSignature {
+        owner: String,
+        #[source]
+        source: VerifyError,
+    },
+    #[error("proposal {0} does not match its contents")]
+    TamperedProposal(ProposalId),
+    #[error("bundle {field} {found} does not match {expected}")]
+    BundleMismatch {
+        field: &'static str,
+        expected: String,
+        found: String,
+    },
+    #[error("proposal {id} is denied: {reason}")]
+    Denied {
+        id: ProposalId,
+        #[source]
+        reason: Denial,
+    },
+    #[error("invalid time window: {0}")]
+    InvalidTimeWindow(&'static str),
+    #[error("proposal {id} cannot execute before Unix time {at}")]
+    TooEarly { id: ProposalId, at: u64 },
+    #[error("proposal {id} expired at Unix time {at}")]
+    Expired { id: ProposalId, at: u64 },
+    #[error("the cancel window of proposal {id} closed at Unix time {at}")]
+    CancelWindowClosed { id: ProposalId, at: u64 },
+    #[error("proposal {0} does not have enough valid signatures")]
+    NotApproved(ProposalId),
+    #[error("proposal {id} has nonce {actual} but the wallet is at nonce {expected}")]
+    OutOfOrder {
+        id: ProposalId,
+        expected: u64,
+        actual: u64,
+    },
+    #[error("invalid transaction: {0}")]
+    Transaction(#[from] TransactionError),
+    #[error(transparent)]
+    Algorithm(#[from] UnknownAlgorithm),
+    #[error(transparent)]
+    Signer(#[from] SignerError),
+    #[error(transparent)]
+    Hsm(#[from] HsmError),
+    #[cfg(feature = "pkcs11")]
+    #[error("PKCS#11 error: {0}")]
+    Pkcs11(#[from] pkcs11::errors::Error),
+    #[error(transparent)]
+    Pin(#[from] PinError),
+    #[error("invalid wallet name {0:?}: use letters, digits, '-', '_' and '.'")]
+    InvalidWalletName(String),
+    #[error("no wallet at {}", .0.display())]
+    NoSuchWallet(PathBuf),
+    #[error("wallet {} is encrypted and no key source is configured", .0.display())]
+    Encrypted(PathBuf),
+    #[error("wallet {} is not encrypted, and encryption is required", .0.display())]
+    NotEncrypted(PathBuf),
+    #[error("cannot decrypt wallet: {0}")]
+    Seal(#[from] SealError),
+    #[error("wallet {} is locked by another process", .0.display())]
+    Locked(PathBuf),
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("serialization error: {0}")]
+    Serialization(#[from] serde_json::Error),
+    #[error("CBOR error: {0}")]
+    Cbor(String),
+    #[error(transparent)]
+    Armor(#[from] ArmorError),
+    #[error("unsupported wallet format: {0}")]
+    UnsupportedFormat(String),
+    #[error("wallet format version {found} is newer than the supported version {supported}")]
+    UnsupportedVersion { found: u64, supported: u64 },
+}
@@ -0,0 +1,629 @@
+//! Versioned on-disk wallet format.
+//!
+//! A wallet file is a document with a header naming the format and its
+//! schema version around the serialized wallet:
+//!
+//! ```json
+//! { "format": "quantum_safe_multisig/wallet", "version": 7, "wallet": { ... } }
+//! ```
+//!
+//! The document is stored as JSON or, more compactly, as CBOR, where keys and
+//! signatures are raw byte strings instead of base64 text. Both encodings
+//! share one schema and [`decode`] tells them apart by their first byte.
+//!
+//! Files from before the header existed count as version 0. Older files are
+//! upgraded on load by running them through [`MIGRATIONS`] one version at a
+//! time; files from a newer version of this crate are refused rather than
+//! guessed at.
+
+use crate::error::WalletError;
+use crate::proposal::Proposal;
+use crate::wallet::QuantumSafeWallet;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::fmt;
+use std::str::FromStr;
+
+/// Format name in the header.
+pub const FORMAT: &str = "quantum_safe_multisig/wallet";
+
+/// Schema version this build reads and writes.
+pub const CURRENT_VERSION: u64 = MIGRATIONS.len() as u64;
+
+/// Format name in the header of exported proposals.
+pub const PROPOSAL_FORMAT: &str = "quantum_safe_multisig/proposal";
+
+/// Schema version of exported proposals. Version 2 turned transactions
+/// into actions and version 3 added time windows.
+pub const PROPOSAL_VERSION: u64 = 3;
+
+/// A step upgrading a whole document from one version to the next.
+pub struct Migration {
+    pub description: &'static str,
+    apply: fn(Value) -> Result<Value, WalletError>,
+}
+
+/// `MIGRATIONS[n]` upgrades version `n` to version `n + 1`.
+pub const MIGRATIONS: [Migration; 7] = [
+    Migration {
+        description: "wrap the wallet in a versioned format header",
+        apply: add_header,
+    },
+    Migration {
+        description: "store key and signature bytes as base64",
+        apply: base64_key_bytes,
+    },
+    Migration {
+        description: "turn proposal transactions into transfer actions",
+        apply: transfer_actions,
+    },
+    Migration {
+        description: "give every owner a voting weight of 1",
+        apply: unit_weights,
+    },
+    Migration {
+        description: "allow an approval policy in place of the threshold",
+        apply: approval_policy,
+    },
+    Migration {
+        description: "allow transfer rules and a ledger of executed transfers",
+        apply: transfer_rules,
+    },
+    Migration {
+        description: "allow an execution delay and time windows on proposals",
+        apply: time_locks,
+    },
+];
+
+/// How a document is serialized.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    Json,
+    Cbor,
+}
+
+impl Encoding {
+    /// The encoding of a serialized document. Every document is a map,
+    /// which in CBOR starts with a byte in `0xa0..=0xbf` and in JSON with
+    /// `{` or whitespace.
+    pub fn detect(bytes: &[u8]) -> Self {
+        match bytes.first() {
+            Some(0xa0..=0xbf) => Encoding::Cbor,
+            _ => Encoding::Json,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Json => "json",
+            Encoding::Cbor => "cbor",
+        }
+    }
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Encoding {
+    type Err = WalletError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(Encoding::Json),
+            "cbor" => Ok(Encoding::Cbor),
+            _ => Err(WalletError::Usage(format!(
+                "unknown encoding {:?}: use json or cbor",
+                s
+            ))),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Envelope<'a> {
+    format: &'static str,
+    version: u64,
+    wallet: &'a QuantumSafeWallet,
+}
+
+#[derive(Serialize)]
+struct ProposalEnvelope<'a> {
+    format: &'static str,
+    version: u64,
+    proposal: &'a Proposal,
+}
+
+/// A wallet or a proposal, told apart by the format in its header.
+#[derive(Debug)]
+pub enum Document {
+    Wallet(Box<QuantumSafeWallet>),
+    Proposal(Box<Proposal>),
+}
+
+/// What loading a document involves.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MigrationPlan {
+    pub from: u64,
+    pub to: u64,
+    /// Description of each migration that would run, oldest first.
+    pub steps: Vec<&'static str>,
+}
+
+impl MigrationPlan {
+    pub fn is_current(&self) -> bool {
+        self.steps.is_empty()
+    }
+}
+
+pub fn encode(wallet: &QuantumSafeWallet, encoding: Encoding) -> Result<Vec<u8>, WalletError> {
+    to_vec(
+        &Envelope {
+            format: FORMAT,
+            version: CURRENT_VERSION,
+            wallet,
+        },
+        encoding,
+    )
+}
+
+/// Parse a wallet file of any supported version and encoding, migrating it
+/// to the current version.
+pub fn decode(bytes: &[u8]) -> Result<QuantumSafeWallet, WalletError> {
+    wallet_from(parse(bytes)?)
+}
+
+fn wallet_from(mut document: Value) -> Result<QuantumSafeWallet, WalletError> {
+    for migration in &MIGRATIONS[version(&document)? as usize..] {
+        document = (migration.apply)(document)?;
+    }
+    take_field(document, "wallet")
+}
+
+/// The migrations [`decode`] would run on `bytes`, without running them.
+pub fn plan(bytes: &[u8]) -> Result<MigrationPlan, WalletError> {
+    let from = version(&parse(bytes)?)?;
+    Ok(MigrationPlan {
+        from,
+        to: CURRENT_VERSION,
+        steps: MIGRATIONS[from as usize..]
+            .iter()
+            .map(|migration| migration.description)
+            .collect(),
+    })
+}
+
+/// Serialize a single proposal for handing to another party.
+pub fn encode_proposal(proposal: &Proposal, encoding: Encoding) -> Result<Vec<u8>, WalletError> {
+    to_vec(
+        &ProposalEnvelope {
+            format: PROPOSAL_FORMAT,
+            version: PROPOSAL_VERSION,
+            proposal,
+        },
+        encoding,
+    )
+}
+
+pub fn decode_proposal(bytes: &[u8]) -> Result<Proposal, WalletError> {
+    proposal_from(parse(bytes)?)
+}
+
+fn proposal_from(mut document: Value) -> Result<Proposal, WalletError> {
+    let version = (document.get("format").and_then(Value::as_str) == Some(PROPOSAL_FORMAT))
+        .then(|| document.get("version").and_then(Value::as_u64))
+        .flatten();
+    if version == Some(1) {
+        if let Some(proposal) = document.get_mut("proposal") {
+            transfer_action(proposal);
+        }
+    }
+    // Proposals from before time windows have none, which reads as
+    // open-ended.
+    if matches!(version, Some(1 | 2)) {
+        document["version"] = json!(PROPOSAL_VERSION);
+    }
+    tagged(document, PROPOSAL_FORMAT, PROPOSAL_VERSION, "proposal")
+}
+
+/// Decode a wallet file or an exported proposal, whichever `bytes` holds.
+pub fn decode_document(bytes: &[u8]) -> Result<Document, WalletError> {
+    let document = parse(bytes)?;
+    if document.get("format").and_then(Value::as_str) == Some(PROPOSAL_FORMAT) {
+        proposal_from(document).map(|proposal| Document::Proposal(Box::new(proposal)))
+    } else {
+        wallet_from(document).map(|wallet| Document::Wallet(Box::new(wallet)))
+    }
+}
+
+pub(crate) fn to_vec<T: Serialize>(value: &T, encoding: Encoding) -> Result<Vec<u8>, WalletError> {
+    match encoding {
+        Encoding::Json => Ok(serde_json::to_vec(value)?),
+        Encoding::Cbor => {
+            let mut out = Vec::new();
+            ciborium::into_writer(value, &mut out)
+                .map_err(|err| WalletError::Cbor(err.to_string()))?;
+            Ok(out)
+        }
+    }
+}
+
+/// Parse a JSON or CBOR document into the JSON data model that migrations
+/// work on. CBOR byte strings become the base64 strings JSON files hold for
+/// the same fields.
+pub(crate) fn parse(bytes: &[u8]) -> Result<Value, WalletError> {
+    match Encoding::detect(bytes) {
+        Encoding::Json => Ok(serde_json::from_slice(bytes)?),
+        Encoding::Cbor => {
+            let value: ciborium::Value =
+                ciborium::from_reader(bytes).map_err(|err| WalletError::Cbor(err.to_string()))?;
+            cbor_to_json(value)
+        }
+    }
+}
+
+fn cbor_to_json(value: ciborium::Value) -> Result<Value, WalletError> {
+    use ciborium::Value as Cbor;
+    Ok(match value {
+        Cbor::Null => Value::Null,
+        Cbor::Bool(b) => Value::Bool(b),
+        Cbor::Integer(i) => {
+            let i = i128::from(i);
+            u64::try_from(i)
+                .map(Value::from)
+                .or_else(|_| i64::try_from(i).map(Value::from))
+                .map_err(|_| WalletError::Cbor(format!("integer {} out of range", i)))?
+        }
+        Cbor::Float(f) => serde_json::Number::from_f64(f)
+            .map(Value::Number)
+            .ok_or_else(|| WalletError::Cbor(format!("non-finite number {}", f)))?,
+        Cbor::Text(text) => Value::String(text),
+        Cbor::Bytes(bytes) => Value::String(STANDARD.encode(bytes)),
+        Cbor::Tag(_, inner) => cbor_to_json(*inner)?,
+        Cbor::Array(items) => Value::Array(
+            items
+                .into_iter()
+                .map(cbor_to_json)
+                .collect::<Result<_, _>>()?,
+        ),
+        Cbor::Map(entries) => Value::Object(
+            entries
+                .into_iter()
+                .map(|(key, value)| match key {
+                    Cbor::Text(key) => Ok((key, cbor_to_json(value)?)),
+                    _ => Err(WalletError::Cbor("map key is not a string".to_string())),
+                })
+                .collect::<Result<_, _>>()?,
+        ),
+        _ => return Err(WalletError::Cbor("unsupported CBOR value".to_string())),
+    })
+}
+
+/// Deserialize the payload `field` of a document whose header must name
+/// `format` at a version no newer than `version`.
+pub(crate) fn tagged<T: serde::de::DeserializeOwned>(
+    document: Value,
+    format: &str,
+    version: u64,
+    field: &str,
+) -> Result<T, WalletError> {
+    let found = document.get("format").and_then(Value::as_str);
+    if found != Some(format) {
+        return Err(WalletError::UnsupportedFormat(format!(
+            "expected {}, found {:?}",
+            format,
+            found.unwrap_or("untagged document")
+        )));
+    }
+    match document.get("version").and_then(Value::as_u64) {
+        Some(found) if found == version => take_field(document, field),
+        Some(found) if found > version => Err(WalletError::UnsupportedVersion {
+            found,
+            supported: version,
+        }),
+        _ => Err(WalletError::UnsupportedFormat(
+            "missing or invalid version".to_string(),
+        )),
+    }
+}
+
+/// Deserialize the payload `field` of a header document.
+fn take_field<T: serde::de::DeserializeOwned>(
+    document: Value,
+    field: &str,
+) -> Result<T, WalletError> {
+    match document {
+        Value::Object(mut header) => Ok(serde_json::from_value(
+            header.remove(field).unwrap_or(Value::Null),
+        )?),
+        _ => Err(WalletError::UnsupportedFormat(
+            "document is not a map".to_string(),
+        )),
+    }
+}
+
+/// Schema version of `document`, refusing other formats and future versions.
+fn version(document: &Value) -> Result<u64, WalletError> {
+    let Some(format) = document.get("format") else {
+        return Ok(0);
+    };
+    if format != FORMAT {
+        return Err(WalletError::UnsupportedFormat(format.to_string()));
+    }
+    let version = document
+        .get("version")
+        .and_then(Value::as_u64)
+        .ok_or_else(|| WalletError::UnsupportedFormat("missing or invalid version".to_string()))?;
+    if version > CURRENT_VERSION {
+        return Err(WalletError::UnsupportedVersion {
+            found: version,
+            supported: CURRENT_VERSION,
+        });
+    }
+    Ok(version)
+}
+
+fn add_header(wallet: Value) -> Result<Value, WalletError> {
+    Ok(json!({ "format": FORMAT, "version": 1, "wallet": wallet }))
+}
+
+fn base64_key_bytes(mut document: Value) -> Result<Value, WalletError> {
+    if let Some(wallet) = document.get_mut("wallet") {
+        encode_key_bytes(wallet);
+    }
+    document["version"] = json!(2);
+    Ok(document)
+}
+
+fn transfer_actions(mut document: Value) -> Result<Value, WalletError> {
+    if let Some(Value::Object(proposals)) = document
+        .get_mut("wallet")
+        .and_then(|wallet| wallet.get_mut("proposals"))
+    {
+        proposals.values_mut().for_each(transfer_action);
+    }
+    document["version"] = json!(3);
+    Ok(document)
+}
+
+fn unit_weights(mut document: Value) -> Result<Value, WalletError> {
+    if let Some(Value::Object(wallet)) = document.get_mut("wallet") {
+        let weights: serde_json::Map<String, Value> = wallet
+            .get("owners")
+            .and_then(Value::as_object)
+            .map(|owners| {
+                owners
+                    .keys()
+                    .map(|owner| (owner.clone(), json!(1)))
+                    .collect()
+            })
+            .unwrap_or_default();
+        wallet.insert("weights".to_string(), Value::Object(weights));
+    }
+    document["version"] = json!(4);
+    Ok(document)
+}
+
+/// Wallets without a policy keep approving by threshold, so only the
+/// version changes; builds that predate policies then refuse the file
+/// instead of dropping a policy they cannot read.
+fn approval_policy(mut document: Value) -> Result<Value, WalletError> {
+    document["version"] = json!(5);
+    Ok(document)
+}
+
+/// Wallets without rules have an empty ledger; as with policies, only the
+/// version changes.
+fn transfer_rules(mut document: Value) -> Result<Value, WalletError> {
+    document["version"] = json!(6);
+    Ok(document)
+}
+
+/// Wallets without a delay execute proposals once approved, and proposals
+/// without a window are open-ended; only the version changes.
+fn time_locks(mut document: Value) -> Result<Value, WalletError> {
+    document["version"] = json!(7);
+    Ok(document)
+}
+
+/// Move a proposal's `transaction` into an `action` tagged as a transfer.
+fn transfer_action(proposal: &mut Value) {
+    let Some(proposal) = proposal.as_object_mut() else {
+        return;
+    };
+    if let Some(Value::Object(mut transaction)) = proposal.remove("transaction") {
+        transaction.insert("type".to_string(), json!("transfer"));
+        proposal.insert("action".to_string(), Value::Object(transaction));
+    }
+}
+
+/// Keys and signatures are the maps with an `algorithm` and `bytes` field.
+fn encode_key_bytes(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            if map.contains_key("algorithm") {
+                if let Some(Value::Array(items)) = map.get("bytes") {
+                    let bytes: Option<Vec<u8>> = items
+                        .iter()
+                        .map(|item| item.as_u64().and_then(|b| u8::try_from(b).ok()))
+                        .collect();
+                    if let Some(bytes) = bytes {
+                        map.insert("bytes".to_string(), Value::String(STANDARD.encode(bytes)));
+                    }
+                }
+            }
+            map.values_mut().for_each(encode_key_bytes);
+        }
+        Value::Array(items) => items.iter_mut().for_each(encode_key_bytes),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::crypto::{Algorithm, OwnerKey};
+    use crate::proposal::{Action, TimeWindow};
+    use crate::signer::{MemorySigner, Signer};
+    use crate::transaction::Transaction;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    /// A two-owner wallet with a transfer signed by one owner pending.
+    fn wallet() -> QuantumSafeWallet {
+        let signers: Vec<MemorySigner> = (0..2)
+            .map(|_| MemorySigner::generate(Algorithm::MlDsa44))
+            .collect();
+        let owners: HashMap<String, OwnerKey> = ["alice", "bob"]
+            .iter()
+            .zip(&signers)
+            .map(|(name, signer)| {
+                let key = OwnerKey::single(signer.public_key().unwrap()).unwrap();
+                (name.to_string(), key)
+            })
+            .collect();
+        let mut wallet = QuantumSafeWallet::new("testnet", owners, 2)
+            .unwrap()
+            .with_clock(Arc::new(ManualClock::new(1_700_000_000)));
+        let transfer = Action::Transfer(Transaction {
+            recipient: "qsc1recipient".to_string(),
+            amount: 5,
+            asset: "QSC".to_string(),
+            memo: String::new(),
+            expiry: None,
+            nonce: 0,
+        });
+        let id = wallet
+            .propose_within("alice", transfer, TimeWindow::default())
+            .unwrap();
+        wallet.sign_transaction(&id, "alice", &signers[0]).unwrap();
+        wallet
+    }
+
+    fn current(wallet: &QuantumSafeWallet) -> Value {
+        serde_json::from_slice(&encode(wallet, Encoding::Json).unwrap()).unwrap()
+    }
+
+    /// Undo [`encode_key_bytes`]: key and signature bytes as integer arrays.
+    fn byte_arrays(value: &mut Value) {
+        match value {
+            Value::Object(map) => {
+                if map.contains_key("algorithm") {
+                    if let Some(Value::String(bytes)) = map.get("bytes") {
+                        let bytes = STANDARD.decode(bytes).unwrap();
+                        map.insert("bytes".to_string(), json!(bytes));
+                    }
+                }
+                map.values_mut().for_each(byte_arrays);
+            }
+            Value::Array(items) => items.iter_mut().for_each(byte_arrays),
+            _ => {}
+        }
+    }
+
+    /// `wallet` as written before weights: proposals hold a `transaction`.
+    fn version_3(wallet: &QuantumSafeWallet) -> Value {
+        let mut document = current(wallet);
+        document["version"] = json!(3);
+        let wallet = document["wallet"].as_object_mut().unwrap();
+        wallet.remove("weights");
+        document
+    }
+
+    /// `wallet` as written before the header existed.
+    fn version_0(wallet: &QuantumSafeWallet) -> Value {
+        let mut wallet = version_3(wallet)["wallet"].take();
+        byte_arrays(&mut wallet);
+        for proposal in wallet["proposals"].as_object_mut().unwrap().values_mut() {
+            let proposal = proposal.as_object_mut().unwrap();
+            let mut transaction = proposal.remove("action").unwrap();
+            transaction.as_object_mut().unwrap().remove("type");
+            proposal.insert("transaction".to_string(), transaction);
+        }
+        wallet
+    }
+
+    fn decoded(document: &Value) -> Value {
+        let bytes = serde_json::to_vec(document).unwrap();
+        serde_json::to_value(decode(&bytes).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn cbor_and_json_hold_the_same_wallet() {
+        let wallet = wallet();
+        let json = encode(&wallet, Encoding::Json).unwrap();
+        let cbor = encode(&wallet, Encoding::Cbor).unwrap();
+        assert_eq!(Encoding::detect(&json), Encoding::Json);
+        assert_eq!(Encoding::detect(&cbor), Encoding::Cbor);
+        assert_eq!(Encoding::detect(b"\n  {}"), Encoding::Json);
+        assert!(cbor.len() < json.len());
+        assert_eq!(parse(&cbor).unwrap(), parse(&json).unwrap());
+        let expected = serde_json::to_value(&wallet).unwrap();
+        for bytes in [json, cbor] {
+            let decoded = decode(&bytes).unwrap();
+            assert_eq!(serde_json::to_value(&decoded).unwrap(), expected);
+        }
+        assert_eq!("cbor".parse::<Encoding>().unwrap(), Encoding::Cbor);
+        assert!(matches!(
+            "yaml".parse::<Encoding>(),
+            Err(WalletError::Usage(_))
+        ));
+    }
+
+    #[test]
+    fn headerless_files_migrate_to_the_current_version() {
+        let wallet = wallet();
+        let v0 = serde_json::to_vec(&version_0(&wallet)).unwrap();
+        let plan = plan(&v0).unwrap();
+        assert_eq!(plan.from, 0);
+        assert_eq!(plan.to, CURRENT_VERSION);
+        assert_eq!(plan.steps.len(), MIGRATIONS.len());
+        assert_eq!(
+            decoded(&version_0(&wallet)),
+            serde_json::to_value(&wallet).unwrap()
+        );
+    }
+
+    #[test]
+    fn intermediate_versions_run_the_remaining_migrations() {
+        let wallet = wallet();
+        let v3 = serde_json::to_vec(&version_3(&wallet)).unwrap();
+        assert_eq!(
+            plan(&v3).unwrap().steps,
+            MIGRATIONS[3..]
+                .iter()
+                .map(|migration| migration.description)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            decoded(&version_3(&wallet)),
+            serde_json::to_value(&wallet).unwrap()
+        );
+        let current = encode(&wallet, Encoding::Cbor).unwrap();
+        assert!(plan(&current).unwrap().is_current());
+    }
+
+    #[test]
+    fn newer_versions_are_refused() {
+        let mut document = current(&wallet());
+        document["version"] = json!(CURRENT_VERSION + 1);
+        let bytes = serde_json::to_vec(&document).unwrap();
+        for result in [plan(&bytes).map(|_| ()), decode(&bytes).map(|_| ())] {
+            assert!(matches!(
+                result,
+                Err(WalletError::UnsupportedVersion { found, supported })
+                    if found == CURRENT_VERSION + 1 && supported == CURRENT_VERSION
+            ));
+        }
+        document["format"] = json!("someone_else/wallet");
+        assert!(matches!(
+            decode(&serde_json::to_vec(&document).unwrap()),
+            Err(WalletError::UnsupportedFormat(_))
+        ));
+    }
+}
@@ -0,0 +1,140 @@
+//! Changes to who owns a wallet and what it takes to approve a proposal.
+//!
+//! An [`OwnerChange`] is proposed, signed and executed like a transfer, so
+//! changing the owner set takes the approval of the owners as they stand
+//! when it executes. Owners sign a canonical encoding that cannot be
+//! mistaken for a transaction, integers big-endian:
+//!
+//! | field      | encoding                                                  |
+//! |------------|-----------------------------------------------------------|
+//! | version    | `u8`, always [`ENCODING_VERSION`]                          |
+//! | kind       | `u8`: `1` add owner, `2` remove owner, `3` replace key, `4` set threshold, `5` set weight, `6` set policy, `7` set transfer rules, `8` set delay |
+//! | subject    | `u32` length, then the owner in UTF-8, the policy's canonical text, or the [rules encoding](crate::rules); empty for set threshold and set delay and for clearing the policy or rules |
+//! | `key`      | `u8` component count, then per component the algorithm name and key bytes, each with a `u32` length; no components unless adding or replacing |
+//! | value      | `u64`: the threshold, weight or delay in seconds being set, otherwise `0` |
+//! | `nonce`    | `u64`                                                      |
+
+use crate::crypto::OwnerKey;
+use crate::policy::Policy;
+use crate::rules::TransferRules;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Leading byte of owner change encodings. Transaction encodings start
+/// with their own version, which is never this value.
+pub const ENCODING_VERSION: u8 = 0x81;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum OwnerChange {
+    /// Register a new owner with weight 1. The threshold is unchanged.
+    AddOwner { owner: String, key: OwnerKey },
+    /// Remove an owner. Too few owners may not remain to meet the threshold.
+    RemoveOwner { owner: String },
+    /// Give an existing owner a new key, such as after losing a token.
+    ReplaceKey { owner: String, key: OwnerKey },
+    /// Require approvals with a combined weight of `required` from now on.
+    SetThreshold { required: usize },
+    /// Give an owner's approval `weight` votes.
+    SetWeight { owner: String, weight: usize },
+    /// Decide approvals by `policy`, or by the flat threshold if `None`.
+    SetPolicy { policy: Option<Policy> },
+    /// Enforce `rules` on proposals, or none if `None`.
+    SetRules { rules: Option<TransferRules> },
+    /// Hold approved proposals for `seconds` before they may execute, or
+    /// not at all if zero.
+    SetDelay { seconds: u64 },
+}
+
+impl OwnerChange {
+    /// Canonical encoding bound to `nonce`; see the module docs for the
+    /// layout.
+    pub fn encode(&self, nonce: u64) -> Vec<u8> {
+        let (kind, subject, key, value) = match self {
+            OwnerChange::AddOwner { owner, key } => (1, owner.clone().into_bytes(), Some(key), 0),
+            OwnerChange::RemoveOwner { owner } => (2, owner.clone().into_bytes(), None, 0),
+            OwnerChange::ReplaceKey { owner, key } => (3, owner.clone().into_bytes(), Some(key), 0),
+            OwnerChange::SetThreshold { required } => (4, Vec::new(), None, *required as u64),
+            OwnerChange::SetWeight { owner, weight } => {
+                (5, owner.clone().into_bytes(), None, *weight as u64)
+            }
+            OwnerChange::SetPolicy { policy } => (
+                6,
+                policy
+                    .as_ref()
+                    .map(|policy| policy.to_string().into_bytes())
+                    .unwrap_or_default(),
+                None,
+                0,
+            ),
+            OwnerChange::SetRules { rules } => (
+                7,
+                rules
+                    .as_ref()
+                    .map(TransferRules::encode)
+                    .unwrap_or_default(),
+                None,
+                0,
+            ),
+            OwnerChange::SetDelay { seconds } => (8, Vec::new(), None, *seconds),
+        };
+        let mut out = vec![ENCODING_VERSION, kind];
+        put_bytes(&mut out, &subject);
+        let components = key.map(OwnerKey::components).unwrap_or_default();
+        out.push(components.len() as u8);
+        for component in components {
+            put_bytes(&mut out, component.algorithm().name().as_bytes());
+            put_bytes(&mut out, component.as_bytes());
+        }
+        out.extend_from_slice(&value.to_be_bytes());
+        out.extend_from_slice(&nonce.to_be_bytes());
+        out
+    }
+}
+
+impl fmt::Display for OwnerChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OwnerChange::AddOwner { owner, key } => write!(
+                f,
+                "add owner {} with key {}",
+                owner,
+                key.post_quantum().fingerprint()
+            ),
+            OwnerChange::RemoveOwner { owner } => write!(f, "remove owner {}", owner),
+            OwnerChange::ReplaceKey { owner, key } => write!(
+                f,
+                "replace {}'s key with {}",
+                owner,
+                key.post_quantum().fingerprint()
+            ),
+            OwnerChange::SetThreshold { required } => {
+                write!(f, "set the threshold to {}", required)
+            }
+            OwnerChange::SetWeight { owner, weight } => {
+                write!(f, "set {}'s weight to {}", owner, weight)
+            }
+            OwnerChange::SetPolicy {
+                policy: Some(policy),
+            } => {
+                write!(f, "set the approval policy to {}", policy)
+            }
+            OwnerChange::SetPolicy { policy: None } => {
+                f.write_str("replace the approval policy with the threshold")
+            }
+            OwnerChange::SetRules { rules: Some(rules) } => {
+                write!(f, "set the transfer rules to {}", rules)
+            }
+            OwnerChange::SetRules { rules: None } => f.write_str("remove the transfer rules"),
+            OwnerChange::SetDelay { seconds: 0 } => f.write_str("remove the execution delay"),
+            OwnerChange::SetDelay { seconds } => {
+                write!(f, "set the execution delay to {} seconds", seconds)
+            }
+        }
+    }
+}
+
+fn put_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    out.extend_from_slice(&(bytes.len() as u32).to_be_bytes());
+    out.extend_from_slice(bytes);
+}
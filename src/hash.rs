@@ -0,0 +1,64 @@
+//! SHA3-256 digests used as content-derived identifiers.
+
+use serde::{Deserialize, Serialize};
+use sha3::{Digest, Sha3_256};
+use std::fmt;
+use std::str::FromStr;
+
+/// A 32-byte SHA3-256 digest, displayed and serialized as lowercase hex.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub struct Hash256([u8; 32]);
+
+impl Hash256 {
+    /// Hash `fields` under a domain tag. Every field is length-prefixed so that
+    /// different splits of the same bytes never produce the same digest.
+    pub fn of(domain: &str, fields: &[&[u8]]) -> Self {
+        let mut hasher = Sha3_256::new();
+        for field in std::iter::once(domain.as_bytes()).chain(fields.iter().copied()) {
+            hasher.update((field.len() as u64).to_be_bytes());
+            hasher.update(field);
+        }
+        Hash256(hasher.finalize().into())
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl fmt::Display for Hash256 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&hex::encode(self.0))
+    }
+}
+
+impl fmt::Debug for Hash256 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Hash256({})", self)
+    }
+}
+
+impl FromStr for Hash256 {
+    type Err = hex::FromHexError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut bytes = [0u8; 32];
+        hex::decode_to_slice(s, &mut bytes)?;
+        Ok(Hash256(bytes))
+    }
+}
+
+impl From<Hash256> for String {
+    fn from(hash: Hash256) -> String {
+        hash.to_string()
+    }
+}
+
+impl TryFrom<String> for Hash256 {
+    type Error = hex::FromHexError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
@@ -0,0 +1,222 @@
+//! PKCS#11 token, key and mechanism discovery, and on-token key generation.
+//!
+//! Owners are mapped to a key on a token through an [`HsmKeyRef`] stored in
+//! the wallet, which selects the token by label or serial number, the key by
+//! `CKA_LABEL` or `CKA_ID`, and the signing mechanism to pass to `C_SignInit`.
+//!
+//! Keys are generated on the token where it supports the scheme. Tokens
+//! without native post-quantum mechanisms can still protect a software key by
+//! wrapping it under an AES key that never leaves the token.
+//!
+//! The selectors and [`HsmKeyRef`] are plain data, so wallets that name HSM
+//! keys load without the `pkcs11` feature; talking to a token needs it.
+
+#[cfg(feature = "pkcs11")]
+mod token;
+
+#[cfg(feature = "pkcs11")]
+pub use self::token::{
+    generate_keypair, list_tokens, supports, unwrap_secret, with_session, wrap_secret, TokenInfo,
+};
+#[cfg(feature = "pkcs11")]
+pub use pkcs11::Ctx;
+
+use crate::crypto::Algorithm;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// PKCS#11 `CK_MECHANISM_TYPE`.
+pub type MechanismType = std::os::raw::c_ulong;
+
+// Mechanism numbers from the PKCS#11 specification, defined here so they can
+// be named without the `pkcs11` crate, which also predates the v3.0 ones.
+pub const CKM_EC_KEY_PAIR_GEN: MechanismType = 0x0000_1040;
+pub const CKM_ECDSA_SHA256: MechanismType = 0x0000_1044;
+pub const CKM_EC_EDWARDS_KEY_PAIR_GEN: MechanismType = 0x0000_1055;
+pub const CKM_EDDSA: MechanismType = 0x0000_1057;
+pub const CKM_VENDOR_DEFINED: MechanismType = 0x8000_0000;
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenSelector {
+    Label(String),
+    Serial(String),
+}
+
+impl fmt::Display for TokenSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenSelector::Label(label) => write!(f, "label:{}", label),
+            TokenSelector::Serial(serial) => write!(f, "serial:{}", serial),
+        }
+    }
+}
+
+/// Parses `label:<label>` or `serial:<serial>`; a bare value is a label.
+impl FromStr for TokenSelector {
+    type Err = HsmError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.split_once(':') {
+            Some(("label", label)) => TokenSelector::Label(label.to_string()),
+            Some(("serial", serial)) => TokenSelector::Serial(serial.to_string()),
+            _ => TokenSelector::Label(s.to_string()),
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeySelector {
+    Label(String),
+    Id(#[serde(with = "hex")] Vec<u8>),
+}
+
+impl fmt::Display for KeySelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeySelector::Label(label) => write!(f, "label:{}", label),
+            KeySelector::Id(id) => write!(f, "id:{}", hex::encode(id)),
+        }
+    }
+}
+
+/// Parses `label:<label>` or `id:<hex>`; a bare value is a label.
+impl FromStr for KeySelector {
+    type Err = HsmError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.split_once(':') {
+            Some(("label", label)) => KeySelector::Label(label.to_string()),
+            Some(("id", id)) => KeySelector::Id(
+                hex::decode(id).map_err(|_| HsmError::InvalidSelector(s.to_string()))?,
+            ),
+            _ => KeySelector::Label(s.to_string()),
+        })
+    }
+}
+
+/// A PKCS#11 mechanism type, including vendor-defined ones.
+///
+/// Written as a standard name (`CKM_EDDSA`, `CKM_ECDSA_SHA256`), a number
+/// (`0x80000101`), or an offset into the vendor range (`vendor:0x101`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub struct Mechanism(pub MechanismType);
+
+impl Mechanism {
+    /// Standard mechanism for `algorithm`, if PKCS#11 defines one this crate
+    /// can rely on. Post-quantum schemes are token-specific and must be
+    /// configured explicitly.
+    pub fn default_for(algorithm: Algorithm) -> Option<Mechanism> {
+        match algorithm {
+            Algorithm::Ed25519 => Some(Mechanism(CKM_EDDSA)),
+            Algorithm::EcdsaP256 => Some(Mechanism(CKM_ECDSA_SHA256)),
+            _ => None,
+        }
+    }
+
+    /// Standard key-pair generation mechanism for `algorithm`; as with
+    /// signing, post-quantum schemes need a vendor mechanism.
+    pub fn keygen_default_for(algorithm: Algorithm) -> Option<Mechanism> {
+        match algorithm {
+            Algorithm::Ed25519 => Some(Mechanism(CKM_EC_EDWARDS_KEY_PAIR_GEN)),
+            Algorithm::EcdsaP256 => Some(Mechanism(CKM_EC_KEY_PAIR_GEN)),
+            _ => None,
+        }
+    }
+
+    pub fn is_vendor_defined(self) -> bool {
+        self.0 & CKM_VENDOR_DEFINED != 0
+    }
+}
+
+const NAMED_MECHANISMS: [(&str, MechanismType); 4] = [
+    ("CKM_EDDSA", CKM_EDDSA),
+    ("CKM_ECDSA_SHA256", CKM_ECDSA_SHA256),
+    ("CKM_EC_EDWARDS_KEY_PAIR_GEN", CKM_EC_EDWARDS_KEY_PAIR_GEN),
+    ("CKM_EC_KEY_PAIR_GEN", CKM_EC_KEY_PAIR_GEN),
+];
+
+impl fmt::Display for Mechanism {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match NAMED_MECHANISMS.iter().find(|(_, value)| *value == self.0) {
+            Some((name, _)) => f.write_str(name),
+            None if self.is_vendor_defined() => {
+                write!(f, "vendor:{:#x}", self.0 & !CKM_VENDOR_DEFINED)
+            }
+            None => write!(f, "{:#x}", self.0),
+        }
+    }
+}
+
+impl FromStr for Mechanism {
+    type Err = HsmError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || HsmError::InvalidMechanism(s.to_string());
+        if let Some((_, value)) = NAMED_MECHANISMS.iter().find(|(name, _)| *name == s) {
+            return Ok(Mechanism(*value));
+        }
+        let (vendor, number) = match s.strip_prefix("vendor:") {
+            Some(offset) => (true, offset),
+            None => (false, s),
+        };
+        let value = match number.strip_prefix("0x") {
+            Some(hex) => MechanismType::from_str_radix(hex, 16),
+            None => number.parse(),
+        }
+        .map_err(|_| invalid())?;
+        if vendor {
+            if value & CKM_VENDOR_DEFINED != 0 {
+                return Err(invalid());
+            }
+            return Ok(Mechanism(CKM_VENDOR_DEFINED | value));
+        }
+        Ok(Mechanism(value))
+    }
+}
+
+impl From<Mechanism> for String {
+    fn from(mechanism: Mechanism) -> String {
+        mechanism.to_string()
+    }
+}
+
+impl TryFrom<String> for Mechanism {
+    type Error = HsmError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// Where an owner's signing key lives and how to drive it.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HsmKeyRef {
+    pub token: TokenSelector,
+    pub key: KeySelector,
+    pub mechanism: Mechanism,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum HsmError {
+    #[cfg(feature = "pkcs11")]
+    #[error("PKCS#11 error: {0}")]
+    Pkcs11(#[from] pkcs11::errors::Error),
+    #[error("no token matches {0}")]
+    TokenNotFound(TokenSelector),
+    #[error("more than one token matches {0}")]
+    AmbiguousToken(TokenSelector),
+    #[error("no key matches {0}")]
+    KeyNotFound(KeySelector),
+    #[error("more than one key matches {0}")]
+    AmbiguousKey(KeySelector),
+    #[error("a key matching {0} already exists")]
+    KeyExists(KeySelector),
+    #[error("invalid token or key selector {0:?}")]
+    InvalidSelector(String),
+    #[error("invalid PKCS#11 mechanism {0:?}")]
+    InvalidMechanism(String),
+}
@@ -0,0 +1,210 @@
+//! Operations on a live token through a loaded PKCS#11 module.
+
+use super::{HsmError, KeySelector, Mechanism, TokenSelector};
+use crate::crypto::Algorithm;
+use pkcs11::types::{
+    CKA_CLASS, CKA_EC_PARAMS, CKA_EXTRACTABLE, CKA_ID, CKA_LABEL, CKA_PRIVATE, CKA_SENSITIVE,
+    CKA_SIGN, CKA_TOKEN, CKA_VERIFY, CKF_RW_SESSION, CKF_SERIAL_SESSION, CKM_AES_KEY_WRAP_PAD,
+    CKO_PRIVATE_KEY, CKO_SECRET_KEY, CKU_USER, CK_ATTRIBUTE, CK_FALSE, CK_MECHANISM,
+    CK_OBJECT_CLASS, CK_OBJECT_HANDLE, CK_SESSION_HANDLE, CK_SLOT_ID, CK_TRUE,
+};
+use pkcs11::Ctx;
+use serde::Serialize;
+use std::ptr;
+use zeroize::Zeroizing;
+
+/// DER-encoded curve OIDs for `CKA_EC_PARAMS`.
+const ED25519_OID: [u8; 5] = [0x06, 0x03, 0x2b, 0x65, 0x70];
+const P256_OID: [u8; 10] = [0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07];
+
+/// Length of the random `CKA_ID` given to generated key pairs.
+const KEY_ID_LEN: usize = 16;
+
+/// Description of a token present in a slot.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct TokenInfo {
+    pub slot: CK_SLOT_ID,
+    pub label: String,
+    pub serial: String,
+    pub manufacturer: String,
+    pub model: String,
+}
+
+/// Enumerate all slots that currently hold a token.
+pub fn list_tokens(ctx: &Ctx) -> Result<Vec<TokenInfo>, pkcs11::errors::Error> {
+    ctx.get_slot_list(true)?
+        .into_iter()
+        .map(|slot| {
+            let info = ctx.get_token_info(slot)?;
+            Ok(TokenInfo {
+                slot,
+                label: String::from(info.label),
+                serial: String::from(info.serialNumber),
+                manufacturer: String::from(info.manufacturerID),
+                model: String::from(info.model),
+            })
+        })
+        .collect()
+}
+
+/// Run `f` inside a logged-in read/write session on `slot`, logging out and
+/// closing the session again whatever happens.
+pub fn with_session<T, E>(
+    ctx: &Ctx,
+    slot: CK_SLOT_ID,
+    pin: &str,
+    f: impl FnOnce(CK_SESSION_HANDLE) -> Result<T, E>,
+) -> Result<T, E>
+where
+    E: From<pkcs11::errors::Error>,
+{
+    let session = ctx.open_session(slot, CKF_SERIAL_SESSION | CKF_RW_SESSION, None, None)?;
+    let result = ctx
+        .login(session, CKU_USER, Some(pin))
+        .map_err(E::from)
+        .and_then(|()| {
+            let result = f(session);
+            ctx.logout(session)?;
+            result
+        });
+    ctx.close_session(session)?;
+    result
+}
+
+/// Generate a key pair for `algorithm` on the token with `mechanism`,
+/// labelled `label` and tagged with a fresh random `CKA_ID`.
+///
+/// The private key is a sensitive, non-extractable token object that can
+/// only sign; the caller reads the public half back to register the owner.
+pub fn generate_keypair(
+    ctx: &Ctx,
+    session: CK_SESSION_HANDLE,
+    algorithm: Algorithm,
+    mechanism: Mechanism,
+    label: &str,
+) -> Result<KeySelector, HsmError> {
+    let key = KeySelector::Label(label.to_string());
+    match key.find(ctx, session, CKO_PRIVATE_KEY) {
+        Err(HsmError::KeyNotFound(_)) => {}
+        Ok(_) | Err(HsmError::AmbiguousKey(_)) => return Err(HsmError::KeyExists(key)),
+        Err(err) => return Err(err),
+    }
+    let id = crate::seal::random_bytes(KEY_ID_LEN);
+    let (yes, no) = (CK_TRUE, CK_FALSE);
+    let mut public_template = vec![
+        CK_ATTRIBUTE::new(CKA_TOKEN).with_bool(&yes),
+        CK_ATTRIBUTE::new(CKA_VERIFY).with_bool(&yes),
+        CK_ATTRIBUTE::new(CKA_LABEL).with_string(label),
+        CK_ATTRIBUTE::new(CKA_ID).with_bytes(&id),
+    ];
+    let ec_params: Option<&[u8]> = match algorithm {
+        Algorithm::Ed25519 => Some(&ED25519_OID),
+        Algorithm::EcdsaP256 => Some(&P256_OID),
+        _ => None,
+    };
+    if let Some(params) = ec_params {
+        public_template.push(CK_ATTRIBUTE::new(CKA_EC_PARAMS).with_bytes(params));
+    }
+    let private_template = [
+        CK_ATTRIBUTE::new(CKA_TOKEN).with_bool(&yes),
+        CK_ATTRIBUTE::new(CKA_PRIVATE).with_bool(&yes),
+        CK_ATTRIBUTE::new(CKA_SENSITIVE).with_bool(&yes),
+        CK_ATTRIBUTE::new(CKA_EXTRACTABLE).with_bool(&no),
+        CK_ATTRIBUTE::new(CKA_SIGN).with_bool(&yes),
+        CK_ATTRIBUTE::new(CKA_LABEL).with_string(label),
+        CK_ATTRIBUTE::new(CKA_ID).with_bytes(&id),
+    ];
+    ctx.generate_key_pair(
+        session,
+        &mechanism.to_ck(),
+        &public_template,
+        &private_template,
+    )?;
+    Ok(key)
+}
+
+/// Whether the token in `slot` advertises `mechanism`.
+pub fn supports(ctx: &Ctx, slot: CK_SLOT_ID, mechanism: Mechanism) -> Result<bool, HsmError> {
+    Ok(ctx.get_mechanism_list(slot)?.contains(&mechanism.0))
+}
+
+/// Encrypt `secret` under the AES key `wrapping_key` with RFC 5649 key wrap.
+pub fn wrap_secret(
+    ctx: &Ctx,
+    session: CK_SESSION_HANDLE,
+    wrapping_key: &KeySelector,
+    secret: &[u8],
+) -> Result<Vec<u8>, HsmError> {
+    let key = wrapping_key.find(ctx, session, CKO_SECRET_KEY)?;
+    ctx.encrypt_init(session, &Mechanism(CKM_AES_KEY_WRAP_PAD).to_ck(), key)?;
+    Ok(ctx.encrypt(session, secret)?)
+}
+
+/// Inverse of [`wrap_secret`]. Key wrap is authenticated, so a wrong key or
+/// tampered ciphertext fails here rather than yielding garbage.
+pub fn unwrap_secret(
+    ctx: &Ctx,
+    session: CK_SESSION_HANDLE,
+    wrapping_key: &KeySelector,
+    wrapped: &[u8],
+) -> Result<Zeroizing<Vec<u8>>, HsmError> {
+    let key = wrapping_key.find(ctx, session, CKO_SECRET_KEY)?;
+    ctx.decrypt_init(session, &Mechanism(CKM_AES_KEY_WRAP_PAD).to_ck(), key)?;
+    Ok(Zeroizing::new(ctx.decrypt(session, wrapped)?))
+}
+
+impl TokenSelector {
+    pub fn matches(&self, token: &TokenInfo) -> bool {
+        match self {
+            TokenSelector::Label(label) => token.label == *label,
+            TokenSelector::Serial(serial) => token.serial == *serial,
+        }
+    }
+
+    /// Slot of the single token this selector matches.
+    pub fn find_slot(&self, ctx: &Ctx) -> Result<CK_SLOT_ID, HsmError> {
+        let mut matching = list_tokens(ctx)?
+            .into_iter()
+            .filter(|token| self.matches(token));
+        match (matching.next(), matching.next()) {
+            (Some(token), None) => Ok(token.slot),
+            (None, _) => Err(HsmError::TokenNotFound(self.clone())),
+            (Some(_), Some(_)) => Err(HsmError::AmbiguousToken(self.clone())),
+        }
+    }
+}
+
+impl KeySelector {
+    /// Find the single object of `class` this selector matches.
+    pub fn find(
+        &self,
+        ctx: &Ctx,
+        session: CK_SESSION_HANDLE,
+        class: CK_OBJECT_CLASS,
+    ) -> Result<CK_OBJECT_HANDLE, HsmError> {
+        let selector = match self {
+            KeySelector::Label(label) => CK_ATTRIBUTE::new(CKA_LABEL).with_string(label),
+            KeySelector::Id(id) => CK_ATTRIBUTE::new(CKA_ID).with_bytes(id),
+        };
+        let template = [CK_ATTRIBUTE::new(CKA_CLASS).with_ck_ulong(&class), selector];
+        ctx.find_objects_init(session, &template)?;
+        let found = ctx.find_objects(session, 2);
+        ctx.find_objects_final(session)?;
+        match found?.as_slice() {
+            [object] => Ok(*object),
+            [] => Err(HsmError::KeyNotFound(self.clone())),
+            _ => Err(HsmError::AmbiguousKey(self.clone())),
+        }
+    }
+}
+
+impl Mechanism {
+    /// Parameterless `CK_MECHANISM` for `C_SignInit` and friends.
+    pub fn to_ck(self) -> CK_MECHANISM {
+        CK_MECHANISM {
+            mechanism: self.0,
+            pParameter: ptr::null_mut(),
+            ulParameterLen: 0,
+        }
+    }
+}
@@ -0,0 +1,36 @@
+//! Quantum-Safe Multi-Sig Wallet with HSM Support
+//! Uses SPHINCS+, ML-DSA or Falcon for quantum-safe signatures and PKCS#11 HSM for key storage.
+//!
+//! The PKCS#11 backend sits behind the default `pkcs11` feature. Without it
+//! the crate still creates, verifies and stores wallets, including the
+//! token and key references of HSM-backed owners, but cannot talk to a token.
+
+pub mod armor;
+pub mod bundle;
+mod bytes;
+pub mod clock;
+pub mod crypto;
+pub mod encryption;
+pub mod error;
+pub mod format;
+pub mod governance;
+pub mod hash;
+pub mod hsm;
+pub mod payload;
+pub mod pin;
+pub mod policy;
+pub mod proposal;
+pub mod rules;
+pub mod seal;
+pub mod signer;
+pub mod storage;
+pub mod transaction;
+pub mod wallet;
+
+pub use crate::crypto::{Algorithm, OwnerKey, PublicKey, Signature};
+pub use crate::error::WalletError;
+pub use crate::governance::OwnerChange;
+pub use crate::proposal::{Action, Proposal, ProposalId, TimeWindow};
+pub use crate::signer::Signer;
+pub use crate::transaction::Transaction;
+pub use crate::wallet::QuantumSafeWallet;
@@ -1,113 +1,2366 @@
-//! Quantum-Safe Multi-Sig Wallet with HSM Support
-//! Uses SPHINCS+ for quantum-safe signatures and PKCS#11 HSM for key storage.
+//! Command-line front end for the quantum-safe multi-sig wallet.
 
-use pqcrypto_sign::sphincs::{self, PublicKey, Signature};
-use pkcs11::{Ctx, types::{CK_ATTRIBUTE_TYPE, CKF_RW_SESSION, CKF_SERIAL_SESSION}};
+use clap::builder::FalseyValueParser;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use quantum_safe_multisig::armor::{
+    self, PARTIAL_SIGNATURES_LABEL, PROPOSAL_LABEL, SIGNING_REQUEST_LABEL, WALLET_LABEL,
+};
+use quantum_safe_multisig::bundle::{PartialSignatures, SigningRequest};
+use quantum_safe_multisig::crypto::{Algorithm, OwnerKey, OwnerKeyError, PublicKey};
+use quantum_safe_multisig::encryption::{Encryption, Unlocker};
+use quantum_safe_multisig::error::WalletError;
+use quantum_safe_multisig::format::{self, Document, Encoding};
+use quantum_safe_multisig::governance::OwnerChange;
+use quantum_safe_multisig::hsm::{self, Ctx, HsmKeyRef, KeySelector, Mechanism, TokenSelector};
+use quantum_safe_multisig::pin::{Pin, PinSource};
+use quantum_safe_multisig::policy::{self, Approval, Policy};
+use quantum_safe_multisig::proposal::{Action, ProposalId, TimeWindow};
+use quantum_safe_multisig::rules::TransferRules;
+use quantum_safe_multisig::signer::{Keystore, Pkcs11Signer, Protection, Signer, Unlock};
+use quantum_safe_multisig::storage::{
+    WalletDir, WalletStore, DEFAULT_WALLET, REQUIRE_ENCRYPTION_ENV, WALLET_DIR_ENV,
+};
+use quantum_safe_multisig::transaction::Transaction;
+use quantum_safe_multisig::wallet::QuantumSafeWallet;
+use serde_json::{json, Value};
+use std::cell::OnceCell;
 use std::collections::HashMap;
-use std::fs::{File, OpenOptions};
+use std::fs;
 use std::io::{Read, Write};
-use serde::{Serialize, Deserialize};
-use clap::{Arg, Command};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::rc::Rc;
+use zeroize::Zeroizing;
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct QuantumSafeWallet {
-    owners: HashMap<String, PublicKey>,
-    threshold: usize,
-    signatures: HashMap<String, Signature>,
+const BIN: &str = "quantum_safe_multisig";
+
+/// A quantum-safe multi-signature wallet with HSM support.
+#[derive(Parser)]
+#[command(name = BIN, version, author = "Orly")]
+#[command(after_help = "Examples:
+  quantum_safe_multisig keygen alice
+  quantum_safe_multisig keygen bob
+  quantum_safe_multisig init --owner alice --owner bob --threshold 2
+  quantum_safe_multisig propose qsc1recipient --amount 100 --creator alice
+  quantum_safe_multisig sign alice
+  quantum_safe_multisig sign bob
+  quantum_safe_multisig execute")]
+struct Cli {
+    #[command(flatten)]
+    global: GlobalArgs,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Args)]
+struct GlobalArgs {
+    /// Name of the wallet to use
+    #[arg(long, global = true, default_value = DEFAULT_WALLET)]
+    wallet: String,
+    /// Directory holding the wallets (default: the user data directory)
+    #[arg(long, global = true, env = WALLET_DIR_ENV)]
+    wallet_dir: Option<PathBuf>,
+    /// Refuse to read or write a wallet file that is not encrypted
+    #[arg(
+        long,
+        global = true,
+        env = REQUIRE_ENCRYPTION_ENV,
+        value_parser = FalseyValueParser::new()
+    )]
+    require_encryption: bool,
+    /// Directory of software owner keys
+    #[arg(long, global = true, default_value = "keystore")]
+    keystore: PathBuf,
+    /// PKCS#11 module to load for token operations
+    #[arg(long, global = true, default_value = "/usr/lib/softhsm/libsofthsm2.so")]
+    hsm_module: String,
+    /// Where to read the token PIN: prompt, env:<VAR>, fd:<n>, file:<path>,
+    /// cmd:<command> or pinentry[:<program>]
+    #[arg(long, global = true, default_value = "prompt")]
+    pin_source: PinSource,
+    /// Accept PINs from the environment or from files other users can read
+    #[arg(long, global = true)]
+    allow_insecure_pin: bool,
+    /// Print results as text or as JSON
+    #[arg(long, global = true, value_enum, default_value_t = Output::Text)]
+    output: Output,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Create a wallet owned by keystore keys
+    #[command(after_help = "Examples:
+  quantum_safe_multisig init --threshold 2
+  quantum_safe_multisig --wallet treasury init --owner alice --owner bob --owner carol --threshold 2
+  quantum_safe_multisig init --owner alice --threshold 1 --encrypt passphrase
+  quantum_safe_multisig init --owner cfo --owner eng1 --owner eng2 --policy 'all(1 of [cfo], 1 of [eng1, eng2])'")]
+    Init(InitArgs),
+    /// List owners, or propose adding, removing, rekeying or reweighing one
+    #[command(subcommand)]
+    Owner(OwnerCommand),
+    /// Propose changing how many owners must approve
+    #[command(subcommand)]
+    Threshold(ThresholdCommand),
+    /// Show, check or propose changing the grouped approval policy
+    #[command(subcommand)]
+    Policy(PolicyCommand),
+    /// Show or propose changing amount bands and spending limits
+    #[command(subcommand)]
+    Rules(RulesCommand),
+    /// Propose changing how long approved proposals wait before executing
+    #[command(subcommand)]
+    Delay(DelayCommand),
+    /// Propose a transfer for the owners to sign
+    #[command(after_help = "Examples:
+  quantum_safe_multisig propose qsc1recipient --amount 100 --creator alice
+  quantum_safe_multisig propose qsc1recipient --amount 5 --asset USDQ --memo rent --creator bob
+  quantum_safe_multisig propose qsc1recipient --amount 100 --not-before +1h --expires-at +7d --creator alice")]
+    Propose(ProposeArgs),
+    /// Sign a pending proposal as an owner
+    #[command(after_help = "Examples:
+  quantum_safe_multisig sign alice
+  quantum_safe_multisig sign bob --proposal 3f2a...")]
+    Sign(SignArgs),
+    /// Withdraw a pending proposal as an owner
+    #[command(after_help = "Examples:
+  quantum_safe_multisig cancel alice
+  quantum_safe_multisig cancel bob --proposal 3f2a...")]
+    Cancel {
+        /// Owner cancelling the proposal
+        owner: String,
+        #[command(flatten)]
+        proposal: ProposalArg,
+    },
+    /// Show the wallet's owners, threshold and pending proposals
+    #[command(after_help = "Examples:
+  quantum_safe_multisig status
+  quantum_safe_multisig --wallet treasury status")]
+    Status,
+    /// Check whether a proposal has enough valid signatures
+    #[command(after_help = "Examples:
+  quantum_safe_multisig verify
+  quantum_safe_multisig verify --proposal 3f2a...")]
+    Verify(ProposalArg),
+    /// Execute an approved proposal
+    #[command(after_help = "Examples:
+  quantum_safe_multisig execute
+  quantum_safe_multisig execute --proposal 3f2a...")]
+    Execute(ProposalArg),
+    /// Write the wallet or one proposal out for another owner
+    #[command(after_help = "Examples:
+  quantum_safe_multisig export --armor > wallet.asc
+  quantum_safe_multisig export --proposal 3f2a... --armor > proposal.asc
+  quantum_safe_multisig export --encoding json --out wallet.json")]
+    Export(ExportArgs),
+    /// Read in a wallet, or a proposal and its signatures, exported elsewhere
+    #[command(after_help = "Examples:
+  quantum_safe_multisig --wallet treasury import wallet.asc
+  quantum_safe_multisig import proposal.asc
+  quantum_safe_multisig import - < proposal.asc")]
+    Import(ImportArgs),
+    /// Sign proposals on a machine that never sees the wallet file
+    #[command(subcommand)]
+    Offline(OfflineCommand),
+    /// Generate an owner key in the keystore or on a token
+    #[command(after_help = "Examples:
+  quantum_safe_multisig keygen alice
+  quantum_safe_multisig keygen bob --algorithm sphincs+-shake-128s --export bob.pub")]
+    Keygen(KeygenArgs),
+    /// Change how the wallet file is encrypted or encoded
+    #[command(after_help = "Examples:
+  quantum_safe_multisig storage --encrypt passphrase
+  quantum_safe_multisig storage --encrypt hsm --hsm-token label:ops
+  quantum_safe_multisig storage --decrypt --encoding json")]
+    Storage(StorageArgs),
+    /// Upgrade the wallet file to the current format version
+    #[command(after_help = "Examples:
+  quantum_safe_multisig migrate --dry-run
+  quantum_safe_multisig --wallet treasury migrate")]
+    Migrate {
+        /// Report what would change without writing the wallet
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// List the wallets in the wallet directory
+    #[command(after_help = "Examples:
+  quantum_safe_multisig wallets
+  quantum_safe_multisig --wallet-dir /srv/wallets wallets")]
+    Wallets,
+    /// List PKCS#11 tokens available through --hsm-module
+    #[command(after_help = "Examples:
+  quantum_safe_multisig tokens
+  quantum_safe_multisig --hsm-module /usr/lib/opensc-pkcs11.so tokens")]
+    Tokens,
+}
+
+#[derive(Subcommand)]
+enum OwnerCommand {
+    /// List the owners and their keys
+    #[command(after_help = "Examples:
+  quantum_safe_multisig owner list")]
+    List,
+    /// Propose adding an owner with a key from the keystore, a public key
+    /// file or a token
+    #[command(after_help = "Examples:
+  quantum_safe_multisig owner add dave --creator alice
+  quantum_safe_multisig owner add erin --public-key erin.pub --creator alice")]
+    Add(OwnerKeyArgs),
+    /// Propose removing an owner; their pending signatures go with them
+    #[command(after_help = "Examples:
+  quantum_safe_multisig owner remove dave --creator alice")]
+    Remove {
+        /// Owner to remove
+        name: String,
+        /// Owner making the proposal
+        #[arg(long)]
+        creator: String,
+    },
+    /// Propose giving an owner a new key; their pending signatures are dropped
+    #[command(after_help = "Examples:
+  quantum_safe_multisig owner replace-key bob --public-key bob-new.pub --creator alice")]
+    ReplaceKey(OwnerKeyArgs),
+    /// Propose changing how many votes an owner's approval counts for
+    #[command(after_help = "Examples:
+  quantum_safe_multisig owner weight cfo 3 --creator alice")]
+    Weight {
+        /// Owner to reweigh
+        name: String,
+        /// Votes the owner's approval counts for
+        weight: usize,
+        /// Owner making the proposal
+        #[arg(long)]
+        creator: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum OfflineCommand {
+    /// Write a signing request for a pending proposal
+    #[command(after_help = "Examples:
+  quantum_safe_multisig offline request --armor > request.asc
+  quantum_safe_multisig offline request --proposal 3f2a... --out request.cbor")]
+    Request(RequestArgs),
+    /// Sign a request without the wallet file, writing partial signatures
+    #[command(after_help = "Examples:
+  quantum_safe_multisig offline sign alice request.asc --armor > alice.asc
+  quantum_safe_multisig offline sign carol request.cbor --hsm --hsm-token label:ops --out carol.cbor")]
+    Sign(OfflineSignArgs),
+    /// Add partial signatures to their proposal
+    #[command(after_help = "Examples:
+  quantum_safe_multisig offline combine alice.asc carol.cbor")]
+    Combine {
+        /// Partial-signature files, or - for standard input
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+enum ThresholdCommand {
+    /// Propose requiring approvals with this combined weight
+    #[command(after_help = "Examples:
+  quantum_safe_multisig threshold set 3 --creator alice")]
+    Set {
+        /// Combined owner weight needed to approve a proposal
+        required: usize,
+        /// Owner making the proposal
+        #[arg(long)]
+        creator: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum PolicyCommand {
+    /// Show the approval policy, or the threshold if there is none
+    #[command(after_help = "Examples:
+  quantum_safe_multisig policy show")]
+    Show,
+    /// Check a policy against the current owners without proposing it
+    #[command(after_help = "Examples:
+  quantum_safe_multisig policy check 'all(1 of [cfo], 2 of *)'")]
+    Check {
+        /// Policy text
+        policy: Policy,
+    },
+    /// Propose deciding approvals by a policy
+    #[command(after_help = "Examples:
+  quantum_safe_multisig policy set 'all(1 of [cfo], 2 of [eng1, eng2, eng3])' --creator alice
+  quantum_safe_multisig policy set 'any(3 of *, 2 of [recovery1, recovery2])' --creator alice")]
+    Set {
+        /// Policy text
+        policy: Policy,
+        /// Owner making the proposal
+        #[arg(long)]
+        creator: String,
+    },
+    /// Propose going back to the flat threshold
+    #[command(after_help = "Examples:
+  quantum_safe_multisig policy clear --creator alice")]
+    Clear {
+        /// Owner making the proposal
+        #[arg(long)]
+        creator: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum DelayCommand {
+    /// Propose holding approved proposals this long before they may
+    /// execute; 0 removes the delay
+    #[command(after_help = "Examples:
+  quantum_safe_multisig delay set 24h --creator alice
+  quantum_safe_multisig delay set 0 --creator alice")]
+    Set {
+        /// Delay, in seconds or with an s, m, h or d suffix
+        delay: Seconds,
+        /// Owner making the proposal
+        #[arg(long)]
+        creator: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum RulesCommand {
+    /// Show the transfer rules and how much of each spend cap is used
+    #[command(after_help = "Examples:
+  quantum_safe_multisig rules show")]
+    Show,
+    /// Propose enforcing the transfer rules in a JSON file
+    #[command(after_help = "Examples:
+  quantum_safe_multisig rules set rules.json --creator alice")]
+    Set {
+        /// JSON file holding the rules, or - for standard input
+        file: PathBuf,
+        /// Owner making the proposal
+        #[arg(long)]
+        creator: String,
+    },
+    /// Propose removing every transfer rule
+    #[command(after_help = "Examples:
+  quantum_safe_multisig rules clear --creator alice")]
+    Clear {
+        /// Owner making the proposal
+        #[arg(long)]
+        creator: String,
+    },
+}
+
+#[derive(Args)]
+struct InitArgs {
+    /// Keystore key to make an owner; repeat for each owner (default: every
+    /// key in the keystore)
+    #[arg(long = "owner", value_name = "NAME")]
+    owners: Vec<String>,
+    /// Combined weight of the owners whose signatures are required, which
+    /// with no --weight is the number of owners (default: every owner)
+    #[arg(long)]
+    threshold: Option<usize>,
+    /// Give an owner more than one vote; repeat for each owner
+    #[arg(long = "weight", value_name = "NAME=WEIGHT")]
+    weights: Vec<OwnerWeight>,
+    /// Decide approvals by a grouped policy instead of the threshold
+    #[arg(long)]
+    policy: Option<Policy>,
+    /// JSON file of amount bands and spending limits to enforce
+    #[arg(long, value_name = "FILE")]
+    rules: Option<PathBuf>,
+    /// Hold approved proposals this long before they may execute, in
+    /// seconds or with an s, m, h or d suffix
+    #[arg(long)]
+    delay: Option<Seconds>,
+    /// Chain or network the wallet operates on
+    #[arg(long, default_value = "mainnet")]
+    chain: String,
+    /// Encoding of the wallet file
+    #[arg(long, default_value = "cbor")]
+    encoding: Encoding,
+    #[command(flatten)]
+    encryption: EncryptionArgs,
+}
+
+/// `NAME=WEIGHT` on the command line.
+#[derive(Clone)]
+struct OwnerWeight(String, usize);
+
+impl std::str::FromStr for OwnerWeight {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, weight) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected NAME=WEIGHT, got {:?}", s))?;
+        let weight = weight
+            .parse()
+            .map_err(|_| format!("invalid weight {:?}", weight))?;
+        Ok(OwnerWeight(name.to_string(), weight))
+    }
+}
+
+#[derive(Args)]
+struct OwnerKeyArgs {
+    /// Owner whose key it is
+    name: String,
+    /// Owner making the proposal
+    #[arg(long)]
+    creator: String,
+    /// File holding the owner's key, as written by `keygen --export`;
+    /// composite keys are refused, since the command line cannot sign for them
+    #[arg(long, conflicts_with = "hsm")]
+    public_key: Option<PathBuf>,
+    /// Read the owner's key from a token
+    #[arg(long)]
+    hsm: bool,
+    /// Algorithm of the key on the token
+    #[arg(long, default_value = "ml-dsa-65")]
+    algorithm: Algorithm,
+    #[command(flatten)]
+    token: TokenKeyArgs,
+}
+
+#[derive(Args)]
+struct ProposeArgs {
+    /// Recipient of the transfer
+    recipient: String,
+    /// Amount in the asset's smallest unit
+    #[arg(long)]
+    amount: u64,
+    /// Asset to transfer
+    #[arg(long, default_value = "QSC")]
+    asset: String,
+    /// Memo to attach
+    #[arg(long, default_value = "")]
+    memo: String,
+    /// Owner making the proposal
+    #[arg(long)]
+    creator: String,
+    /// Earliest time the transfer may execute: Unix seconds, or +<delay>
+    /// from now
+    #[arg(long, value_name = "TIME")]
+    not_before: Option<When>,
+    /// Time from which the transfer may no longer execute: Unix seconds, or
+    /// +<delay> from now
+    #[arg(long, value_name = "TIME")]
+    expires_at: Option<When>,
+    /// Last time the transaction itself is valid, signed into the
+    /// transaction rather than the proposal: Unix seconds, or +<delay> from
+    /// now
+    #[arg(long, value_name = "TIME")]
+    expiry: Option<When>,
+}
+
+/// A duration on the command line: seconds, or a number with an `s`, `m`,
+/// `h` or `d` suffix.
+#[derive(Clone, Copy)]
+struct Seconds(u64);
+
+impl std::str::FromStr for Seconds {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (number, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+            Some(split) => s.split_at(split),
+            None => (s, "s"),
+        };
+        let unit = match unit {
+            "s" => 1,
+            "m" => 60,
+            "h" => 60 * 60,
+            "d" => 24 * 60 * 60,
+            _ => return Err(format!("unknown unit {:?}: use s, m, h or d", unit)),
+        };
+        number
+            .parse::<u64>()
+            .ok()
+            .and_then(|number| number.checked_mul(unit))
+            .map(Seconds)
+            .ok_or_else(|| format!("invalid duration {:?}", s))
+    }
 }
 
-impl QuantumSafeWallet {
-    pub fn new(owners: HashMap<String, PublicKey>, threshold: usize) -> Self {
-        assert!(threshold <= owners.len(), "Threshold must be <= owner count");
+/// A point in time on the command line: Unix seconds, or `+<duration>`
+/// from now.
+#[derive(Clone, Copy)]
+enum When {
+    At(u64),
+    In(Seconds),
+}
+
+impl When {
+    fn resolve(self, now: u64) -> u64 {
+        match self {
+            When::At(at) => at,
+            When::In(Seconds(seconds)) => now.saturating_add(seconds),
+        }
+    }
+}
+
+impl std::str::FromStr for When {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix('+') {
+            Some(duration) => Ok(When::In(duration.parse()?)),
+            None => s
+                .parse()
+                .map(When::At)
+                .map_err(|_| format!("expected Unix seconds or +<duration>, got {:?}", s)),
+        }
+    }
+}
+
+#[derive(Args)]
+struct ProposalArg {
+    /// Proposal to act on (default: the only pending one)
+    #[arg(long)]
+    proposal: Option<ProposalId>,
+}
+
+#[derive(Args)]
+struct SignArgs {
+    /// Owner to sign as
+    owner: String,
+    #[command(flatten)]
+    proposal: ProposalArg,
+    /// Sign with the owner's key on a token instead of the keystore
+    #[arg(long)]
+    hsm: bool,
+    #[command(flatten)]
+    token: TokenKeyArgs,
+}
+
+/// Which token key an owner signs with.
+#[derive(Args)]
+struct TokenKeyArgs {
+    /// Token holding the owner's key: label:<label> or serial:<serial>
+    #[arg(long)]
+    hsm_token: Option<TokenSelector>,
+    /// Key on the token: label:<label> or id:<hex> (default: label:<owner>)
+    #[arg(long)]
+    hsm_key: Option<KeySelector>,
+    /// Signing mechanism: CKM_EDDSA, CKM_ECDSA_SHA256, 0x<number> or
+    /// vendor:0x<offset> (default: the standard one for classical keys)
+    #[arg(long)]
+    hsm_mechanism: Option<Mechanism>,
+}
+
+#[derive(Args)]
+struct ExportArgs {
+    /// Export only this proposal and its signatures
+    #[arg(long)]
+    proposal: Option<ProposalId>,
+    #[command(flatten)]
+    file: FileArgs,
+}
+
+/// Where and how a document is written.
+#[derive(Args)]
+struct FileArgs {
+    /// Encoding of the document
+    #[arg(long, default_value = "cbor")]
+    encoding: Encoding,
+    /// Wrap the document in base64 armor for pasting into text channels
+    #[arg(long)]
+    armor: bool,
+    /// File to write (default: standard output)
+    #[arg(long)]
+    out: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct RequestArgs {
+    #[command(flatten)]
+    proposal: ProposalArg,
+    #[command(flatten)]
+    file: FileArgs,
+}
+
+#[derive(Args)]
+struct OfflineSignArgs {
+    /// Owner to sign as
+    owner: String,
+    /// Signing request, or - for standard input
+    request: PathBuf,
+    /// Sign with the owner's key on a token instead of the keystore
+    #[arg(long, requires = "hsm_token")]
+    hsm: bool,
+    #[command(flatten)]
+    token: TokenKeyArgs,
+    #[command(flatten)]
+    file: FileArgs,
+}
+
+#[derive(Args)]
+struct ImportArgs {
+    /// File to read, or - for standard input
+    file: PathBuf,
+}
+
+#[derive(Args)]
+struct KeygenArgs {
+    /// Owner the key is for
+    name: String,
+    /// Signature algorithm
+    #[arg(long, default_value = "ml-dsa-65")]
+    algorithm: Algorithm,
+    /// Generate the key on a token, or wrapped by one if it cannot
+    /// generate the algorithm
+    #[arg(long, requires = "hsm_token")]
+    hsm: bool,
+    /// Key-pair generation mechanism (needed for post-quantum keys)
+    #[arg(long)]
+    hsm_keygen_mechanism: Option<Mechanism>,
+    /// AES key that wraps software keys when the token cannot generate the
+    /// algorithm
+    #[arg(long, default_value = "label:qsms-wrap")]
+    hsm_wrap_key: KeySelector,
+    #[command(flatten)]
+    token: TokenKeyArgs,
+    /// Also write the public key to this file, for `owner add --public-key`
+    #[arg(long)]
+    export: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct StorageArgs {
+    /// Encoding of the wallet file
+    #[arg(long)]
+    encoding: Option<Encoding>,
+    #[command(flatten)]
+    encryption: EncryptionArgs,
+    /// Store the wallet file unencrypted
+    #[arg(long, conflicts_with = "encrypt")]
+    decrypt: bool,
+}
+
+#[derive(Args)]
+struct EncryptionArgs {
+    /// Encrypt the wallet file with a passphrase or a data key wrapped by
+    /// --hsm-wrap-key on --hsm-token
+    #[arg(long)]
+    encrypt: Option<EncryptWith>,
+    /// Token holding the wrapping key for --encrypt hsm
+    #[arg(
+        long = "hsm-token",
+        id = "encrypt_token",
+        required_if_eq("encrypt", "hsm")
+    )]
+    token: Option<TokenSelector>,
+    /// AES key that wraps the data key for --encrypt hsm
+    #[arg(
+        long = "hsm-wrap-key",
+        id = "encrypt_wrap_key",
+        default_value = "label:qsms-wrap"
+    )]
+    wrap_key: KeySelector,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum EncryptWith {
+    Passphrase,
+    Hsm,
+}
+
+impl EncryptionArgs {
+    fn encryption(&self) -> Option<Encryption> {
+        match (self.encrypt?, &self.token) {
+            (EncryptWith::Passphrase, _) => Some(Encryption::Passphrase),
+            (EncryptWith::Hsm, Some(token)) => Some(Encryption::Hsm {
+                token: token.clone(),
+                wrapping_key: self.wrap_key.clone(),
+            }),
+            (EncryptWith::Hsm, None) => None,
+        }
+    }
+}
+
+/// How results are printed.
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Output {
+    /// Sentences for people
+    Text,
+    /// One JSON document per command on standard output, errors included
+    Json,
+}
+
+/// What a command did, for people and for scripts.
+struct Report {
+    text: String,
+    json: Value,
+    /// Exit status of a successful run; `verify` reports its verdict here.
+    status: u8,
+}
+
+impl Report {
+    fn new(text: impl Into<String>, json: Value) -> Self {
         Self {
-            owners,
-            threshold,
-            signatures: HashMap::new(),
+            text: text.into(),
+            json,
+            status: 0,
+        }
+    }
+
+    /// A command whose output is its data, such as an export to standard
+    /// output.
+    fn none() -> Self {
+        Self::new("", Value::Null)
+    }
+
+    fn print(&self, output: Output) {
+        match output {
+            Output::Text if !self.text.is_empty() => println!("{}", self.text),
+            Output::Json if !self.json.is_null() => println!("{}", self.json),
+            _ => {}
         }
     }
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let output = cli.global.output;
+    match run(cli) {
+        Ok(report) => {
+            report.print(output);
+            ExitCode::from(report.status)
+        }
+        Err(err) => {
+            match output {
+                Output::Text => eprintln!("error: {}", err),
+                Output::Json => println!(
+                    "{}",
+                    json!({
+                        "error": {
+                            "kind": error_kind(&err),
+                            "code": exit_code(&err),
+                            "message": err.to_string(),
+                        }
+                    })
+                ),
+            }
+            ExitCode::from(exit_code(&err))
+        }
+    }
+}
+
+/// Process exit status for each class of failure, so scripts can tell a
+/// typo from a missing token from a bad signature. `verify` exits with
+/// [`NOT_APPROVED`] when a proposal lacks approvals.
+fn exit_code(err: &WalletError) -> u8 {
+    match err {
+        WalletError::Usage(_)
+        | WalletError::Algorithm(_)
+        | WalletError::InvalidThreshold { .. }
+        | WalletError::InvalidWeight { .. }
+        | WalletError::InvalidOwnerKey { .. }
+        | WalletError::Policy(_)
+        | WalletError::Rules(_)
+        | WalletError::InvalidTimeWindow(_)
+        | WalletError::InvalidWalletName(_)
+        | WalletError::OwnerExists(_)
+        | WalletError::InvalidOwnerName(_) => 2,
+        WalletError::UnknownOwner(_)
+        | WalletError::UnknownProposal(_)
+        | WalletError::NoSuchWallet(_) => 3,
+        WalletError::AlgorithmMismatch { .. }
+        | WalletError::KeyMismatch { .. }
+        | WalletError::BadSignature { .. }
+        | WalletError::TamperedProposal(_)
+        | WalletError::BundleMismatch { .. } => 4,
+        WalletError::NotApproved(_)
+        | WalletError::OutOfOrder { .. }
+        | WalletError::TooEarly { .. } => NOT_APPROVED,
+        WalletError::Transaction(_) => 6,
+        WalletError::Hsm(_) | WalletError::Pkcs11(_) | WalletError::Pin(_) => 7,
+        WalletError::Signer(_) => 8,
+        WalletError::Io(_) => 9,
+        WalletError::Serialization(_)
+        | WalletError::Cbor(_)
+        | WalletError::Armor(_)
+        | WalletError::UnsupportedFormat(_)
+        | WalletError::UnsupportedVersion { .. } => 10,
+        WalletError::Locked(_) => 11,
+        WalletError::Encrypted(_) | WalletError::NotEncrypted(_) | WalletError::Seal(_) => 12,
+        WalletError::Denied { .. } => DENIED,
+        WalletError::Expired { .. } | WalletError::CancelWindowClosed { .. } => TOO_LATE,
+    }
+}
+
+/// Exit status of a proposal that cannot execute yet.
+const NOT_APPROVED: u8 = 5;
+
+/// Exit status of a transfer the transfer rules refuse.
+const DENIED: u8 = 13;
+
+/// Exit status of a proposal that expired, or that can no longer be
+/// cancelled.
+const TOO_LATE: u8 = 14;
+
+/// Stable name of each error for `--output json`.
+fn error_kind(err: &WalletError) -> &'static str {
+    match err {
+        WalletError::Usage(_) => "usage",
+        WalletError::UnknownOwner(_) => "unknown_owner",
+        WalletError::OwnerExists(_) => "owner_exists",
+        WalletError::InvalidOwnerName(_) => "invalid_owner_name",
+        WalletError::UnknownProposal(_) => "unknown_proposal",
+        WalletError::InvalidThreshold { .. } => "invalid_threshold",
+        WalletError::InvalidWeight { .. } => "invalid_weight",
+        WalletError::InvalidOwnerKey { .. } => "invalid_owner_key",
+        WalletError::Policy(_) => "invalid_policy",
+        WalletError::Rules(_) => "invalid_rules",
+        WalletError::Denied { .. } => "denied",
+        WalletError::InvalidTimeWindow(_) => "invalid_time_window",
+        WalletError::TooEarly { .. } => "too_early",
+        WalletError::Expired { .. } => "expired",
+        WalletError::CancelWindowClosed { .. } => "cancel_window_closed",
+        WalletError::AlgorithmMismatch { .. } => "algorithm_mismatch",
+        WalletError::KeyMismatch { .. } => "key_mismatch",
+        WalletError::BadSignature { .. } => "bad_signature",
+        WalletError::TamperedProposal(_) => "tampered_proposal",
+        WalletError::BundleMismatch { .. } => "bundle_mismatch",
+        WalletError::NotApproved(_) => "not_approved",
+        WalletError::OutOfOrder { .. } => "out_of_order",
+        WalletError::Transaction(_) => "invalid_transaction",
+        WalletError::Algorithm(_) => "unknown_algorithm",
+        WalletError::Signer(_) => "signer",
+        WalletError::Hsm(_) => "hsm",
+        WalletError::Pkcs11(_) => "pkcs11",
+        WalletError::Pin(_) => "pin",
+        WalletError::InvalidWalletName(_) => "invalid_wallet_name",
+        WalletError::NoSuchWallet(_) => "no_such_wallet",
+        WalletError::Encrypted(_) => "encrypted",
+        WalletError::NotEncrypted(_) => "not_encrypted",
+        WalletError::Seal(_) => "decryption",
+        WalletError::Locked(_) => "locked",
+        WalletError::Io(_) => "io",
+        WalletError::Serialization(_) => "serialization",
+        WalletError::Cbor(_) => "cbor",
+        WalletError::Armor(_) => "armor",
+        WalletError::UnsupportedFormat(_) => "unsupported_format",
+        WalletError::UnsupportedVersion { .. } => "unsupported_version",
+    }
+}
 
-    /// Sign a transaction using HSM
-    pub fn sign_transaction_with_hsm(&mut self, owner: &str, hsm: &Ctx, message: &[u8], pin: &str) {
-        if let Some(pub_key) = self.owners.get(owner) {
-            let session = hsm.open_session(0, CKF_SERIAL_SESSION | CKF_RW_SESSION).unwrap();
-            hsm.login(session, pin).unwrap();
+/// Everything a command needs beyond its own arguments.
+struct Context {
+    wallet: String,
+    wallet_dir: WalletDir,
+    require_encryption: bool,
+    keystore: Keystore,
+    token: Rc<TokenAccess>,
+}
+
+impl Context {
+    /// Lock the selected wallet's file.
+    fn store(&self) -> Result<WalletStore, WalletError> {
+        let store = self
+            .wallet_dir
+            .open(&self.wallet)?
+            .with_unlocker(Box::new(CliUnlocker {
+                token: Rc::clone(&self.token),
+                passphrase: OnceCell::new(),
+            }));
+        Ok(if self.require_encryption {
+            store.require_encryption()
+        } else {
+            store
+        })
+    }
 
-            let signature = hsm.sign(session, message).unwrap();
-            self.signatures.insert(owner.to_string(), signature);
+    /// Lock and load the selected wallet.
+    fn load(&self) -> Result<(WalletStore, QuantumSafeWallet), WalletError> {
+        let store = self.store()?;
+        let wallet = store.load()?;
+        Ok((store, wallet))
+    }
+}
 
-            hsm.logout(session).unwrap();
-            println!("{} signed the transaction using HSM.", owner);
+fn run(cli: Cli) -> Result<Report, WalletError> {
+    let global = cli.global;
+    let cx = Context {
+        wallet: global.wallet,
+        wallet_dir: match global.wallet_dir {
+            Some(dir) => WalletDir::new(dir),
+            None => WalletDir::default_location()?,
+        },
+        require_encryption: global.require_encryption,
+        keystore: Keystore::open(global.keystore),
+        token: Rc::new(TokenAccess {
+            module: global.hsm_module,
+            pin_source: global.pin_source,
+            allow_insecure_pin: global.allow_insecure_pin,
+            ctx: OnceCell::new(),
+            pin: OnceCell::new(),
+        }),
+    };
+    match cli.command {
+        Command::Init(args) => init(&cx, args),
+        Command::Owner(OwnerCommand::List) => list_owners(&cx),
+        Command::Owner(OwnerCommand::Add(args)) => {
+            let key = owner_key(&cx, &args)?;
+            let change = OwnerChange::AddOwner {
+                owner: args.name,
+                key,
+            };
+            propose_change(&cx, &args.creator, change)
+        }
+        Command::Owner(OwnerCommand::Remove { name, creator }) => {
+            propose_change(&cx, &creator, OwnerChange::RemoveOwner { owner: name })
+        }
+        Command::Owner(OwnerCommand::ReplaceKey(args)) => {
+            let key = owner_key(&cx, &args)?;
+            let change = OwnerChange::ReplaceKey {
+                owner: args.name,
+                key,
+            };
+            propose_change(&cx, &args.creator, change)
+        }
+        Command::Owner(OwnerCommand::Weight {
+            name,
+            weight,
+            creator,
+        }) => propose_change(
+            &cx,
+            &creator,
+            OwnerChange::SetWeight {
+                owner: name,
+                weight,
+            },
+        ),
+        Command::Threshold(ThresholdCommand::Set { required, creator }) => {
+            propose_change(&cx, &creator, OwnerChange::SetThreshold { required })
+        }
+        Command::Policy(PolicyCommand::Show) => show_policy(&cx),
+        Command::Policy(PolicyCommand::Check { policy }) => {
+            let (_store, wallet) = cx.load()?;
+            let weights = owner_names(&wallet)
+                .into_iter()
+                .map(|owner| {
+                    let weight = wallet.weight(&owner).unwrap_or(1);
+                    (owner, weight)
+                })
+                .collect();
+            policy.validate(&weights)?;
+            Ok(Report::new(
+                format!("{} is satisfiable by the current owners.", policy),
+                json!({ "policy": policy, "valid": true }),
+            ))
+        }
+        Command::Policy(PolicyCommand::Set { policy, creator }) => propose_change(
+            &cx,
+            &creator,
+            OwnerChange::SetPolicy {
+                policy: Some(policy),
+            },
+        ),
+        Command::Policy(PolicyCommand::Clear { creator }) => {
+            propose_change(&cx, &creator, OwnerChange::SetPolicy { policy: None })
+        }
+        Command::Rules(RulesCommand::Show) => show_rules(&cx),
+        Command::Rules(RulesCommand::Set { file, creator }) => {
+            let rules = read_rules(&file)?;
+            propose_change(&cx, &creator, OwnerChange::SetRules { rules: Some(rules) })
+        }
+        Command::Rules(RulesCommand::Clear { creator }) => {
+            propose_change(&cx, &creator, OwnerChange::SetRules { rules: None })
+        }
+        Command::Delay(DelayCommand::Set { delay, creator }) => {
+            propose_change(&cx, &creator, OwnerChange::SetDelay { seconds: delay.0 })
+        }
+        Command::Propose(args) => propose(&cx, args),
+        Command::Sign(args) => sign(&cx, args),
+        Command::Cancel { owner, proposal } => {
+            let (store, mut wallet) = cx.load()?;
+            let proposal_id = selected_proposal(proposal.proposal, &wallet)?;
+            let proposal = wallet.cancel(&proposal_id, &owner)?;
+            store.save(&wallet)?;
+            Ok(Report::new(
+                format!(
+                    "{} cancelled proposal {}: {}.",
+                    owner,
+                    proposal.id(),
+                    proposal.action()
+                ),
+                json!({
+                    "proposal_id": proposal.id(),
+                    "cancelled_by": owner,
+                    "nonce": wallet.nonce(),
+                }),
+            ))
+        }
+        Command::Status => status(&cx),
+        Command::Verify(args) => {
+            let (_store, wallet) = cx.load()?;
+            let proposal_id = selected_proposal(args.proposal, &wallet)?;
+            let approval = wallet.approval(&proposal_id)?;
+            let now = wallet.now();
+            // The verdict comes from the same checks `execute` makes.
+            let (verdict, text, status) = match wallet.check_executable(&proposal_id) {
+                Ok(()) => ("approved", "Transaction Approved!".to_string(), 0),
+                Err(WalletError::Denied { reason, .. }) => {
+                    ("denied", format!("Transaction Denied! {}", reason), DENIED)
+                }
+                Err(WalletError::NotApproved(_)) => (
+                    "pending",
+                    format!(
+                        "Transaction Rejected! {}; missing: {}{}",
+                        tally(&wallet, &approval),
+                        approval.missing.join(", "),
+                        completions(&approval)
+                            .map(|text| format!("\n{}", text))
+                            .unwrap_or_default()
+                    ),
+                    NOT_APPROVED,
+                ),
+                Err(WalletError::Expired { .. }) => {
+                    ("expired", "Transaction Expired!".to_string(), TOO_LATE)
+                }
+                Err(WalletError::TooEarly { at, .. }) => (
+                    "waiting",
+                    format!(
+                        "Transaction Approved! It may execute in {}.",
+                        duration(at - now)
+                    ),
+                    NOT_APPROVED,
+                ),
+                Err(WalletError::OutOfOrder {
+                    expected, actual, ..
+                }) => (
+                    "out_of_order",
+                    format!(
+                        "Transaction Approved! It has nonce {} and the wallet is at nonce {}: \
+                         the proposals before it must execute or be cancelled first.",
+                        actual, expected
+                    ),
+                    NOT_APPROVED,
+                ),
+                Err(err) => return Err(err),
+            };
+            let mut report = Report::new(
+                text,
+                json!({
+                    "proposal_id": proposal_id,
+                    "verdict": verdict,
+                    "approval": approval,
+                }),
+            );
+            report.status = status;
+            Ok(report)
+        }
+        Command::Execute(args) => {
+            let (store, mut wallet) = cx.load()?;
+            let proposal_id = selected_proposal(args.proposal, &wallet)?;
+            let proposal = wallet.execute_transaction(&proposal_id)?;
+            store.save(&wallet)?;
+            Ok(Report::new(
+                format!(
+                    "Proposal {} executed at nonce {}: {}.",
+                    proposal.id(),
+                    proposal.nonce(),
+                    proposal.action()
+                ),
+                json!({
+                    "proposal_id": proposal.id(),
+                    "nonce": proposal.nonce(),
+                    "action": proposal.action(),
+                }),
+            ))
+        }
+        Command::Export(args) => export(&cx, args),
+        Command::Import(args) => import(&cx, args),
+        Command::Offline(OfflineCommand::Request(args)) => {
+            let (_store, wallet) = cx.load()?;
+            let proposal_id = selected_proposal(args.proposal.proposal, &wallet)?;
+            let request = wallet.signing_request(&proposal_id)?;
+            write_document(
+                &args.file,
+                SIGNING_REQUEST_LABEL,
+                request.encode(args.file.encoding)?,
+                json!({
+                    "proposal_id": proposal_id,
+                    "wallet_id": request.wallet_id(),
+                    "nonce": request.nonce(),
+                    "payload_hash": request.payload_hash(),
+                }),
+            )
+        }
+        Command::Offline(OfflineCommand::Sign(args)) => offline_sign(&cx, args),
+        Command::Offline(OfflineCommand::Combine { files }) => combine(&cx, &files),
+        Command::Keygen(args) => keygen(&cx, args),
+        Command::Storage(args) => {
+            let (mut store, wallet) = cx.load()?;
+            if let Some(encryption) = args.encryption.encryption() {
+                store.set_encryption(Some(encryption));
+            } else if args.decrypt {
+                store.set_encryption(None);
+            }
+            if let Some(encoding) = args.encoding {
+                store.set_encoding(encoding);
+            }
+            store.save(&wallet)?;
+            let (encryption, described) = match store.encryption() {
+                None => ("none", "unencrypted"),
+                Some(Encryption::Passphrase) => ("passphrase", "encrypted with a passphrase"),
+                Some(Encryption::Hsm { .. }) => ("hsm", "encrypted with a token-wrapped key"),
+            };
+            Ok(Report::new(
+                format!(
+                    "{} is stored as {}, {}.",
+                    store.path().display(),
+                    store.encoding(),
+                    described
+                ),
+                json!({
+                    "path": store.path(),
+                    "encoding": store.encoding().name(),
+                    "encryption": encryption,
+                }),
+            ))
+        }
+        Command::Migrate { dry_run } => migrate_wallet(&cx.store()?, dry_run),
+        Command::Wallets => {
+            let names = cx.wallet_dir.names()?;
+            Ok(Report::new(
+                names.join("\n"),
+                json!({ "wallet_dir": cx.wallet_dir.path(), "wallets": names }),
+            ))
+        }
+        Command::Tokens => {
+            let tokens = hsm::list_tokens(cx.token.ctx()?)?;
+            let text: Vec<String> = tokens
+                .iter()
+                .map(|token| {
+                    format!(
+                        "slot {}: {} (serial {}, {} {})",
+                        token.slot, token.label, token.serial, token.manufacturer, token.model
+                    )
+                })
+                .collect();
+            Ok(Report::new(text.join("\n"), json!({ "tokens": tokens })))
         }
     }
+}
 
-    /// Verify the transaction by checking if enough valid signatures exist
-    pub fn verify_transaction(&self, message: &[u8]) -> bool {
-        let valid_sigs = self.signatures.iter().filter(|(owner, sig)| {
-            if let Some(pub_key) = self.owners.get(*owner) {
-                sphincs::verify(message, sig, pub_key).is_ok()
+fn init(cx: &Context, args: InitArgs) -> Result<Report, WalletError> {
+    let mut store = cx.store()?;
+    if store.exists() {
+        return Err(WalletError::Usage(format!(
+            "{} already exists",
+            store.path().display()
+        )));
+    }
+    let names = if args.owners.is_empty() {
+        cx.keystore.names()?
+    } else {
+        args.owners
+    };
+    if names.is_empty() {
+        return Err(WalletError::Usage(format!(
+            "no owners: generate keys with `{} keygen` first",
+            BIN
+        )));
+    }
+    let mut owners = HashMap::new();
+    for name in names {
+        let key = single_key(&name, cx.keystore.public_key(&name)?)?;
+        if owners.insert(name.clone(), key).is_some() {
+            return Err(WalletError::Usage(format!(
+                "--owner {} is given more than once",
+                name
+            )));
+        }
+    }
+    let mut weights = HashMap::new();
+    for OwnerWeight(name, weight) in args.weights {
+        if weights.insert(name.clone(), weight).is_some() {
+            return Err(WalletError::Usage(format!(
+                "--weight for {} is given more than once",
+                name
+            )));
+        }
+    }
+    let threshold = args.threshold.unwrap_or_else(|| {
+        owners
+            .keys()
+            .map(|owner| weights.get(owner).copied().unwrap_or(1))
+            .fold(0, usize::saturating_add)
+    });
+    let mut wallet = QuantumSafeWallet::weighted(&args.chain, owners, weights, threshold)?;
+    if let Some(policy) = args.policy {
+        wallet = wallet.with_policy(policy)?;
+    }
+    if let Some(path) = &args.rules {
+        wallet = wallet.with_rules(read_rules(path)?)?;
+    }
+    if let Some(Seconds(delay)) = args.delay {
+        wallet = wallet.with_delay(delay);
+    }
+    store.set_encoding(args.encoding);
+    store.set_encryption(args.encryption.encryption());
+    store.save(&wallet)?;
+    let rule = match wallet.policy() {
+        Some(policy) => format!("policy {}", policy),
+        None => format!(
+            "{} of {}{}",
+            wallet.threshold().required(),
+            wallet.total_weight(),
+            if is_weighted(&wallet) {
+                " owner weight"
             } else {
-                false
+                " owners"
             }
-        }).count();
+        ),
+    };
+    Ok(Report::new(
+        format!(
+            "Created wallet {} at {}: {}, ID {}.",
+            cx.wallet,
+            store.path().display(),
+            rule,
+            wallet.wallet_id()
+        ),
+        json!({
+            "wallet": cx.wallet,
+            "path": store.path(),
+            "wallet_id": wallet.wallet_id(),
+            "chain": wallet.chain_id(),
+            "threshold": wallet.threshold().required(),
+            "policy": wallet.policy(),
+            "rules": wallet.rules(),
+            "delay": wallet.delay(),
+            "owners": owner_names(&wallet),
+            "weights": owner_names(&wallet)
+                .iter()
+                .map(|owner| (owner.clone(), json!(wallet.weight(owner))))
+                .collect::<serde_json::Map<_, _>>(),
+        }),
+    ))
+}
+
+fn show_policy(cx: &Context) -> Result<Report, WalletError> {
+    let (_store, wallet) = cx.load()?;
+    let text = match wallet.policy() {
+        Some(policy) => policy.to_string(),
+        None => format!(
+            "No approval policy; approvals need weight {} of {}.",
+            wallet.threshold().required(),
+            wallet.total_weight()
+        ),
+    };
+    Ok(Report::new(
+        text,
+        json!({
+            "policy": wallet.policy(),
+            "threshold": wallet.threshold().required(),
+            "total_weight": wallet.total_weight(),
+        }),
+    ))
+}
 
-        valid_sigs >= self.threshold
+fn show_rules(cx: &Context) -> Result<Report, WalletError> {
+    let (_store, wallet) = cx.load()?;
+    let Some(rules) = wallet.rules() else {
+        return Ok(Report::new(
+            "No transfer rules.".to_string(),
+            json!({ "rules": null, "caps": [] }),
+        ));
+    };
+    let mut text = Vec::new();
+    for band in &rules.bands {
+        text.push(format!(
+            "{} from {}: {}",
+            band.asset.as_deref().unwrap_or("any asset"),
+            band.from,
+            band.approval
+        ));
+    }
+    if let Some(policy) = &rules.owner_changes {
+        text.push(format!("owner changes: {}", policy));
+    }
+    if let Some(recipients) = &rules.recipients {
+        let recipients: Vec<&str> = recipients.iter().map(String::as_str).collect();
+        text.push(format!("recipients: {}", recipients.join(", ")));
+    }
+    for (asset, limit) in &rules.max_amounts {
+        text.push(format!("{} per transfer: at most {}", asset, limit));
     }
+    let mut caps = Vec::new();
+    for cap in &rules.caps {
+        let spent = wallet.spent(&cap.asset, cap.window);
+        text.push(format!(
+            "{} {}: {} of {} spent",
+            cap.asset, cap.window, spent, cap.limit
+        ));
+        caps.push(json!({
+            "asset": cap.asset,
+            "window": cap.window,
+            "limit": cap.limit,
+            "spent": spent,
+        }));
+    }
+    Ok(Report::new(
+        text.join("\n"),
+        json!({ "rules": rules, "caps": caps }),
+    ))
 }
 
-fn save_wallet(wallet: &QuantumSafeWallet) {
-    let serialized = serde_json::to_string(wallet).unwrap();
-    let mut file = OpenOptions::new().write(true).create(true).open("wallet.json").unwrap();
-    file.write_all(serialized.as_bytes()).unwrap();
+/// Transfer rules from a JSON file, or standard input for `-`.
+fn read_rules(path: &Path) -> Result<TransferRules, WalletError> {
+    let bytes = if path == Path::new("-") {
+        let mut bytes = Vec::new();
+        std::io::stdin().read_to_end(&mut bytes)?;
+        bytes
+    } else {
+        fs::read(path)?
+    };
+    Ok(serde_json::from_slice(&bytes)?)
 }
 
-fn load_wallet() -> QuantumSafeWallet {
-    let mut file = File::open("wallet.json").unwrap();
-    let mut contents = String::new();
-    file.read_to_string(&mut contents).unwrap();
-    serde_json::from_str(&contents).unwrap()
+fn list_owners(cx: &Context) -> Result<Report, WalletError> {
+    let (_store, wallet) = cx.load()?;
+    let mut lines = Vec::new();
+    let mut owners = Vec::new();
+    for name in owner_names(&wallet) {
+        let Some(key) = wallet.owner_key(&name) else {
+            continue;
+        };
+        let algorithms: Vec<&str> = key
+            .components()
+            .iter()
+            .map(|component| component.algorithm().name())
+            .collect();
+        let fingerprint = key.post_quantum().fingerprint();
+        let weight = wallet.weight(&name).unwrap_or(1);
+        let hsm_key = wallet.hsm_key(&name);
+        lines.push(match hsm_key {
+            Some(key_ref) => format!(
+                "{}  weight {}  {}  {}  (token {}, key {})",
+                name,
+                weight,
+                algorithms.join("+"),
+                fingerprint,
+                key_ref.token,
+                key_ref.key
+            ),
+            None => format!(
+                "{}  weight {}  {}  {}",
+                name,
+                weight,
+                algorithms.join("+"),
+                fingerprint
+            ),
+        });
+        owners.push(json!({
+            "name": name,
+            "weight": weight,
+            "algorithms": algorithms,
+            "fingerprint": fingerprint,
+            "hsm": hsm_key,
+        }));
+    }
+    Ok(Report::new(lines.join("\n"), json!({ "owners": owners })))
 }
 
-fn main() {
-    let matches = Command::new("Quantum-Safe Multi-Sig Wallet")
-        .version("1.0")
-        .author("Orly")
-        .about("A quantum-safe multi-signature wallet with HSM support")
-        .arg(Arg::new("sign")
-            .long("sign")
-            .takes_value(true)
-            .help("Sign a transaction using HSM"))
-        .arg(Arg::new("verify")
-            .long("verify")
-            .help("Verify a transaction"))
-        .get_matches();
+/// The key `args` points at: on a token, in a public key file, or in the
+/// keystore under the owner's name.
+fn owner_key(cx: &Context, args: &OwnerKeyArgs) -> Result<OwnerKey, WalletError> {
+    if args.hsm {
+        let key_ref = token_key(&args.name, &args.token, args.algorithm)?;
+        let ctx = cx.token.ctx()?;
+        let signer = Pkcs11Signer::new(ctx, &key_ref, cx.token.pin()?.as_str(), args.algorithm)?;
+        single_key(&args.name, signer.public_key()?)
+    } else if let Some(path) = &args.public_key {
+        let key: OwnerKey = serde_json::from_slice(&fs::read(path)?)?;
+        signing_key(&args.name, Some(&key))?;
+        Ok(key)
+    } else {
+        single_key(&args.name, cx.keystore.public_key(&args.name)?)
+    }
+}
 
-    let (pk1, sk1) = sphincs::keypair();
-    let (pk2, sk2) = sphincs::keypair();
-    let (pk3, sk3) = sphincs::keypair();
+/// `owner`'s key, if the command line can sign for it. It signs with one
+/// keystore entry or token key per owner, so it has no way to produce the
+/// classical half of a composite key and refuses those owners outright.
+fn signing_key<'a>(owner: &str, key: Option<&'a OwnerKey>) -> Result<&'a PublicKey, WalletError> {
+    match key {
+        None => Err(WalletError::UnknownOwner(owner.to_string())),
+        Some(OwnerKey::Single(key)) => Ok(key),
+        Some(OwnerKey::Composite { .. }) => Err(WalletError::Usage(format!(
+            "{}'s key pairs a classical key with a post-quantum one, which the command line cannot sign for; register the post-quantum key alone",
+            owner
+        ))),
+    }
+}
 
-    let mut owners = HashMap::new();
-    owners.insert("Alice".to_string(), pk1.clone());
-    owners.insert("Bob".to_string(), pk2.clone());
-    owners.insert("Charlie".to_string(), pk3.clone());
+/// `key` as `owner`'s only key, which it may be only if it is post-quantum.
+fn single_key(owner: &str, key: PublicKey) -> Result<OwnerKey, WalletError> {
+    OwnerKey::single(key).map_err(|source| WalletError::InvalidOwnerKey {
+        owner: owner.to_string(),
+        source,
+    })
+}
+
+fn propose_change(cx: &Context, creator: &str, change: OwnerChange) -> Result<Report, WalletError> {
+    let (store, mut wallet) = cx.load()?;
+    let description = change.to_string();
+    let proposal_id = wallet.propose_change(creator, change)?;
+    store.save(&wallet)?;
+    Ok(Report::new(
+        format!(
+            "Proposal {} to {} created; it takes effect once approved and executed.",
+            proposal_id, description
+        ),
+        proposal_json(&wallet, &proposal_id)?,
+    ))
+}
+
+fn propose(cx: &Context, args: ProposeArgs) -> Result<Report, WalletError> {
+    let (store, mut wallet) = cx.load()?;
+    let now = wallet.now();
+    let transaction = Transaction {
+        recipient: args.recipient,
+        amount: args.amount,
+        asset: args.asset,
+        memo: args.memo,
+        expiry: args.expiry.map(|when| when.resolve(now)),
+        nonce: 0,
+    };
+    let window = TimeWindow {
+        not_before: args.not_before.map(|when| when.resolve(now)),
+        expires_at: args.expires_at.map(|when| when.resolve(now)),
+    };
+    let proposal_id =
+        wallet.propose_within(&args.creator, Action::Transfer(transaction), window)?;
+    store.save(&wallet)?;
+    Ok(Report::new(
+        format!("Proposal {} created.", proposal_id),
+        proposal_json(&wallet, &proposal_id)?,
+    ))
+}
+
+fn sign(cx: &Context, args: SignArgs) -> Result<Report, WalletError> {
+    let (store, mut wallet) = cx.load()?;
+    let owner = args.owner.as_str();
+    let proposal_id = selected_proposal(args.proposal.proposal, &wallet)?;
+    let algorithm = signing_key(owner, wallet.owner_key(owner))?.algorithm();
+    let key_ref = if args.hsm {
+        if args.token.hsm_token.is_some() {
+            wallet.set_hsm_key(owner, token_key(owner, &args.token, algorithm)?)?;
+        }
+        let key_ref = wallet.hsm_key(owner).ok_or_else(|| {
+            WalletError::Usage(format!(
+                "no HSM key configured for {}; pass --hsm-token",
+                owner
+            ))
+        })?;
+        Some((key_ref.clone(), algorithm))
+    } else {
+        None
+    };
+    with_signer(cx, owner, key_ref, |signer| {
+        wallet.sign_transaction(&proposal_id, owner, signer)
+    })?;
+    store.save(&wallet)?;
+    let mut json = proposal_json(&wallet, &proposal_id)?;
+    json["signer"] = json!(owner);
+    Ok(Report::new(
+        format!("{} signed proposal {}.", owner, proposal_id),
+        json,
+    ))
+}
+
+fn status(cx: &Context) -> Result<Report, WalletError> {
+    let (store, wallet) = cx.load()?;
+    let owners = owner_names(&wallet);
+    let mut text = vec![
+        format!("Wallet:     {} ({})", cx.wallet, store.path().display()),
+        format!("ID:         {}", wallet.wallet_id()),
+        format!("Chain:      {}", wallet.chain_id()),
+        format!("Nonce:      {}", wallet.nonce()),
+        match wallet.policy() {
+            Some(policy) => format!("Policy:     {}", policy),
+            None if is_weighted(&wallet) => format!(
+                "Threshold:  weight {} of {}",
+                wallet.threshold().required(),
+                wallet.total_weight()
+            ),
+            None => format!(
+                "Threshold:  {} of {}",
+                wallet.threshold().required(),
+                owners.len()
+            ),
+        },
+        format!("Owners:     {}", with_weights(&wallet, &owners)),
+    ];
+    if let Some(rules) = wallet.rules() {
+        text.push(format!("Rules:      {}", rules));
+    }
+    if let Some(delay) = wallet.delay() {
+        text.push(format!("Delay:      {}", duration(delay)));
+    }
+    let mut proposals: Vec<_> = wallet.proposals().collect();
+    proposals.sort_by_key(|proposal| (proposal.nonce(), *proposal.id()));
+    if proposals.is_empty() {
+        text.push("No pending proposals.".to_string());
+    }
+    let mut pending = Vec::new();
+    for proposal in proposals {
+        let approval = wallet.approval(proposal.id())?;
+        text.push(String::new());
+        text.push(format!("Proposal {}", proposal.id()));
+        text.push(format!(
+            "  {} at nonce {}, proposed by {}",
+            proposal.action(),
+            proposal.nonce(),
+            proposal.creator()
+        ));
+        text.push(format!("  {}", tally(&wallet, &approval)));
+        if let Some(completions) = completions(&approval) {
+            text.push(format!("  {}", completions));
+        }
+        if let Some(denial) = &approval.denial {
+            text.push(format!("  denied: {}", denial));
+        }
+        if let Some(timing) = timing(wallet.now(), &approval) {
+            text.push(format!("  {}", timing));
+        }
+        pending.push(proposal_json(&wallet, proposal.id())?);
+    }
+    Ok(Report::new(
+        text.join("\n"),
+        json!({
+            "wallet": cx.wallet,
+            "path": store.path(),
+            "wallet_id": wallet.wallet_id(),
+            "chain": wallet.chain_id(),
+            "nonce": wallet.nonce(),
+            "threshold": wallet.threshold().required(),
+            "total_weight": wallet.total_weight(),
+            "policy": wallet.policy(),
+            "rules": wallet.rules(),
+            "delay": wallet.delay(),
+            "owners": owners,
+            "weights": owners
+                .iter()
+                .map(|owner| (owner.clone(), json!(wallet.weight(owner))))
+                .collect::<serde_json::Map<_, _>>(),
+            "proposals": pending,
+        }),
+    ))
+}
+
+/// Writes the export itself to standard output unless `--out` is given, in
+/// which case it reports what it wrote.
+fn export(cx: &Context, args: ExportArgs) -> Result<Report, WalletError> {
+    let (_store, wallet) = cx.load()?;
+    let (bytes, label) = match &args.proposal {
+        Some(id) => {
+            let proposal = wallet
+                .proposal(id)
+                .ok_or(WalletError::UnknownProposal(*id))?;
+            (
+                format::encode_proposal(proposal, args.file.encoding)?,
+                PROPOSAL_LABEL,
+            )
+        }
+        None => (format::encode(&wallet, args.file.encoding)?, WALLET_LABEL),
+    };
+    write_document(
+        &args.file,
+        label,
+        bytes,
+        json!({ "proposal_id": args.proposal }),
+    )
+}
+
+/// Write `bytes` as `args` asks. Writing to standard output prints nothing
+/// else; writing to a file reports it, with `details` added to the JSON.
+fn write_document(
+    args: &FileArgs,
+    label: &str,
+    bytes: Vec<u8>,
+    mut details: Value,
+) -> Result<Report, WalletError> {
+    let bytes = if args.armor {
+        armor::armor(label, &bytes).into_bytes()
+    } else {
+        bytes
+    };
+    let Some(path) = &args.out else {
+        if args.encoding == Encoding::Cbor && !args.armor {
+            return Err(WalletError::Usage(
+                "CBOR is binary; pass --armor or --out to write it".to_string(),
+            ));
+        }
+        std::io::stdout().write_all(&bytes)?;
+        return Ok(Report::none());
+    };
+    fs::write(path, &bytes)?;
+    details["path"] = json!(path);
+    details["bytes"] = json!(bytes.len());
+    details["encoding"] = json!(args.encoding.name());
+    details["armor"] = json!(args.armor);
+    Ok(Report::new(
+        format!("Wrote {} bytes to {}.", bytes.len(), path.display()),
+        details,
+    ))
+}
+
+/// Read a document from `path`, or standard input for `-`, removing any
+/// armor.
+fn read_document(path: &Path) -> Result<Vec<u8>, WalletError> {
+    let mut bytes = Vec::new();
+    if path == Path::new("-") {
+        std::io::stdin().read_to_end(&mut bytes)?;
+    } else {
+        bytes = fs::read(path)?;
+    }
+    if armor::is_armored(&bytes) {
+        let text = String::from_utf8_lossy(&bytes);
+        let label = armor::label_of(&text).unwrap_or(WALLET_LABEL);
+        bytes = armor::dearmor(label, &text)?;
+    }
+    Ok(bytes)
+}
+
+fn import(cx: &Context, args: ImportArgs) -> Result<Report, WalletError> {
+    let bytes = read_document(&args.file)?;
+    match format::decode_document(&bytes)? {
+        Document::Wallet(wallet) => {
+            let store = cx.store()?;
+            if store.exists() {
+                return Err(WalletError::Usage(format!(
+                    "{} already exists; import into a new --wallet",
+                    store.path().display()
+                )));
+            }
+            store.save(&wallet)?;
+            Ok(Report::new(
+                format!(
+                    "Imported wallet {} as {}.",
+                    wallet.wallet_id(),
+                    store.path().display()
+                ),
+                json!({
+                    "imported": "wallet",
+                    "wallet_id": wallet.wallet_id(),
+                    "path": store.path(),
+                }),
+            ))
+        }
+        Document::Proposal(proposal) => {
+            let (store, mut wallet) = cx.load()?;
+            let proposal_id = wallet.import_proposal(*proposal)?;
+            store.save(&wallet)?;
+            let mut json = proposal_json(&wallet, &proposal_id)?;
+            json["imported"] = json!("proposal");
+            Ok(Report::new(
+                format!(
+                    "Imported proposal {}; approved by {}.",
+                    proposal_id,
+                    wallet.approvals(&proposal_id)?.join(", ")
+                ),
+                json,
+            ))
+        }
+    }
+}
+
+fn keygen(cx: &Context, args: KeygenArgs) -> Result<Report, WalletError> {
+    let name = args.name.as_str();
+    let algorithm = args.algorithm;
+    if !policy::is_owner_name(name) {
+        return Err(WalletError::InvalidOwnerName(name.to_string()));
+    }
+    if args.export.is_some() && !algorithm.is_post_quantum() {
+        return Err(WalletError::InvalidOwnerKey {
+            owner: name.to_string(),
+            source: OwnerKeyError::ClassicalOnly(algorithm),
+        });
+    }
+    let mut text = Vec::new();
+    let (public_key, location, token_key) = match &args.token.hsm_token {
+        Some(token) if args.hsm => {
+            let ctx = cx.token.ctx()?;
+            let slot = token.find_slot(ctx)?;
+            let keygen_mechanism = args
+                .hsm_keygen_mechanism
+                .or_else(|| Mechanism::keygen_default_for(algorithm));
+            let native = match keygen_mechanism {
+                Some(mechanism) if hsm::supports(ctx, slot, mechanism)? => Some(mechanism),
+                _ => None,
+            };
+            let hsm_pin = cx.token.pin()?;
+            if let Some(keygen_mechanism) = native {
+                let key = hsm::with_session(ctx, slot, hsm_pin.as_str(), |session| {
+                    hsm::generate_keypair(ctx, session, algorithm, keygen_mechanism, name)
+                })?;
+                let key_ref = HsmKeyRef {
+                    token: token.clone(),
+                    key: key.clone(),
+                    mechanism: signing_mechanism(args.token.hsm_mechanism, algorithm)?,
+                };
+                let public_key =
+                    Pkcs11Signer::new(ctx, &key_ref, hsm_pin.as_str(), algorithm)?.public_key()?;
+                text.push(format!(
+                    "Generated {} key for {} on the token as {}; add it with `{} owner add {} --hsm --hsm-token {} --hsm-key {} --algorithm {}`.",
+                    algorithm, name, key, BIN, name, token, key, algorithm
+                ));
+                (public_key, "token", Some(key_ref))
+            } else {
+                let public_key = cx.keystore.generate_wrapped(
+                    name,
+                    algorithm,
+                    ctx,
+                    hsm_pin.as_str(),
+                    token,
+                    &args.hsm_wrap_key,
+                )?;
+                text.push(format!(
+                    "Token cannot generate {} keys; generated {}'s key in software, wrapped under {}.",
+                    algorithm, name, args.hsm_wrap_key
+                ));
+                (public_key, "keystore_wrapped", None)
+            }
+        }
+        _ => {
+            let passphrase = new_keystore_passphrase()?;
+            let public_key = cx.keystore.generate(name, algorithm, &passphrase)?;
+            text.push(format!("Generated {} key for {}.", algorithm, name));
+            (public_key, "keystore", None)
+        }
+    };
+    let fingerprint = public_key.fingerprint();
+    text.push(format!("Fingerprint: {}", fingerprint));
+    if let Some(path) = &args.export {
+        fs::write(
+            path,
+            serde_json::to_vec_pretty(&single_key(name, public_key)?)?,
+        )?;
+        text.push(format!("Public key written to {}.", path.display()));
+    }
+    Ok(Report::new(
+        text.join("\n"),
+        json!({
+            "name": name,
+            "algorithm": algorithm,
+            "fingerprint": fingerprint,
+            "location": location,
+            "hsm": token_key,
+            "export": args.export,
+        }),
+    ))
+}
+
+/// Runs without the wallet file: everything needed comes from the request.
+fn offline_sign(cx: &Context, args: OfflineSignArgs) -> Result<Report, WalletError> {
+    let owner = args.owner.as_str();
+    let request = SigningRequest::decode(&read_document(&args.request)?)?;
+    let algorithm = signing_key(owner, request.owner_key(owner))?.algorithm();
+    let key_ref = if args.hsm {
+        Some((token_key(owner, &args.token, algorithm)?, algorithm))
+    } else {
+        None
+    };
+    let partial = with_signer(cx, owner, key_ref, |signer| request.sign(owner, signer))?;
+    let mut report = write_document(
+        &args.file,
+        PARTIAL_SIGNATURES_LABEL,
+        partial.encode(args.file.encoding)?,
+        json!({
+            "proposal_id": request.proposal_id(),
+            "wallet_id": request.wallet_id(),
+            "nonce": request.nonce(),
+            "payload_hash": request.payload_hash(),
+            "signer": owner,
+        }),
+    )?;
+    if !report.text.is_empty() {
+        report.text = format!(
+            "{} signed proposal {}. {}",
+            owner,
+            request.proposal_id(),
+            report.text
+        );
+    }
+    Ok(report)
+}
+
+fn combine(cx: &Context, files: &[PathBuf]) -> Result<Report, WalletError> {
+    let (store, mut wallet) = cx.load()?;
+    let mut text = Vec::new();
+    let mut combined = Vec::new();
+    let mut proposal_ids = Vec::new();
+    for file in files {
+        let partial = PartialSignatures::decode(&read_document(file)?)?;
+        let proposal_id = wallet.combine(&partial)?;
+        text.push(format!(
+            "Added {}'s signature to proposal {}.",
+            partial.owner(),
+            proposal_id
+        ));
+        combined.push(json!({
+            "file": file,
+            "signer": partial.owner(),
+            "proposal_id": proposal_id,
+        }));
+        proposal_ids.push(proposal_id);
+    }
+    store.save(&wallet)?;
+    proposal_ids.sort_unstable();
+    proposal_ids.dedup();
+    let mut proposals = Vec::new();
+    for id in &proposal_ids {
+        let approval = wallet.approval(id)?;
+        text.push(format!("Proposal {}: {}.", id, tally(&wallet, &approval)));
+        if let Some(completions) = completions(&approval) {
+            text.push(format!("  {}", completions));
+        }
+        proposals.push(proposal_json(&wallet, id)?);
+    }
+    Ok(Report::new(
+        text.join("\n"),
+        json!({ "combined": combined, "proposals": proposals }),
+    ))
+}
+
+/// Passphrase to seal a new keystore key under, asked twice since a typo
+/// would lose the key for good.
+fn new_keystore_passphrase() -> Result<Zeroizing<String>, WalletError> {
+    let passphrase = Zeroizing::new(rpassword::prompt_password("New keystore passphrase: ")?);
+    if passphrase.is_empty() {
+        return Err(WalletError::Usage(
+            "the keystore passphrase must not be empty".to_string(),
+        ));
+    }
+    let repeated = Zeroizing::new(rpassword::prompt_password("Repeat keystore passphrase: ")?);
+    if passphrase != repeated {
+        return Err(WalletError::Usage(
+            "the passphrases do not match".to_string(),
+        ));
+    }
+    Ok(passphrase)
+}
+
+/// Run `f` with `owner`'s signer: the token key in `hsm` if given, otherwise
+/// the keystore entry, unlocked the way it is protected.
+fn with_signer<T>(
+    cx: &Context,
+    owner: &str,
+    hsm: Option<(HsmKeyRef, Algorithm)>,
+    f: impl FnOnce(&dyn Signer) -> Result<T, WalletError>,
+) -> Result<T, WalletError> {
+    if let Some((key_ref, algorithm)) = hsm {
+        let signer = Pkcs11Signer::new(
+            cx.token.ctx()?,
+            &key_ref,
+            cx.token.pin()?.as_str(),
+            algorithm,
+        )?;
+        f(&signer)
+    } else if cx.keystore.protection(owner)? == Protection::HsmWrapped {
+        let hsm_pin = cx.token.pin()?;
+        let signer = cx.keystore.signer(
+            owner,
+            Unlock::Hsm {
+                ctx: cx.token.ctx()?,
+                pin: hsm_pin.as_str(),
+            },
+        )?;
+        f(&signer)
+    } else {
+        let passphrase = Zeroizing::new(rpassword::prompt_password("Keystore passphrase: ")?);
+        let signer = cx.keystore.signer(owner, Unlock::Passphrase(&passphrase))?;
+        f(&signer)
+    }
+}
 
-    let mut wallet = QuantumSafeWallet::new(owners, 2);
+fn migrate_wallet(store: &WalletStore, dry_run: bool) -> Result<Report, WalletError> {
+    let plan = store.migration_plan()?;
+    let json = json!({
+        "path": store.path(),
+        "from": plan.from,
+        "to": plan.to,
+        "steps": plan.steps,
+        "migrated": !dry_run && !plan.is_current(),
+    });
+    if plan.is_current() {
+        return Ok(Report::new(
+            format!(
+                "{} is already at format version {}.",
+                store.path().display(),
+                plan.to
+            ),
+            json,
+        ));
+    }
+    let mut text = vec![format!(
+        "{} {} from format version {} to {}:",
+        store.path().display(),
+        if dry_run {
+            "would be migrated"
+        } else {
+            "migrated"
+        },
+        plan.from,
+        plan.to
+    )];
+    for step in &plan.steps {
+        text.push(format!("  - {}", step));
+    }
+    if !dry_run {
+        store.save(&store.load()?)?;
+    }
+    Ok(Report::new(text.join("\n"), json))
+}
+
+/// Whether any owner's approval counts for more than one vote.
+fn is_weighted(wallet: &QuantumSafeWallet) -> bool {
+    wallet.total_weight() != wallet.owners().count()
+}
 
-    let transaction = b"Transfer 10 coins";
+/// `owners` joined for display, with their weights if the wallet is
+/// weighted.
+fn with_weights(wallet: &QuantumSafeWallet, owners: &[String]) -> String {
+    let owners: Vec<String> = owners
+        .iter()
+        .map(|owner| match wallet.weight(owner) {
+            Some(weight) if is_weighted(wallet) => format!("{} ({})", owner, weight),
+            _ => owner.clone(),
+        })
+        .collect();
+    owners.join(", ")
+}
 
-    if let Some(owner) = matches.value_of("sign") {
-        let hsm = Ctx::new("/usr/lib/softhsm/libsofthsm2.so").unwrap();
-        let hsm_pin = "1234"; // Replace with secure pin management
+/// How far a proposal is from approval: whether the policy is met if the
+/// wallet has one, otherwise M-of-N when every owner has weight 1 and
+/// accumulated weight when not.
+fn tally(wallet: &QuantumSafeWallet, approval: &Approval) -> String {
+    let progress = match approval.required {
+        None if approval.approved => "policy met".to_string(),
+        None => "policy not met".to_string(),
+        Some(required) if is_weighted(wallet) => format!(
+            "approved with weight {} of {} needed",
+            approval.weight, required
+        ),
+        Some(required) => format!(
+            "approved by {} of {} needed",
+            approval.signers.len(),
+            required
+        ),
+    };
+    if approval.signers.is_empty() {
+        progress
+    } else {
+        format!("{}: {}", progress, with_weights(wallet, &approval.signers))
+    }
+}
 
-        wallet.sign_transaction_with_hsm(owner, &hsm, transaction, hsm_pin);
-        save_wallet(&wallet);
+/// Which further owners could complete a pending approval, smallest sets
+/// first.
+fn completions(approval: &Approval) -> Option<String> {
+    if approval.approved || approval.completions.is_empty() {
+        return None;
     }
+    let sets: Vec<String> = approval
+        .completions
+        .iter()
+        .map(|set| format!("[{}]", set.join(", ")))
+        .collect();
+    Some(format!("could be completed by: {}", sets.join(" or ")))
+}
 
-    if matches.is_present("verify") {
-        if wallet.verify_transaction(transaction) {
-            println!("Transaction Approved!");
+/// When a proposal may execute and until when, relative to `now`.
+fn timing(now: u64, approval: &Approval) -> Option<String> {
+    let opens = approval
+        .executable_at
+        .filter(|&at| now < at)
+        .map(|at| format!("may execute in {}", duration(at - now)));
+    let closes = approval.expires_at.map(|at| {
+        if now < at {
+            format!("expires in {}", duration(at - now))
         } else {
-            println!("Transaction Rejected!");
+            "expired".to_string()
+        }
+    });
+    let parts: Vec<String> = opens.into_iter().chain(closes).collect();
+    (!parts.is_empty()).then(|| parts.join(", "))
+}
+
+/// `seconds` as its two largest units, such as `1d 4h` or `5m 30s`.
+fn duration(seconds: u64) -> String {
+    let units = [(86_400, "d"), (3_600, "h"), (60, "m"), (1, "s")];
+    let parts: Vec<String> = units
+        .iter()
+        .scan(seconds, |left, &(size, unit)| {
+            let count = *left / size;
+            *left %= size;
+            Some((count, unit))
+        })
+        .skip_while(|&(count, _)| count == 0)
+        .take(2)
+        .filter(|&(count, _)| count > 0)
+        .map(|(count, unit)| format!("{}{}", count, unit))
+        .collect();
+    if parts.is_empty() {
+        "0s".to_string()
+    } else {
+        parts.join(" ")
+    }
+}
+
+/// Owner names, sorted.
+fn owner_names(wallet: &QuantumSafeWallet) -> Vec<String> {
+    let mut names: Vec<String> = wallet.owners().map(|(name, _)| name.to_string()).collect();
+    names.sort_unstable();
+    names
+}
+
+/// A pending proposal and where it stands, for `--output json`.
+fn proposal_json(wallet: &QuantumSafeWallet, id: &ProposalId) -> Result<Value, WalletError> {
+    let proposal = wallet
+        .proposal(id)
+        .ok_or(WalletError::UnknownProposal(*id))?;
+    Ok(json!({
+        "proposal_id": id,
+        "nonce": proposal.nonce(),
+        "creator": proposal.creator(),
+        "created_at": proposal.created_at(),
+        "action": proposal.action(),
+        "approval": wallet.approval(id)?,
+    }))
+}
+
+/// `proposal`, or the wallet's only pending proposal.
+fn selected_proposal(
+    proposal: Option<ProposalId>,
+    wallet: &QuantumSafeWallet,
+) -> Result<ProposalId, WalletError> {
+    if let Some(id) = proposal {
+        return Ok(id);
+    }
+    let mut pending = wallet.proposals();
+    match (pending.next(), pending.next()) {
+        (Some(proposal), None) => Ok(*proposal.id()),
+        (None, _) => Err(WalletError::Usage(
+            "the wallet has no pending proposals".to_string(),
+        )),
+        (Some(_), Some(_)) => Err(WalletError::Usage(
+            "the wallet has several pending proposals; pick one with --proposal".to_string(),
+        )),
+    }
+}
+
+/// The token key `owner` signs with, from `--hsm-token`, `--hsm-key` and
+/// `--hsm-mechanism`.
+fn token_key(
+    owner: &str,
+    args: &TokenKeyArgs,
+    algorithm: Algorithm,
+) -> Result<HsmKeyRef, WalletError> {
+    Ok(HsmKeyRef {
+        token: args
+            .hsm_token
+            .clone()
+            .ok_or_else(|| WalletError::Usage("--hsm needs --hsm-token".to_string()))?,
+        key: match &args.hsm_key {
+            Some(key) => key.clone(),
+            None => KeySelector::Label(owner.to_string()),
+        },
+        mechanism: signing_mechanism(args.hsm_mechanism, algorithm)?,
+    })
+}
+
+/// The PKCS#11 module and PIN, loaded and read at most once per run.
+struct TokenAccess {
+    module: String,
+    pin_source: PinSource,
+    allow_insecure_pin: bool,
+    ctx: OnceCell<Ctx>,
+    pin: OnceCell<Pin>,
+}
+
+impl TokenAccess {
+    fn ctx(&self) -> Result<&Ctx, WalletError> {
+        if let Some(ctx) = self.ctx.get() {
+            return Ok(ctx);
+        }
+        let ctx = Ctx::new_and_initialize(&self.module)?;
+        Ok(self.ctx.get_or_init(|| ctx))
+    }
+
+    fn pin(&self) -> Result<Pin, WalletError> {
+        if let Some(pin) = self.pin.get() {
+            return Ok(pin.clone());
+        }
+        let pin = self.pin_source.read("Token PIN", self.allow_insecure_pin)?;
+        Ok(self.pin.get_or_init(|| pin).clone())
+    }
+}
+
+/// Prompts for the wallet passphrase once, and shares the CLI's token access.
+struct CliUnlocker {
+    token: Rc<TokenAccess>,
+    passphrase: OnceCell<Zeroizing<String>>,
+}
+
+impl Unlocker for CliUnlocker {
+    fn passphrase(&self) -> Result<Zeroizing<String>, WalletError> {
+        if let Some(passphrase) = self.passphrase.get() {
+            return Ok(passphrase.clone());
+        }
+        let passphrase = Zeroizing::new(rpassword::prompt_password("Wallet passphrase: ")?);
+        Ok(self.passphrase.get_or_init(|| passphrase).clone())
+    }
+
+    /// Re-encrypting under the passphrase the wallet was opened with asks
+    /// for nothing; a passphrase set for the first time is asked twice.
+    fn new_passphrase(&self) -> Result<Zeroizing<String>, WalletError> {
+        if let Some(passphrase) = self.passphrase.get() {
+            return Ok(passphrase.clone());
+        }
+        let passphrase = Zeroizing::new(rpassword::prompt_password("New wallet passphrase: ")?);
+        let repeated = Zeroizing::new(rpassword::prompt_password("Repeat wallet passphrase: ")?);
+        if passphrase != repeated {
+            return Err(WalletError::Usage(
+                "the passphrases do not match".to_string(),
+            ));
         }
+        Ok(self.passphrase.get_or_init(|| passphrase).clone())
+    }
+
+    fn hsm(&self) -> Result<(&Ctx, Pin), WalletError> {
+        Ok((self.token.ctx()?, self.token.pin()?))
     }
 }
 
+/// `--hsm-mechanism`, or the standard mechanism for classical algorithms.
+fn signing_mechanism(
+    mechanism: Option<Mechanism>,
+    algorithm: Algorithm,
+) -> Result<Mechanism, WalletError> {
+    match mechanism {
+        Some(mechanism) => Ok(mechanism),
+        None => Mechanism::default_for(algorithm).ok_or_else(|| {
+            WalletError::Usage(format!(
+                "--hsm-mechanism is required for {} keys",
+                algorithm
+            ))
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quantum_safe_multisig::armor::ArmorError;
+    use quantum_safe_multisig::crypto::{UnknownAlgorithm, VerifyError};
+    use quantum_safe_multisig::hash::Hash256;
+    use quantum_safe_multisig::pin::PinError;
+    use quantum_safe_multisig::policy::PolicyError;
+    use quantum_safe_multisig::rules::{Denial, RulesError};
+    use quantum_safe_multisig::seal::SealError;
+    use quantum_safe_multisig::signer::SignerError;
+    use quantum_safe_multisig::transaction::TransactionError;
+    use std::collections::HashSet;
+
+    /// One error of each kind with its exit status and JSON kind.
+    fn errors() -> Vec<(WalletError, u8, &'static str)> {
+        let id = Hash256::of("test", &[]);
+        let path = PathBuf::from("default.wallet");
+        vec![
+            (WalletError::Usage("bad".to_string()), 2, "usage"),
+            (
+                WalletError::Algorithm(UnknownAlgorithm("rsa".to_string())),
+                2,
+                "unknown_algorithm",
+            ),
+            (
+                WalletError::InvalidThreshold {
+                    threshold: 3,
+                    total_weight: 2,
+                },
+                2,
+                "invalid_threshold",
+            ),
+            (
+                WalletError::InvalidWeight {
+                    owner: "alice".to_string(),
+                    reason: "must be at least 1",
+                },
+                2,
+                "invalid_weight",
+            ),
+            (
+                WalletError::InvalidOwnerKey {
+                    owner: "alice".to_string(),
+                    source: OwnerKeyError::ClassicalOnly(Algorithm::Ed25519),
+                },
+                2,
+                "invalid_owner_key",
+            ),
+            (
+                WalletError::Policy(PolicyError::ZeroThreshold),
+                2,
+                "invalid_policy",
+            ),
+            (
+                WalletError::Rules(RulesError::NoRecipients),
+                2,
+                "invalid_rules",
+            ),
+            (
+                WalletError::InvalidTimeWindow("it has already closed"),
+                2,
+                "invalid_time_window",
+            ),
+            (
+                WalletError::InvalidWalletName("..".to_string()),
+                2,
+                "invalid_wallet_name",
+            ),
+            (
+                WalletError::OwnerExists("alice".to_string()),
+                2,
+                "owner_exists",
+            ),
+            (
+                WalletError::InvalidOwnerName("a b".to_string()),
+                2,
+                "invalid_owner_name",
+            ),
+            (
+                WalletError::UnknownOwner("carol".to_string()),
+                3,
+                "unknown_owner",
+            ),
+            (WalletError::UnknownProposal(id), 3, "unknown_proposal"),
+            (WalletError::NoSuchWallet(path.clone()), 3, "no_such_wallet"),
+            (
+                WalletError::AlgorithmMismatch {
+                    owner: "alice".to_string(),
+                    algorithm: Algorithm::Falcon512,
+                },
+                4,
+                "algorithm_mismatch",
+            ),
+            (
+                WalletError::KeyMismatch {
+                    owner: "alice".to_string(),
+                    expected: id,
+                    actual: id,
+                },
+                4,
+                "key_mismatch",
+            ),
+            (
+                WalletError::BadSignature {
+                    owner: "alice".to_string(),
+                    source: VerifyError::AlgorithmMismatch {
+                        key: Algorithm::MlDsa44,
+                        signature: Algorithm::Falcon512,
+                    },
+                },
+                4,
+                "bad_signature",
+            ),
+            (WalletError::TamperedProposal(id), 4, "tampered_proposal"),
+            (
+                WalletError::BundleMismatch {
+                    field: "nonce",
+                    expected: "0".to_string(),
+                    found: "1".to_string(),
+                },
+                4,
+                "bundle_mismatch",
+            ),
+            (WalletError::NotApproved(id), NOT_APPROVED, "not_approved"),
+            (
+                WalletError::OutOfOrder {
+                    id,
+                    expected: 0,
+                    actual: 1,
+                },
+                NOT_APPROVED,
+                "out_of_order",
+            ),
+            (
+                WalletError::TooEarly { id, at: 0 },
+                NOT_APPROVED,
+                "too_early",
+            ),
+            (
+                WalletError::Transaction(TransactionError::Truncated),
+                6,
+                "invalid_transaction",
+            ),
+            (WalletError::Pin(PinError::Empty), 7, "pin"),
+            (
+                WalletError::Signer(SignerError::NoSuchKey("alice".to_string())),
+                8,
+                "signer",
+            ),
+            (WalletError::Io(std::io::Error::other("disk full")), 9, "io"),
+            (
+                WalletError::Serialization(serde_json::from_str::<Value>("{").unwrap_err()),
+                10,
+                "serialization",
+            ),
+            (WalletError::Cbor("truncated".to_string()), 10, "cbor"),
+            (
+                WalletError::Armor(ArmorError::MissingBegin(WALLET_LABEL)),
+                10,
+                "armor",
+            ),
+            (
+                WalletError::UnsupportedFormat("other".to_string()),
+                10,
+                "unsupported_format",
+            ),
+            (
+                WalletError::UnsupportedVersion {
+                    found: 9,
+                    supported: 7,
+                },
+                10,
+                "unsupported_version",
+            ),
+            (WalletError::Locked(path.clone()), 11, "locked"),
+            (WalletError::Encrypted(path.clone()), 12, "encrypted"),
+            (WalletError::NotEncrypted(path), 12, "not_encrypted"),
+            (WalletError::Seal(SealError::Decrypt), 12, "decryption"),
+            (
+                WalletError::Denied {
+                    id,
+                    reason: Denial::Recipient("qsc1thief".to_string()),
+                },
+                DENIED,
+                "denied",
+            ),
+            (WalletError::Expired { id, at: 0 }, TOO_LATE, "expired"),
+            (
+                WalletError::CancelWindowClosed { id, at: 0 },
+                TOO_LATE,
+                "cancel_window_closed",
+            ),
+        ]
+    }
+
+    #[test]
+    fn exit_codes_follow_the_documented_classes() {
+        for (err, code, _) in errors() {
+            assert_eq!(exit_code(&err), code, "{err:?}");
+        }
+        let used: HashSet<u8> = errors().iter().map(|(err, _, _)| exit_code(err)).collect();
+        assert!(!used.contains(&0) && !used.contains(&1));
+    }
+
+    #[test]
+    fn json_error_kinds_are_stable_and_distinct() {
+        let mut seen = HashSet::new();
+        for (err, _, kind) in errors() {
+            assert_eq!(error_kind(&err), kind, "{err:?}");
+            assert!(seen.insert(kind), "{kind} is used twice");
+            assert!(kind.bytes().all(|b| b.is_ascii_lowercase() || b == b'_'));
+        }
+    }
+}
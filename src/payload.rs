@@ -0,0 +1,92 @@
+//! Domain-separated payloads that owners actually sign.
+//!
+//! Signing raw message bytes would let an approval be replayed on another
+//! wallet, another network, or after the transaction already ran. Every
+//! signature therefore covers the message wrapped with the chain tag, the
+//! wallet ID and the nonce the proposal is bound to, and the proposal's
+//! time window if it has one.
+
+use crate::hash::Hash256;
+use crate::proposal::TimeWindow;
+
+/// Identifies the signing payload format; bump it if the layout changes.
+pub const DOMAIN_TAG: &str = "quantum_safe_multisig/signing-payload/v1";
+
+/// Format of payloads for proposals with a time window, which follows the
+/// message with the [window encoding](TimeWindow::encode). Proposals without
+/// one keep signing [`DOMAIN_TAG`] payloads, so earlier signatures stay
+/// valid.
+pub const WINDOWED_DOMAIN_TAG: &str = "quantum_safe_multisig/signing-payload/v2";
+
+/// Identifier of a [`crate::QuantumSafeWallet`].
+pub type WalletId = Hash256;
+
+pub struct SigningPayload<'a> {
+    pub chain_id: &'a str,
+    pub wallet_id: &'a WalletId,
+    pub nonce: u64,
+    pub message: &'a [u8],
+    pub window: &'a TimeWindow,
+}
+
+impl SigningPayload<'_> {
+    /// Canonical byte encoding: each variable-length field is prefixed with
+    /// its length as a big-endian `u32`, the nonce is a big-endian `u64`.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out =
+            Vec::with_capacity(DOMAIN_TAG.len() + self.chain_id.len() + self.message.len() + 56);
+        let tag = if self.window.is_unbounded() {
+            DOMAIN_TAG
+        } else {
+            WINDOWED_DOMAIN_TAG
+        };
+        put_field(&mut out, tag.as_bytes());
+        put_field(&mut out, self.chain_id.as_bytes());
+        out.extend_from_slice(self.wallet_id.as_bytes());
+        out.extend_from_slice(&self.nonce.to_be_bytes());
+        put_field(&mut out, self.message);
+        if !self.window.is_unbounded() {
+            out.extend_from_slice(&self.window.encode());
+        }
+        out
+    }
+}
+
+fn put_field(out: &mut Vec<u8>, field: &[u8]) {
+    out.extend_from_slice(&(field.len() as u32).to_be_bytes());
+    out.extend_from_slice(field);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_binding_changes_the_payload() {
+        let wallet_id = Hash256::of("wallet", &[b"first"]);
+        let other_wallet = Hash256::of("wallet", &[b"second"]);
+        let unbounded = TimeWindow::default();
+        let window = TimeWindow {
+            not_before: None,
+            expires_at: Some(1_700_000_000),
+        };
+        let payload = |chain_id, wallet_id, nonce, window| {
+            SigningPayload {
+                chain_id,
+                wallet_id,
+                nonce,
+                message: b"transfer",
+                window,
+            }
+            .to_bytes()
+        };
+        let base = payload("mainnet", &wallet_id, 7, &unbounded);
+        assert_ne!(base, payload("testnet", &wallet_id, 7, &unbounded));
+        assert_ne!(base, payload("mainnet", &other_wallet, 7, &unbounded));
+        assert_ne!(base, payload("mainnet", &wallet_id, 8, &unbounded));
+        assert_ne!(base, payload("mainnet", &wallet_id, 7, &window));
+        assert!(base[4..].starts_with(DOMAIN_TAG.as_bytes()));
+        assert!(payload("mainnet", &wallet_id, 7, &window)[4..]
+            .starts_with(WINDOWED_DOMAIN_TAG.as_bytes()));
+    }
+}
@@ -0,0 +1,410 @@
+//! Where HSM PINs come from.
+//!
+//! A [`PinSource`] is chosen on the command line: an interactive prompt, an
+//! environment variable, an inherited file descriptor, a file only the user
+//! can read, a helper command that prints the PIN, or a `pinentry` program.
+//! Sources that leak the PIN to other processes are refused unless the caller
+//! explicitly allows them.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use zeroize::Zeroizing;
+
+/// A PIN held in memory that is wiped on drop and never printed.
+#[derive(Clone)]
+pub struct Pin(Zeroizing<String>);
+
+impl Pin {
+    pub fn new(pin: Zeroizing<String>) -> Result<Self, PinError> {
+        if pin.is_empty() {
+            return Err(PinError::Empty);
+        }
+        Ok(Self(pin))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Pin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Pin(<redacted>)")
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub enum PinSource {
+    /// Ask on the terminal without echo.
+    Prompt,
+    /// Read an environment variable. Visible to anything that can read this
+    /// process's environment, so insecure.
+    Env(String),
+    /// Read the first line from an inherited file descriptor.
+    Fd(u32),
+    /// Read the first line of a file that only its owner can access.
+    File(PathBuf),
+    /// Run a shell command and take the first line it prints.
+    Command(String),
+    /// Ask a `pinentry` program over the Assuan protocol.
+    Pinentry(String),
+}
+
+impl PinSource {
+    /// Whether this source exposes the PIN beyond the current user's session
+    /// by design.
+    pub fn is_insecure(&self) -> bool {
+        matches!(self, PinSource::Env(_))
+    }
+
+    /// Obtain the PIN, explaining what it is for as `description` where the
+    /// source is interactive. Insecure sources, and PIN files other users can
+    /// read, are refused unless `allow_insecure` is set.
+    pub fn read(&self, description: &str, allow_insecure: bool) -> Result<Pin, PinError> {
+        if self.is_insecure() && !allow_insecure {
+            return Err(PinError::Insecure(self.clone()));
+        }
+        match self {
+            PinSource::Prompt => {
+                Pin::new(rpassword::prompt_password(format!("{}: ", description))?.into())
+            }
+            PinSource::Env(var) => match std::env::var(var) {
+                Ok(pin) => Pin::new(Zeroizing::new(pin)),
+                Err(std::env::VarError::NotUnicode(_)) => Err(PinError::NotUtf8),
+                Err(std::env::VarError::NotPresent) => Err(PinError::EnvNotSet(var.clone())),
+            },
+            PinSource::Fd(fd) => first_line(&Zeroizing::new(fs::read(format!("/dev/fd/{}", fd))?)),
+            PinSource::File(path) => {
+                if !allow_insecure {
+                    check_permissions(path)?;
+                }
+                first_line(&Zeroizing::new(fs::read(path)?))
+            }
+            PinSource::Command(command) => {
+                let output = Command::new("sh")
+                    .arg("-c")
+                    .arg(command)
+                    .stdin(Stdio::inherit())
+                    .stderr(Stdio::inherit())
+                    .output()?;
+                let stdout = Zeroizing::new(output.stdout);
+                if !output.status.success() {
+                    return Err(PinError::CommandFailed(output.status.to_string()));
+                }
+                first_line(&stdout)
+            }
+            PinSource::Pinentry(program) => pinentry(program, description),
+        }
+    }
+}
+
+/// The first line of `bytes`, without its line ending.
+fn first_line(bytes: &[u8]) -> Result<Pin, PinError> {
+    let line = bytes.split(|b| *b == b'\n').next().unwrap_or_default();
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    let pin = std::str::from_utf8(line).map_err(|_| PinError::NotUtf8)?;
+    Pin::new(pin.to_string().into())
+}
+
+#[cfg(unix)]
+fn check_permissions(path: &std::path::Path) -> Result<(), PinError> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let metadata = fs::metadata(path)?;
+    let mode = metadata.permissions().mode();
+    if !metadata.is_file() || mode & 0o077 != 0 {
+        return Err(PinError::Permissions {
+            path: path.to_path_buf(),
+            mode: mode & 0o7777,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn check_permissions(path: &std::path::Path) -> Result<(), PinError> {
+    Err(PinError::Permissions {
+        path: path.to_path_buf(),
+        mode: 0,
+    })
+}
+
+/// Run the minimal Assuan exchange `pinentry` programs understand:
+/// `SETDESC`, `SETPROMPT`, then `GETPIN`, which answers with a `D` line.
+fn pinentry(program: &str, description: &str) -> Result<Pin, PinError> {
+    let mut child = Command::new(program)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+    let mut input = child.stdin.take().expect("stdin is piped");
+    let mut output = BufReader::new(child.stdout.take().expect("stdout is piped"));
+
+    read_response(&mut output)?;
+    for command in [
+        format!("SETDESC {}", percent_encode(description)),
+        "SETPROMPT PIN:".to_string(),
+    ] {
+        writeln!(input, "{}", command)?;
+        read_response(&mut output)?;
+    }
+    writeln!(input, "GETPIN")?;
+    let pin = read_response(&mut output)?;
+    let _ = writeln!(input, "BYE");
+    drop(input);
+    let _ = child.wait();
+
+    let pin = pin.ok_or(PinError::Empty)?;
+    let pin = std::str::from_utf8(&pin).map_err(|_| PinError::NotUtf8)?;
+    Pin::new(pin.to_string().into())
+}
+
+/// Read up to the `OK` that ends a response, returning the last `D` line.
+fn read_response(output: &mut impl BufRead) -> Result<Option<Zeroizing<Vec<u8>>>, PinError> {
+    let mut data = None;
+    loop {
+        let mut line = Zeroizing::new(String::new());
+        if output.read_line(&mut line)? == 0 {
+            return Err(PinError::Pinentry("unexpected end of output".to_string()));
+        }
+        let line = line.trim_end_matches(['\r', '\n']);
+        if line == "OK" || line.starts_with("OK ") {
+            return Ok(data);
+        } else if let Some(error) = line.strip_prefix("ERR ") {
+            return Err(PinError::Pinentry(error.to_string()));
+        } else if let Some(value) = line.strip_prefix("D ") {
+            data = Some(percent_decode(value));
+        }
+        // Comments and status lines carry nothing we need.
+    }
+}
+
+/// Assuan escapes `%`, CR and LF in data as `%XX`.
+fn percent_encode(s: &str) -> String {
+    s.replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+fn percent_decode(s: &str) -> Zeroizing<Vec<u8>> {
+    let bytes = s.as_bytes();
+    let mut decoded = Zeroizing::new(Vec::with_capacity(bytes.len()));
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], escaped) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    decoded
+}
+
+impl fmt::Display for PinSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PinSource::Prompt => f.write_str("prompt"),
+            PinSource::Env(var) => write!(f, "env:{}", var),
+            PinSource::Fd(fd) => write!(f, "fd:{}", fd),
+            PinSource::File(path) => write!(f, "file:{}", path.display()),
+            PinSource::Command(command) => write!(f, "cmd:{}", command),
+            PinSource::Pinentry(program) => write!(f, "pinentry:{}", program),
+        }
+    }
+}
+
+/// Parses `prompt`, `env:<VAR>`, `fd:<n>`, `file:<path>`, `cmd:<command>`,
+/// `pinentry` or `pinentry:<program>`.
+impl FromStr for PinSource {
+    type Err = PinError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || PinError::InvalidSource(s.to_string());
+        Ok(match s.split_once(':') {
+            None if s == "prompt" => PinSource::Prompt,
+            None if s == "pinentry" => PinSource::Pinentry("pinentry".to_string()),
+            Some(("env", var)) if !var.is_empty() => PinSource::Env(var.to_string()),
+            Some(("fd", fd)) => PinSource::Fd(fd.parse().map_err(|_| invalid())?),
+            Some(("file", path)) if !path.is_empty() => PinSource::File(path.into()),
+            Some(("cmd", command)) if !command.is_empty() => {
+                PinSource::Command(command.to_string())
+            }
+            Some(("pinentry", program)) if !program.is_empty() => {
+                PinSource::Pinentry(program.to_string())
+            }
+            _ => return Err(invalid()),
+        })
+    }
+}
+
+impl From<PinSource> for String {
+    fn from(source: PinSource) -> String {
+        source.to_string()
+    }
+}
+
+impl TryFrom<String> for PinSource {
+    type Error = PinError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum PinError {
+    #[error("could not read PIN: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("invalid PIN source {0:?}")]
+    InvalidSource(String),
+    #[error("PIN source {0} is insecure; pass --allow-insecure-pin to use it anyway")]
+    Insecure(PinSource),
+    #[error("environment variable {0} is not set")]
+    EnvNotSet(String),
+    #[error("PIN file {} is accessible to other users (mode {mode:o})", path.display())]
+    Permissions { path: PathBuf, mode: u32 },
+    #[error("PIN command failed: {0}")]
+    CommandFailed(String),
+    #[error("pinentry failed: {0}")]
+    Pinentry(String),
+    #[error("PIN is empty")]
+    Empty,
+    #[error("PIN is not valid UTF-8")]
+    NotUtf8,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    fn scratch(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("qsms-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn sources_parse_and_display() {
+        for (text, source) in [
+            ("prompt", PinSource::Prompt),
+            ("env:HSM_PIN", PinSource::Env("HSM_PIN".to_string())),
+            ("fd:3", PinSource::Fd(3)),
+            ("file:/run/pin:1", PinSource::File("/run/pin:1".into())),
+            (
+                "cmd:pass show hsm",
+                PinSource::Command("pass show hsm".to_string()),
+            ),
+            (
+                "pinentry:pinentry-tty",
+                PinSource::Pinentry("pinentry-tty".to_string()),
+            ),
+        ] {
+            assert_eq!(text.parse::<PinSource>().unwrap(), source);
+            assert_eq!(source.to_string(), text);
+        }
+        assert_eq!(
+            "pinentry".parse::<PinSource>().unwrap(),
+            PinSource::Pinentry("pinentry".to_string())
+        );
+        for bad in [
+            "",
+            "env:",
+            "fd:x",
+            "fd:-1",
+            "file:",
+            "cmd:",
+            "pinentry:",
+            "stdin",
+        ] {
+            assert!(
+                matches!(bad.parse::<PinSource>(), Err(PinError::InvalidSource(_))),
+                "{bad:?} parsed"
+            );
+        }
+    }
+
+    #[test]
+    fn first_line_strips_the_line_ending() {
+        for input in [&b"1234"[..], b"1234\n", b"1234\r\n", b"1234\nextra\n"] {
+            assert_eq!(first_line(input).unwrap().as_str(), "1234");
+        }
+        assert!(matches!(first_line(b"\n1234"), Err(PinError::Empty)));
+        assert!(matches!(first_line(b""), Err(PinError::Empty)));
+        assert!(matches!(first_line(b"\xff\xfe"), Err(PinError::NotUtf8)));
+    }
+
+    #[test]
+    fn assuan_escapes_round_trip() {
+        let text = "50% done\r\nnext";
+        assert_eq!(percent_encode(text), "50%25 done%0D%0Anext");
+        assert_eq!(
+            percent_decode(&percent_encode(text)).as_slice(),
+            text.as_bytes()
+        );
+        assert_eq!(percent_decode("%7e%7E").as_slice(), b"~~");
+        assert_eq!(percent_decode("100%zz").as_slice(), b"100%zz");
+        assert_eq!(percent_decode("trailing%4").as_slice(), b"trailing%4");
+    }
+
+    #[test]
+    fn env_pins_are_insecure() {
+        let var = format!("QSMS_TEST_PIN_{}", std::process::id());
+        let source = PinSource::Env(var.clone());
+        assert!(matches!(
+            source.read("HSM PIN", false),
+            Err(PinError::Insecure(_))
+        ));
+        assert!(matches!(
+            source.read("HSM PIN", true),
+            Err(PinError::EnvNotSet(_))
+        ));
+        std::env::set_var(&var, "1234");
+        assert_eq!(source.read("HSM PIN", true).unwrap().as_str(), "1234");
+        std::env::remove_var(&var);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn pin_files_must_be_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = scratch("pin-file");
+        let path = dir.join("pin");
+        fs::write(&path, "1234\n").unwrap();
+        let source = PinSource::File(path.clone());
+
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+        assert!(check_permissions(&path).is_ok());
+        assert_eq!(source.read("HSM PIN", false).unwrap().as_str(), "1234");
+
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        assert!(matches!(
+            source.read("HSM PIN", false),
+            Err(PinError::Permissions { mode: 0o644, .. })
+        ));
+        assert_eq!(source.read("HSM PIN", true).unwrap().as_str(), "1234");
+
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o700)).unwrap();
+        assert!(matches!(
+            check_permissions(&dir),
+            Err(PinError::Permissions { .. })
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,556 @@
+//! Approval rules: which sets of owner signatures authorise a proposal.
+//!
+//! By default a wallet approves once its signers' weights reach a flat
+//! [`Threshold`]. A [`Policy`] replaces that with thresholds over groups of
+//! owners combined with `all` and `any`, written as text such as
+//!
+//! ```text
+//! all(2 of [cfo, controller, treasurer], 1 of [ciso, secops])
+//! any(3 of *, 1 of [recovery1, recovery2])
+//! ```
+//!
+//! `*` is every owner. Thresholds count owner weights, so with every weight
+//! at 1 `2 of [...]` means two of the listed owners.
+
+use crate::error::WalletError;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// Policies whose proposals lack more than this many owners are not
+/// searched for ways to complete them.
+pub const MAX_EXPLAINED_OWNERS: usize = 16;
+
+/// How deeply `all` and `any` may nest. Policies are read from wallet files
+/// and proposals, so the recursive parser must not follow any depth it is
+/// handed.
+pub const MAX_POLICY_DEPTH: usize = 8;
+
+/// Where a proposal stands against the threshold.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Approval {
+    /// Owners whose signatures are all present and valid, sorted.
+    pub signers: Vec<String>,
+    /// Owners who have not validly signed yet, sorted.
+    pub missing: Vec<String>,
+    /// Combined weight of `signers`.
+    pub weight: usize,
+    /// Weight the flat threshold needs; `None` when a [`Policy`] decides.
+    pub required: Option<usize>,
+    pub approved: bool,
+    /// Why the transfer rules refuse the proposal however many owners
+    /// sign, if they do.
+    pub denial: Option<String>,
+    /// Smallest sets of further owners whose signatures would approve the
+    /// proposal, each sorted. Empty once approved, or when more than
+    /// [`MAX_EXPLAINED_OWNERS`] owners are missing.
+    pub completions: Vec<Vec<String>>,
+    /// Earliest time the proposal may execute, as far as it is known: its
+    /// not-before time, or once approved the end of the execution delay if
+    /// that is later.
+    pub executable_at: Option<u64>,
+    /// Time from which the proposal may no longer execute, whether its
+    /// window closes or its transaction expires.
+    pub expires_at: Option<u64>,
+}
+
+/// Weighted approval: owners whose weights add up to at least `required`.
+/// When every owner has weight 1 this is plain M-of-N.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Threshold(usize);
+
+impl Threshold {
+    /// A threshold of `required` out of the owners' `total_weight`, which
+    /// must be at least one and no more than that total.
+    pub fn new(required: usize, total_weight: usize) -> Result<Self, WalletError> {
+        if required == 0 || required > total_weight {
+            return Err(WalletError::InvalidThreshold {
+                threshold: required,
+                total_weight,
+            });
+        }
+        Ok(Self(required))
+    }
+
+    pub fn required(self) -> usize {
+        self.0
+    }
+
+    /// Whether owners with a valid signature and a combined `weight` meet
+    /// the threshold.
+    pub fn is_met(self, weight: usize) -> bool {
+        weight >= self.0
+    }
+}
+
+/// Owners a threshold counts.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Group {
+    /// Every owner of the wallet.
+    Everyone,
+    Members(Vec<String>),
+}
+
+impl Group {
+    fn contains(&self, owner: &str) -> bool {
+        match self {
+            Group::Everyone => true,
+            Group::Members(members) => members.iter().any(|member| member == owner),
+        }
+    }
+}
+
+/// A rule over owner signatures, built from thresholds with `all` and `any`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub enum Policy {
+    /// Signers in `group` whose weights add up to `required`.
+    Threshold { required: usize, group: Group },
+    /// Every sub-policy.
+    All(Vec<Policy>),
+    /// At least one sub-policy.
+    Any(Vec<Policy>),
+}
+
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+pub enum PolicyError {
+    #[error("invalid policy at position {position}: {message}")]
+    Parse { position: usize, message: String },
+    #[error("{0:?} in the approval policy is not an owner")]
+    UnknownOwner(String),
+    #[error("{0:?} is listed twice in one policy group")]
+    DuplicateOwner(String),
+    #[error("a policy threshold must be at least 1")]
+    ZeroThreshold,
+    #[error("policy {0} cannot be met even if every owner signs")]
+    Unsatisfiable(String),
+}
+
+impl Policy {
+    /// Whether `signers`, with the owners' `weights`, satisfy the policy.
+    pub fn is_met(&self, signers: &[&str], weights: &HashMap<String, usize>) -> bool {
+        match self {
+            Policy::Threshold { required, group } => {
+                let weight: usize = signers
+                    .iter()
+                    .filter(|signer| group.contains(signer))
+                    .filter_map(|signer| weights.get(*signer))
+                    .sum();
+                weight >= *required
+            }
+            Policy::All(policies) => policies
+                .iter()
+                .all(|policy| policy.is_met(signers, weights)),
+            Policy::Any(policies) => policies
+                .iter()
+                .any(|policy| policy.is_met(signers, weights)),
+        }
+    }
+
+    /// Check the policy against the owners and their `weights`: every named
+    /// owner must exist, once per group, thresholds must be at least one,
+    /// and the policy must be met when every owner signs. Policies only get
+    /// easier to meet as signers are added, so that last check is enough to
+    /// rule out policies nobody can ever satisfy.
+    pub fn validate(&self, weights: &HashMap<String, usize>) -> Result<(), PolicyError> {
+        self.check_structure(weights)?;
+        let everyone: Vec<&str> = weights.keys().map(String::as_str).collect();
+        if !self.is_met(&everyone, weights) {
+            return Err(PolicyError::Unsatisfiable(self.to_string()));
+        }
+        Ok(())
+    }
+
+    fn check_structure(&self, weights: &HashMap<String, usize>) -> Result<(), PolicyError> {
+        match self {
+            Policy::Threshold { required, group } => {
+                if *required == 0 {
+                    return Err(PolicyError::ZeroThreshold);
+                }
+                if let Group::Members(members) = group {
+                    for (i, member) in members.iter().enumerate() {
+                        if !weights.contains_key(member) {
+                            return Err(PolicyError::UnknownOwner(member.clone()));
+                        }
+                        if members[..i].contains(member) {
+                            return Err(PolicyError::DuplicateOwner(member.clone()));
+                        }
+                    }
+                }
+                Ok(())
+            }
+            Policy::All(policies) | Policy::Any(policies) => policies
+                .iter()
+                .try_for_each(|policy| policy.check_structure(weights)),
+        }
+    }
+
+    /// The smallest sets of owners outside `signers` whose signatures would
+    /// meet the policy, each sorted, in order of size. None of the sets
+    /// contains another. Empty if the policy is already met, or if more than
+    /// [`MAX_EXPLAINED_OWNERS`] owners have not signed.
+    pub fn completions(
+        &self,
+        signers: &[&str],
+        weights: &HashMap<String, usize>,
+    ) -> Vec<Vec<String>> {
+        let mut missing: Vec<&str> = weights
+            .keys()
+            .map(String::as_str)
+            .filter(|owner| !signers.contains(owner))
+            .collect();
+        missing.sort_unstable();
+        if self.is_met(signers, weights) || missing.len() > MAX_EXPLAINED_OWNERS {
+            return Vec::new();
+        }
+        let mut subsets: Vec<u32> = (1..1u32 << missing.len()).collect();
+        subsets.sort_by_key(|subset| subset.count_ones());
+        let mut found: Vec<u32> = Vec::new();
+        for subset in subsets {
+            if found.iter().any(|smaller| subset & smaller == *smaller) {
+                continue;
+            }
+            let mut candidate = signers.to_vec();
+            candidate.extend(
+                (0..missing.len())
+                    .filter(|i| subset & (1 << i) != 0)
+                    .map(|i| missing[i]),
+            );
+            if self.is_met(&candidate, weights) {
+                found.push(subset);
+            }
+        }
+        let mut completions: Vec<Vec<String>> = found
+            .into_iter()
+            .map(|subset| {
+                (0..missing.len())
+                    .filter(|i| subset & (1 << i) != 0)
+                    .map(|i| missing[i].to_string())
+                    .collect()
+            })
+            .collect();
+        completions.sort_by(|a, b| a.len().cmp(&b.len()).then_with(|| a.cmp(b)));
+        completions
+    }
+}
+
+impl fmt::Display for Policy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Policy::Threshold { required, group } => match group {
+                Group::Everyone => write!(f, "{} of *", required),
+                Group::Members(members) => write!(f, "{} of [{}]", required, members.join(", ")),
+            },
+            Policy::All(policies) | Policy::Any(policies) => {
+                f.write_str(if matches!(self, Policy::All(_)) {
+                    "all("
+                } else {
+                    "any("
+                })?;
+                for (i, policy) in policies.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    policy.fmt(f)?;
+                }
+                f.write_str(")")
+            }
+        }
+    }
+}
+
+impl FromStr for Policy {
+    type Err = PolicyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            text: s,
+            position: 0,
+            depth: 0,
+        };
+        let policy = parser.policy()?;
+        parser.skip_whitespace();
+        if parser.position < s.len() {
+            return Err(parser.error("unexpected text after the policy"));
+        }
+        Ok(policy)
+    }
+}
+
+impl From<Policy> for String {
+    fn from(policy: Policy) -> String {
+        policy.to_string()
+    }
+}
+
+impl TryFrom<String> for Policy {
+    type Error = PolicyError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// Whether `name` can be written in a policy, and so be an owner's name.
+pub fn is_owner_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(is_name_char)
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '@')
+}
+
+/// Recursive-descent parser for the policy grammar:
+///
+/// ```text
+/// policy := ("all" | "any") "(" policy ("," policy)* ")" | count "of" group
+/// group  := "*" | "[" owner ("," owner)* "]"
+/// owner  := one or more letters, digits, '-', '_', '.' or '@'
+/// ```
+struct Parser<'a> {
+    text: &'a str,
+    position: usize,
+    /// `all` and `any` groups open at `position`.
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn policy(&mut self) -> Result<Policy, PolicyError> {
+        self.skip_whitespace();
+        let rest = &self.text[self.position..];
+        if rest.starts_with(|c: char| c.is_ascii_digit()) {
+            let required = self.count()?;
+            self.keyword("of")?;
+            let group = self.group()?;
+            return Ok(Policy::Threshold { required, group });
+        }
+        let word = self.word();
+        let combine = match word {
+            "all" => Policy::All,
+            "any" => Policy::Any,
+            _ => return Err(self.error("expected a count, all( or any(")),
+        };
+        if self.depth == MAX_POLICY_DEPTH {
+            return Err(self.error(&format!(
+                "all( and any( nest more than {} deep",
+                MAX_POLICY_DEPTH
+            )));
+        }
+        self.expect('(')?;
+        self.depth += 1;
+        let mut policies = vec![self.policy()?];
+        while self.eat(',') {
+            policies.push(self.policy()?);
+        }
+        self.depth -= 1;
+        self.expect(')')?;
+        Ok(combine(policies))
+    }
+
+    fn count(&mut self) -> Result<usize, PolicyError> {
+        let start = self.position;
+        let digits = self.take_while(|c| c.is_ascii_digit());
+        digits.parse().map_err(|_| PolicyError::Parse {
+            position: start,
+            message: format!("count {} is too large", digits),
+        })
+    }
+
+    fn group(&mut self) -> Result<Group, PolicyError> {
+        if self.eat('*') {
+            return Ok(Group::Everyone);
+        }
+        self.expect('[')?;
+        let mut members = vec![self.owner()?];
+        while self.eat(',') {
+            members.push(self.owner()?);
+        }
+        self.expect(']')?;
+        Ok(Group::Members(members))
+    }
+
+    fn owner(&mut self) -> Result<String, PolicyError> {
+        self.skip_whitespace();
+        let name = self.word();
+        if name.is_empty() {
+            return Err(self.error("expected an owner name"));
+        }
+        Ok(name.to_string())
+    }
+
+    fn keyword(&mut self, keyword: &str) -> Result<(), PolicyError> {
+        self.skip_whitespace();
+        if self.word() != keyword {
+            return Err(self.error(&format!("expected {:?}", keyword)));
+        }
+        Ok(())
+    }
+
+    fn word(&mut self) -> &str {
+        self.take_while(is_name_char)
+    }
+
+    fn take_while(&mut self, accept: impl Fn(char) -> bool) -> &str {
+        let rest = &self.text[self.position..];
+        let len = rest.find(|c| !accept(c)).unwrap_or(rest.len());
+        self.position += len;
+        &rest[..len]
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        self.skip_whitespace();
+        if self.text[self.position..].starts_with(c) {
+            self.position += c.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), PolicyError> {
+        if !self.eat(c) {
+            return Err(self.error(&format!("expected {:?}", c)));
+        }
+        Ok(())
+    }
+
+    fn skip_whitespace(&mut self) {
+        self.take_while(char::is_whitespace);
+    }
+
+    fn error(&self, message: &str) -> PolicyError {
+        PolicyError::Parse {
+            position: self.position,
+            message: message.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TREASURY: &str = "all(2 of [cfo, controller, treasurer], 1 of [ciso, secops])";
+
+    fn weights(owners: &[&str]) -> HashMap<String, usize> {
+        owners.iter().map(|owner| (owner.to_string(), 1)).collect()
+    }
+
+    fn treasury() -> HashMap<String, usize> {
+        weights(&["cfo", "controller", "treasurer", "ciso", "secops"])
+    }
+
+    #[test]
+    fn text_round_trips() {
+        for text in [
+            TREASURY,
+            "any(3 of *, 1 of [recovery1, recovery2])",
+            "1 of *",
+        ] {
+            let policy: Policy = text.parse().unwrap();
+            assert_eq!(policy.to_string(), text);
+        }
+        let spaced: Policy = " all( 2 of [ cfo,controller ] ,1 of * ) ".parse().unwrap();
+        assert_eq!(spaced.to_string(), "all(2 of [cfo, controller], 1 of *)");
+        for bad in ["", "2 of", "2 of [cfo", "all()", "2 of * extra", "two of *"] {
+            assert!(
+                matches!(bad.parse::<Policy>(), Err(PolicyError::Parse { .. })),
+                "{bad:?} parsed"
+            );
+        }
+    }
+
+    #[test]
+    fn validation_checks_owners_and_thresholds() {
+        let owners = treasury();
+        let policy: Policy = TREASURY.parse().unwrap();
+        assert_eq!(policy.validate(&owners), Ok(()));
+        let check = |text: &str| text.parse::<Policy>().unwrap().validate(&owners);
+        assert_eq!(
+            check("1 of [cfo, auditor]"),
+            Err(PolicyError::UnknownOwner("auditor".to_string()))
+        );
+        assert_eq!(
+            check("2 of [cfo, cfo]"),
+            Err(PolicyError::DuplicateOwner("cfo".to_string()))
+        );
+        assert_eq!(
+            check("any(0 of *, 1 of [cfo])"),
+            Err(PolicyError::ZeroThreshold)
+        );
+        assert!(matches!(
+            check("3 of [ciso, secops]"),
+            Err(PolicyError::Unsatisfiable(_))
+        ));
+        let mut heavy = owners.clone();
+        heavy.insert("ciso".to_string(), 2);
+        assert_eq!(
+            "3 of [ciso, secops]"
+                .parse::<Policy>()
+                .unwrap()
+                .validate(&heavy),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn policies_are_met_by_weight() {
+        let owners = treasury();
+        let policy: Policy = TREASURY.parse().unwrap();
+        assert!(!policy.is_met(&["cfo", "controller"], &owners));
+        assert!(!policy.is_met(&["cfo", "ciso", "secops"], &owners));
+        assert!(policy.is_met(&["cfo", "treasurer", "secops"], &owners));
+        let mut heavy = owners.clone();
+        heavy.insert("cfo".to_string(), 2);
+        assert!(policy.is_met(&["cfo", "ciso"], &heavy));
+    }
+
+    #[test]
+    fn completions_are_the_smallest_sets() {
+        let owners = treasury();
+        let policy: Policy = TREASURY.parse().unwrap();
+        let names = |sets: &[&[&str]]| -> Vec<Vec<String>> {
+            sets.iter()
+                .map(|set| set.iter().map(|owner| owner.to_string()).collect())
+                .collect()
+        };
+        assert_eq!(
+            policy.completions(&["cfo"], &owners),
+            names(&[
+                &["ciso", "controller"],
+                &["ciso", "treasurer"],
+                &["controller", "secops"],
+                &["secops", "treasurer"],
+            ])
+        );
+        assert_eq!(
+            policy.completions(&["cfo", "controller"], &owners),
+            names(&[&["ciso"], &["secops"]])
+        );
+        assert!(policy
+            .completions(&["cfo", "controller", "ciso"], &owners)
+            .is_empty());
+        let crowd: Vec<String> = (0..=MAX_EXPLAINED_OWNERS)
+            .map(|i| format!("owner{i}"))
+            .collect();
+        let crowd = crowd.iter().map(|owner| (owner.clone(), 1)).collect();
+        assert!("1 of *"
+            .parse::<Policy>()
+            .unwrap()
+            .completions(&[], &crowd)
+            .is_empty());
+    }
+
+    #[test]
+    fn nesting_is_limited() {
+        let nested = |depth: usize| format!("{}1 of *{}", "any(".repeat(depth), ")".repeat(depth));
+        assert!(nested(MAX_POLICY_DEPTH).parse::<Policy>().is_ok());
+        assert!(matches!(
+            nested(MAX_POLICY_DEPTH + 1).parse::<Policy>(),
+            Err(PolicyError::Parse { .. })
+        ));
+        assert!(nested(100_000).parse::<Policy>().is_err());
+    }
+}
@@ -0,0 +1,301 @@
+//! Transaction proposals awaiting owner approval.
+
+use crate::crypto::Signature;
+use crate::governance::OwnerChange;
+use crate::hash::Hash256;
+use crate::transaction::{Transaction, TransactionError};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+
+/// Content-derived identifier of a [`Proposal`].
+pub type ProposalId = Hash256;
+
+/// What a proposal does once approved.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Action {
+    Transfer(Transaction),
+    OwnerChange { change: OwnerChange, nonce: u64 },
+}
+
+impl Action {
+    /// Wallet nonce the action is bound to.
+    pub fn nonce(&self) -> u64 {
+        match self {
+            Action::Transfer(transaction) => transaction.nonce,
+            Action::OwnerChange { nonce, .. } => *nonce,
+        }
+    }
+
+    pub(crate) fn set_nonce(&mut self, value: u64) {
+        match self {
+            Action::Transfer(transaction) => transaction.nonce = value,
+            Action::OwnerChange { nonce, .. } => *nonce = value,
+        }
+    }
+
+    /// Canonical bytes owners sign: the transaction encoding for transfers,
+    /// the owner change encoding otherwise.
+    pub fn encode(&self) -> Vec<u8> {
+        match self {
+            Action::Transfer(transaction) => transaction.encode(),
+            Action::OwnerChange { change, nonce } => change.encode(*nonce),
+        }
+    }
+
+    /// Check the constraints that hold regardless of wallet state.
+    pub fn validate(&self) -> Result<(), TransactionError> {
+        match self {
+            Action::Transfer(transaction) => transaction.validate(),
+            Action::OwnerChange { .. } => Ok(()),
+        }
+    }
+
+    pub fn transaction(&self) -> Option<&Transaction> {
+        match self {
+            Action::Transfer(transaction) => Some(transaction),
+            Action::OwnerChange { .. } => None,
+        }
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Action::Transfer(transaction) => write!(
+                f,
+                "{} {} to {}",
+                transaction.amount, transaction.asset, transaction.recipient
+            ),
+            Action::OwnerChange { change, .. } => change.fmt(f),
+        }
+    }
+}
+
+/// When a proposal may execute, in Unix seconds; open-ended on either side
+/// when unset. Owners sign it along with the action.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimeWindow {
+    /// Earliest time the proposal may execute.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub not_before: Option<u64>,
+    /// Time from which the proposal may no longer execute.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+}
+
+impl TimeWindow {
+    pub fn is_unbounded(&self) -> bool {
+        self.not_before.is_none() && self.expires_at.is_none()
+    }
+
+    /// Whether the window has closed by `now`.
+    pub fn has_expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|expires_at| now >= expires_at)
+    }
+
+    /// Canonical encoding: per bound a `u8` flag (`0` absent, `1` present)
+    /// and then the big-endian `u64`, `not_before` first.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(18);
+        for bound in [self.not_before, self.expires_at] {
+            match bound {
+                Some(at) => {
+                    out.push(1);
+                    out.extend_from_slice(&at.to_be_bytes());
+                }
+                None => out.push(0),
+            }
+        }
+        out
+    }
+}
+
+/// An action put up for approval, together with the signatures collected so far.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Proposal {
+    id: ProposalId,
+    action: Action,
+    creator: String,
+    created_at: u64,
+    #[serde(default, skip_serializing_if = "TimeWindow::is_unbounded")]
+    window: TimeWindow,
+    /// When the signatures first met the approval rule, if they still do.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    approved_at: Option<u64>,
+    signatures: HashMap<String, Vec<Signature>>,
+}
+
+impl Proposal {
+    pub fn new(creator: &str, action: Action, window: TimeWindow, created_at: u64) -> Self {
+        Self {
+            id: Self::compute_id(creator, &action, &window, created_at),
+            action,
+            creator: creator.to_string(),
+            created_at,
+            window,
+            approved_at: None,
+            signatures: HashMap::new(),
+        }
+    }
+
+    /// Derive the ID from everything that defines the proposal, so the same
+    /// proposal always gets the same ID and any change to it gets a new one.
+    /// Proposals without a time window keep the IDs they had before windows
+    /// existed.
+    pub fn compute_id(
+        creator: &str,
+        action: &Action,
+        window: &TimeWindow,
+        created_at: u64,
+    ) -> ProposalId {
+        let action = action.encode();
+        let bounds = window.encode();
+        let created_at = created_at.to_be_bytes();
+        let mut fields: Vec<&[u8]> = vec![creator.as_bytes(), &created_at, &action];
+        if !window.is_unbounded() {
+            fields.push(&bounds);
+        }
+        Hash256::of("proposal", &fields)
+    }
+
+    pub fn id(&self) -> &ProposalId {
+        &self.id
+    }
+
+    pub fn action(&self) -> &Action {
+        &self.action
+    }
+
+    /// The transfer this proposal makes, if it is one.
+    pub fn transaction(&self) -> Option<&Transaction> {
+        self.action.transaction()
+    }
+
+    pub fn creator(&self) -> &str {
+        &self.creator
+    }
+
+    /// Creation time in seconds since the Unix epoch.
+    pub fn created_at(&self) -> u64 {
+        self.created_at
+    }
+
+    /// When the proposal may execute.
+    pub fn window(&self) -> &TimeWindow {
+        &self.window
+    }
+
+    /// Time from which the proposal may no longer execute: when its window
+    /// closes or just after its transaction expires, whichever comes first.
+    pub fn expires_at(&self) -> Option<u64> {
+        let expiry = self
+            .transaction()
+            .and_then(|transaction| transaction.expiry)
+            .map(|expiry| expiry.saturating_add(1));
+        match (self.window.expires_at, expiry) {
+            (Some(closes), Some(expiry)) => Some(closes.min(expiry)),
+            (closes, expiry) => closes.or(expiry),
+        }
+    }
+
+    /// Whether the proposal can no longer execute by `now`.
+    pub fn has_expired(&self, now: u64) -> bool {
+        self.expires_at()
+            .is_some_and(|expires_at| now >= expires_at)
+    }
+
+    /// When the proposal's signatures first met the approval rule, if they
+    /// still do.
+    pub fn approved_at(&self) -> Option<u64> {
+        self.approved_at
+    }
+
+    pub(crate) fn set_approved_at(&mut self, at: Option<u64>) {
+        self.approved_at = at;
+    }
+
+    /// Wallet nonce this proposal is bound to. It can only execute while the
+    /// wallet is at exactly this nonce.
+    pub fn nonce(&self) -> u64 {
+        self.action.nonce()
+    }
+
+    /// Signatures collected per owner. Owners with a composite key have one
+    /// entry per component algorithm.
+    pub fn signatures(&self) -> &HashMap<String, Vec<Signature>> {
+        &self.signatures
+    }
+
+    /// Record `signature` for `owner`, replacing any earlier signature of the
+    /// same algorithm.
+    pub(crate) fn add_signature(&mut self, owner: &str, signature: Signature) {
+        let signatures = self.signatures.entry(owner.to_string()).or_default();
+        signatures.retain(|existing| existing.algorithm() != signature.algorithm());
+        signatures.push(signature);
+    }
+
+    /// Drop every signature `owner` made on this proposal.
+    pub(crate) fn remove_signatures(&mut self, owner: &str) {
+        self.signatures.remove(owner);
+    }
+
+    /// Remove and return the collected signatures.
+    pub(crate) fn take_signatures(&mut self) -> HashMap<String, Vec<Signature>> {
+        std::mem::take(&mut self.signatures)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CREATED_AT: u64 = 1_700_000_000;
+
+    fn transfer(amount: u64, nonce: u64) -> Action {
+        Action::Transfer(Transaction {
+            recipient: "qsc1recipient".to_string(),
+            amount,
+            asset: "QSC".to_string(),
+            memo: String::new(),
+            expiry: None,
+            nonce,
+        })
+    }
+
+    fn id(creator: &str, action: Action, window: TimeWindow, created_at: u64) -> ProposalId {
+        *Proposal::new(creator, action, window, created_at).id()
+    }
+
+    #[test]
+    fn ids_are_derived_from_the_content() {
+        let open = TimeWindow::default();
+        let base = id("alice", transfer(5, 0), open, CREATED_AT);
+        assert_eq!(base, id("alice", transfer(5, 0), open, CREATED_AT));
+        let window = TimeWindow {
+            not_before: Some(CREATED_AT + 60),
+            expires_at: None,
+        };
+        let variants = [
+            id("bob", transfer(5, 0), open, CREATED_AT),
+            id("alice", transfer(6, 0), open, CREATED_AT),
+            id("alice", transfer(5, 1), open, CREATED_AT),
+            id("alice", transfer(5, 0), open, CREATED_AT + 1),
+            id("alice", transfer(5, 0), window, CREATED_AT),
+            id(
+                "alice",
+                Action::OwnerChange {
+                    change: OwnerChange::SetThreshold { required: 5 },
+                    nonce: 0,
+                },
+                open,
+                CREATED_AT,
+            ),
+        ];
+        for (i, variant) in variants.iter().enumerate() {
+            assert_ne!(*variant, base);
+            assert!(!variants[..i].contains(variant));
+        }
+    }
+}
@@ -0,0 +1,521 @@
+//! Rules keyed on what a proposal does rather than who signed it.
+//!
+//! [`TransferRules`] let a wallet treat transfers differently by amount,
+//! asset and recipient:
+//!
+//! - amount bands pick the approval policy a transfer needs, so small
+//!   payments can take one signer and large ones three;
+//! - an allow-list restricts who transfers may go to;
+//! - per-asset limits cap a single transfer;
+//! - rolling daily and weekly caps bound how much of an asset leaves the
+//!   wallet, measured against the [`Spend`] ledger the wallet keeps of
+//!   executed transfers.
+//!
+//! Owner changes can have an approval policy of their own. Limits are
+//! checked when a proposal is verified and again when it executes; a
+//! transfer that breaks one is refused with a [`Denial`] saying which.
+//!
+//! Owners sign rule changes as the canonical encoding below, integers
+//! big-endian and strings as a `u32` length and UTF-8 bytes:
+//!
+//! | field           | encoding                                                  |
+//! |-----------------|-----------------------------------------------------------|
+//! | version         | `u8`, always [`ENCODING_VERSION`]                          |
+//! | bands           | `u32` count, then per band the asset (empty for any), the `u64` starting amount and the policy text |
+//! | owner changes   | policy text, empty for none                               |
+//! | recipients      | `u8` flag (`0` any, `1` allow-list), `u32` count, then each recipient in sorted order |
+//! | max amounts     | `u32` count, then per asset in sorted order the asset and `u64` limit |
+//! | caps            | `u32` count, then per cap the asset, `u8` window (`1` daily, `2` weekly) and `u64` limit |
+
+use crate::policy::{Policy, PolicyError};
+use crate::proposal::Action;
+use crate::transaction::Transaction;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+
+pub const ENCODING_VERSION: u8 = 1;
+
+/// Spends older than this are dropped from the ledger: no cap looks further
+/// back.
+pub const LEDGER_RETENTION: u64 = Window::Weekly.seconds();
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TransferRules {
+    /// Approval policies by transfer amount.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub bands: Vec<AmountBand>,
+    /// Approval policy for owner, threshold and rule changes, in place of
+    /// the wallet's own.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner_changes: Option<Policy>,
+    /// Recipients transfers may go to; any recipient if `None`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recipients: Option<BTreeSet<String>>,
+    /// Largest single transfer of each asset.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub max_amounts: BTreeMap<String, u64>,
+    /// Rolling spend caps.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub caps: Vec<SpendCap>,
+}
+
+/// Transfers of at least `from` need `approval`. A band names an asset or,
+/// with none, covers assets that have no bands of their own.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AmountBand {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub asset: Option<String>,
+    pub from: u64,
+    pub approval: Policy,
+}
+
+/// At most `limit` of `asset` may be transferred in any `window`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SpendCap {
+    pub asset: String,
+    pub window: Window,
+    pub limit: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Window {
+    Daily,
+    Weekly,
+}
+
+impl Window {
+    pub const fn seconds(self) -> u64 {
+        match self {
+            Window::Daily => 24 * 60 * 60,
+            Window::Weekly => 7 * 24 * 60 * 60,
+        }
+    }
+}
+
+impl fmt::Display for Window {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Window::Daily => "daily",
+            Window::Weekly => "weekly",
+        })
+    }
+}
+
+/// An executed transfer, as recorded in the wallet's ledger.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Spend {
+    pub asset: String,
+    pub amount: u64,
+    /// Unix time in seconds the transfer executed.
+    pub at: u64,
+}
+
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+pub enum RulesError {
+    #[error("{what} for {asset} must be at least 1")]
+    ZeroLimit { what: &'static str, asset: String },
+    #[error("two amount bands for {asset} start at {from}")]
+    DuplicateBand { asset: String, from: u64 },
+    #[error("two {window} caps for {asset}")]
+    DuplicateCap { asset: String, window: Window },
+    #[error("the recipient allow-list is empty")]
+    NoRecipients,
+    #[error(transparent)]
+    Policy(#[from] PolicyError),
+}
+
+/// Why a transfer may not execute.
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+pub enum Denial {
+    #[error("{0} is not an allowed recipient")]
+    Recipient(String),
+    #[error("{amount} {asset} is over the limit of {limit} {asset} per transfer")]
+    MaxAmount {
+        asset: String,
+        amount: u64,
+        limit: u64,
+    },
+    #[error("{amount} {asset} would bring {window} spending to {total} {asset}, over the cap of {limit} {asset}")]
+    Cap {
+        asset: String,
+        window: Window,
+        amount: u64,
+        total: u64,
+        limit: u64,
+    },
+}
+
+impl TransferRules {
+    /// Check that every limit is at least one, no two bands or caps
+    /// overlap, and every policy is valid for owners with `weights`.
+    pub fn validate(&self, weights: &HashMap<String, usize>) -> Result<(), RulesError> {
+        let mut bands = BTreeSet::new();
+        for band in &self.bands {
+            let asset = band
+                .asset
+                .clone()
+                .unwrap_or_else(|| "any asset".to_string());
+            if !bands.insert((band.asset.clone(), band.from)) {
+                return Err(RulesError::DuplicateBand {
+                    asset,
+                    from: band.from,
+                });
+            }
+            band.approval.validate(weights)?;
+        }
+        if let Some(policy) = &self.owner_changes {
+            policy.validate(weights)?;
+        }
+        if self.recipients.as_ref().is_some_and(BTreeSet::is_empty) {
+            return Err(RulesError::NoRecipients);
+        }
+        for (asset, limit) in &self.max_amounts {
+            if *limit == 0 {
+                return Err(RulesError::ZeroLimit {
+                    what: "the transfer limit",
+                    asset: asset.clone(),
+                });
+            }
+        }
+        let mut caps = BTreeSet::new();
+        for cap in &self.caps {
+            if cap.limit == 0 {
+                return Err(RulesError::ZeroLimit {
+                    what: "a spend cap",
+                    asset: cap.asset.clone(),
+                });
+            }
+            if !caps.insert((cap.asset.as_str(), cap.window.seconds())) {
+                return Err(RulesError::DuplicateCap {
+                    asset: cap.asset.clone(),
+                    window: cap.window,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// The approval policy `action` needs under these rules, or `None` if
+    /// the wallet's own applies. A transfer takes the band with the highest
+    /// starting amount at or below its amount, from its asset's bands if
+    /// there are any and from the bands without an asset otherwise.
+    pub fn approval_for(&self, action: &Action) -> Option<&Policy> {
+        let Some(transaction) = action.transaction() else {
+            return self.owner_changes.as_ref();
+        };
+        let asset = Some(&transaction.asset);
+        let scope = if self.bands.iter().any(|band| band.asset.as_ref() == asset) {
+            asset
+        } else {
+            None
+        };
+        self.bands
+            .iter()
+            .filter(|band| band.asset.as_ref() == scope && band.from <= transaction.amount)
+            .max_by_key(|band| band.from)
+            .map(|band| &band.approval)
+    }
+
+    /// Check `transaction` against the allow-list, per-asset limits and
+    /// caps, counting the spends in `ledger` within each cap's window before
+    /// `now`.
+    pub fn check(
+        &self,
+        transaction: &Transaction,
+        ledger: &[Spend],
+        now: u64,
+    ) -> Result<(), Denial> {
+        if let Some(recipients) = &self.recipients {
+            if !recipients.contains(&transaction.recipient) {
+                return Err(Denial::Recipient(transaction.recipient.clone()));
+            }
+        }
+        if let Some(&limit) = self.max_amounts.get(&transaction.asset) {
+            if transaction.amount > limit {
+                return Err(Denial::MaxAmount {
+                    asset: transaction.asset.clone(),
+                    amount: transaction.amount,
+                    limit,
+                });
+            }
+        }
+        for cap in self
+            .caps
+            .iter()
+            .filter(|cap| cap.asset == transaction.asset)
+        {
+            let total =
+                spent(ledger, &cap.asset, cap.window, now).saturating_add(transaction.amount);
+            if total > cap.limit {
+                return Err(Denial::Cap {
+                    asset: cap.asset.clone(),
+                    window: cap.window,
+                    amount: transaction.amount,
+                    total,
+                    limit: cap.limit,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Canonical encoding; see the module docs for the layout.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = vec![ENCODING_VERSION];
+        out.extend_from_slice(&(self.bands.len() as u32).to_be_bytes());
+        for band in &self.bands {
+            put_str(&mut out, band.asset.as_deref().unwrap_or(""));
+            out.extend_from_slice(&band.from.to_be_bytes());
+            put_str(&mut out, &band.approval.to_string());
+        }
+        put_str(
+            &mut out,
+            &self
+                .owner_changes
+                .as_ref()
+                .map(Policy::to_string)
+                .unwrap_or_default(),
+        );
+        match &self.recipients {
+            None => out.extend_from_slice(&[0, 0, 0, 0, 0]),
+            Some(recipients) => {
+                out.push(1);
+                out.extend_from_slice(&(recipients.len() as u32).to_be_bytes());
+                for recipient in recipients {
+                    put_str(&mut out, recipient);
+                }
+            }
+        }
+        out.extend_from_slice(&(self.max_amounts.len() as u32).to_be_bytes());
+        for (asset, limit) in &self.max_amounts {
+            put_str(&mut out, asset);
+            out.extend_from_slice(&limit.to_be_bytes());
+        }
+        out.extend_from_slice(&(self.caps.len() as u32).to_be_bytes());
+        for cap in &self.caps {
+            put_str(&mut out, &cap.asset);
+            out.push(match cap.window {
+                Window::Daily => 1,
+                Window::Weekly => 2,
+            });
+            out.extend_from_slice(&cap.limit.to_be_bytes());
+        }
+        out
+    }
+}
+
+/// A one-line summary, such as `2 amount bands, 3 allowed recipients`.
+impl fmt::Display for TransferRules {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let counted = |count: usize, one: &str, many: &str| match count {
+            0 => None,
+            1 => Some(format!("1 {}", one)),
+            n => Some(format!("{} {}", n, many)),
+        };
+        let parts: Vec<String> = [
+            counted(self.bands.len(), "amount band", "amount bands"),
+            self.owner_changes
+                .as_ref()
+                .map(|policy| format!("owner changes need {}", policy)),
+            self.recipients.as_ref().and_then(|recipients| {
+                counted(recipients.len(), "allowed recipient", "allowed recipients")
+            }),
+            counted(self.max_amounts.len(), "transfer limit", "transfer limits"),
+            counted(self.caps.len(), "spend cap", "spend caps"),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        if parts.is_empty() {
+            f.write_str("no rules")
+        } else {
+            f.write_str(&parts.join(", "))
+        }
+    }
+}
+
+/// How much of `asset` the spends in `ledger` moved in the `window` up to
+/// `now`.
+pub fn spent(ledger: &[Spend], asset: &str, window: Window, now: u64) -> u64 {
+    let since = now.saturating_sub(window.seconds());
+    ledger
+        .iter()
+        .filter(|spend| spend.asset == asset && spend.at > since)
+        .fold(0, |total: u64, spend| total.saturating_add(spend.amount))
+}
+
+fn put_str(out: &mut Vec<u8>, value: &str) {
+    out.extend_from_slice(&(value.len() as u32).to_be_bytes());
+    out.extend_from_slice(value.as_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn spend(amount: u64, ago: u64) -> Spend {
+        Spend {
+            asset: "QSC".to_string(),
+            amount,
+            at: NOW - ago,
+        }
+    }
+
+    fn transfer(asset: &str, amount: u64, recipient: &str) -> Transaction {
+        Transaction {
+            recipient: recipient.to_string(),
+            amount,
+            asset: asset.to_string(),
+            memo: String::new(),
+            expiry: None,
+            nonce: 0,
+        }
+    }
+
+    fn band(asset: Option<&str>, from: u64, approval: &str) -> AmountBand {
+        AmountBand {
+            asset: asset.map(str::to_string),
+            from,
+            approval: approval.parse().unwrap(),
+        }
+    }
+
+    #[test]
+    fn bands_pick_the_policy_by_amount_and_asset() {
+        let rules = TransferRules {
+            bands: vec![
+                band(None, 0, "1 of *"),
+                band(None, 1_000, "2 of *"),
+                band(Some("BTC"), 10, "2 of [alice, bob]"),
+            ],
+            owner_changes: Some("2 of *".parse().unwrap()),
+            ..TransferRules::default()
+        };
+        let policy = |asset: &str, amount: u64| {
+            rules
+                .approval_for(&Action::Transfer(transfer(asset, amount, "qsc1shop")))
+                .map(Policy::to_string)
+        };
+        assert_eq!(policy("QSC", 999).as_deref(), Some("1 of *"));
+        assert_eq!(policy("QSC", 1_000).as_deref(), Some("2 of *"));
+        assert_eq!(policy("BTC", 10_000).as_deref(), Some("2 of [alice, bob]"));
+        assert_eq!(policy("BTC", 9), None);
+        let change = Action::OwnerChange {
+            change: crate::governance::OwnerChange::SetDelay { seconds: 0 },
+            nonce: 0,
+        };
+        assert_eq!(rules.approval_for(&change), rules.owner_changes.as_ref());
+    }
+
+    #[test]
+    fn limits_deny_transfers() {
+        let rules = TransferRules {
+            recipients: Some(BTreeSet::from(["qsc1shop".to_string()])),
+            max_amounts: BTreeMap::from([("QSC".to_string(), 500)]),
+            caps: vec![SpendCap {
+                asset: "QSC".to_string(),
+                window: Window::Daily,
+                limit: 1_000,
+            }],
+            ..TransferRules::default()
+        };
+        let check = |amount: u64, recipient: &str, ledger: &[Spend]| {
+            rules.check(&transfer("QSC", amount, recipient), ledger, NOW)
+        };
+        assert_eq!(check(500, "qsc1shop", &[]), Ok(()));
+        assert_eq!(
+            check(5, "qsc1thief", &[]),
+            Err(Denial::Recipient("qsc1thief".to_string()))
+        );
+        assert!(matches!(
+            check(501, "qsc1shop", &[]),
+            Err(Denial::MaxAmount { limit: 500, .. })
+        ));
+        let ledger = [spend(400, 60), spend(400, Window::Daily.seconds() - 1)];
+        assert!(matches!(
+            check(201, "qsc1shop", &ledger),
+            Err(Denial::Cap { total: 1_001, .. })
+        ));
+        assert_eq!(check(200, "qsc1shop", &ledger), Ok(()));
+        let aged = [spend(400, 60), spend(400, Window::Daily.seconds())];
+        assert_eq!(check(500, "qsc1shop", &aged), Ok(()));
+        assert_eq!(
+            rules.check(&transfer("BTC", 1_000_000, "qsc1shop"), &ledger, NOW),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn validation_rejects_overlaps_and_zero_limits() {
+        let weights = HashMap::from([("alice".to_string(), 1), ("bob".to_string(), 1)]);
+        let cap = |window| SpendCap {
+            asset: "QSC".to_string(),
+            window,
+            limit: 10,
+        };
+        let cases = [
+            (
+                TransferRules {
+                    bands: vec![band(None, 5, "1 of *"), band(None, 5, "2 of *")],
+                    ..TransferRules::default()
+                },
+                RulesError::DuplicateBand {
+                    asset: "any asset".to_string(),
+                    from: 5,
+                },
+            ),
+            (
+                TransferRules {
+                    caps: vec![cap(Window::Weekly), cap(Window::Weekly)],
+                    ..TransferRules::default()
+                },
+                RulesError::DuplicateCap {
+                    asset: "QSC".to_string(),
+                    window: Window::Weekly,
+                },
+            ),
+            (
+                TransferRules {
+                    max_amounts: BTreeMap::from([("QSC".to_string(), 0)]),
+                    ..TransferRules::default()
+                },
+                RulesError::ZeroLimit {
+                    what: "the transfer limit",
+                    asset: "QSC".to_string(),
+                },
+            ),
+            (
+                TransferRules {
+                    recipients: Some(BTreeSet::new()),
+                    ..TransferRules::default()
+                },
+                RulesError::NoRecipients,
+            ),
+            (
+                TransferRules {
+                    bands: vec![band(None, 0, "1 of [carol]")],
+                    ..TransferRules::default()
+                },
+                RulesError::Policy(PolicyError::UnknownOwner("carol".to_string())),
+            ),
+        ];
+        for (rules, error) in cases {
+            assert_eq!(rules.validate(&weights), Err(error));
+        }
+        let both = TransferRules {
+            caps: vec![cap(Window::Daily), cap(Window::Weekly)],
+            ..TransferRules::default()
+        };
+        assert_eq!(both.validate(&weights), Ok(()));
+    }
+
+    #[test]
+    fn spent_saturates() {
+        let ledger = [spend(u64::MAX, 10), spend(u64::MAX, 20)];
+        assert_eq!(spent(&ledger, "QSC", Window::Daily, NOW), u64::MAX);
+    }
+}
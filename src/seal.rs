@@ -0,0 +1,224 @@
+//! Passphrase-based authenticated encryption for secrets kept on disk.
+//!
+//! The passphrase is stretched with Argon2id and the result keys
+//! XChaCha20-Poly1305. KDF parameters, salt and nonce travel with the
+//! ciphertext so a sealed box can be opened with nothing but the passphrase.
+
+use argon2::Argon2;
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroizing;
+
+pub(crate) const KEY_LEN: usize = 32;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+
+/// Most memory, in KiB, that key derivation may ask for.
+pub const MAX_MEMORY_KIB: u32 = 1024 * 1024;
+
+/// Most passes over memory that key derivation may ask for.
+pub const MAX_ITERATIONS: u32 = 64;
+
+/// Most lanes that key derivation may ask for.
+pub const MAX_PARALLELISM: u32 = 16;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KdfParams {
+    /// Memory cost in KiB.
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+    #[serde(with = "crate::bytes")]
+    pub salt: Vec<u8>,
+}
+
+impl KdfParams {
+    /// Fresh parameters with a random salt and the default costs.
+    pub fn generate() -> Self {
+        Self {
+            memory_kib: 64 * 1024,
+            iterations: 3,
+            parallelism: 1,
+            salt: random_bytes(SALT_LEN),
+        }
+    }
+
+    /// Derive the key for `passphrase`. The parameters are read from files,
+    /// so costs above [`MAX_MEMORY_KIB`], [`MAX_ITERATIONS`] and
+    /// [`MAX_PARALLELISM`] are refused rather than attempted.
+    pub(crate) fn derive_key(
+        &self,
+        passphrase: &[u8],
+    ) -> Result<Zeroizing<[u8; KEY_LEN]>, SealError> {
+        if self.memory_kib > MAX_MEMORY_KIB
+            || self.iterations > MAX_ITERATIONS
+            || self.parallelism > MAX_PARALLELISM
+        {
+            return Err(SealError::TooCostly);
+        }
+        let params = argon2::Params::new(
+            self.memory_kib,
+            self.iterations,
+            self.parallelism,
+            Some(KEY_LEN),
+        )
+        .map_err(|_| SealError::InvalidParams)?;
+        let argon2 = Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params);
+        let mut key = Zeroizing::new([0u8; KEY_LEN]);
+        argon2
+            .hash_password_into(passphrase, &self.salt, key.as_mut())
+            .map_err(|_| SealError::InvalidParams)?;
+        Ok(key)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SealedBox {
+    pub kdf: KdfParams,
+    #[serde(with = "crate::bytes")]
+    pub nonce: Vec<u8>,
+    #[serde(with = "crate::bytes")]
+    pub ciphertext: Vec<u8>,
+}
+
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+pub enum SealError {
+    #[error("invalid key derivation parameters")]
+    InvalidParams,
+    #[error("key derivation parameters ask for more memory, passes or lanes than allowed")]
+    TooCostly,
+    #[error("wrong passphrase or corrupted ciphertext")]
+    Decrypt,
+}
+
+impl SealedBox {
+    /// Encrypt `plaintext` under `passphrase`. `aad` is authenticated but not
+    /// stored; the same bytes must be supplied to [`SealedBox::open`].
+    pub fn seal(passphrase: &[u8], plaintext: &[u8], aad: &[u8]) -> Result<Self, SealError> {
+        let kdf = KdfParams::generate();
+        let key = kdf.derive_key(passphrase)?;
+        let (nonce, ciphertext) = encrypt(&key, plaintext, aad)?;
+        Ok(Self {
+            kdf,
+            nonce,
+            ciphertext,
+        })
+    }
+
+    pub fn open(&self, passphrase: &[u8], aad: &[u8]) -> Result<Zeroizing<Vec<u8>>, SealError> {
+        let key = self.kdf.derive_key(passphrase)?;
+        decrypt(&key, &self.nonce, &self.ciphertext, aad)
+    }
+}
+
+/// XChaCha20-Poly1305 under a raw key with a fresh random nonce, returned
+/// alongside the ciphertext.
+pub(crate) fn encrypt(
+    key: &[u8; KEY_LEN],
+    plaintext: &[u8],
+    aad: &[u8],
+) -> Result<(Vec<u8>, Vec<u8>), SealError> {
+    let nonce = random_bytes(NONCE_LEN);
+    let ciphertext = XChaCha20Poly1305::new(key.into())
+        .encrypt(
+            XNonce::from_slice(&nonce),
+            Payload {
+                msg: plaintext,
+                aad,
+            },
+        )
+        .map_err(|_| SealError::Decrypt)?;
+    Ok((nonce, ciphertext))
+}
+
+pub(crate) fn decrypt(
+    key: &[u8; KEY_LEN],
+    nonce: &[u8],
+    ciphertext: &[u8],
+    aad: &[u8],
+) -> Result<Zeroizing<Vec<u8>>, SealError> {
+    if nonce.len() != NONCE_LEN {
+        return Err(SealError::Decrypt);
+    }
+    XChaCha20Poly1305::new(key.into())
+        .decrypt(
+            XNonce::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad,
+            },
+        )
+        .map(Zeroizing::new)
+        .map_err(|_| SealError::Decrypt)
+}
+
+pub(crate) fn random_bytes(len: usize) -> Vec<u8> {
+    let mut bytes = vec![0u8; len];
+    getrandom::getrandom(&mut bytes).expect("system RNG unavailable");
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AAD: &[u8] = b"test";
+
+    /// Parameters cheap enough to derive in a debug build.
+    fn cheap() -> KdfParams {
+        KdfParams {
+            memory_kib: 64,
+            iterations: 1,
+            ..KdfParams::generate()
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let kdf = cheap();
+        let key = kdf.derive_key(b"correct horse").unwrap();
+        let (nonce, ciphertext) = encrypt(&key, b"wallet", AAD).unwrap();
+        let key = kdf.derive_key(b"correct horse").unwrap();
+        assert_eq!(
+            decrypt(&key, &nonce, &ciphertext, AAD).unwrap().as_slice(),
+            b"wallet"
+        );
+        assert_eq!(
+            decrypt(&key, &nonce, &ciphertext, b"other"),
+            Err(SealError::Decrypt)
+        );
+    }
+
+    #[test]
+    fn wrong_passphrase_fails() {
+        let kdf = cheap();
+        let key = kdf.derive_key(b"correct horse").unwrap();
+        let (nonce, ciphertext) = encrypt(&key, b"wallet", AAD).unwrap();
+        let wrong = kdf.derive_key(b"battery staple").unwrap();
+        assert_eq!(
+            decrypt(&wrong, &nonce, &ciphertext, AAD),
+            Err(SealError::Decrypt)
+        );
+    }
+
+    #[test]
+    fn excessive_costs_are_refused() {
+        for kdf in [
+            KdfParams {
+                memory_kib: MAX_MEMORY_KIB + 1,
+                ..cheap()
+            },
+            KdfParams {
+                iterations: MAX_ITERATIONS + 1,
+                ..cheap()
+            },
+            KdfParams {
+                parallelism: MAX_PARALLELISM + 1,
+                ..cheap()
+            },
+        ] {
+            assert_eq!(kdf.derive_key(b"pass").err(), Some(SealError::TooCostly));
+        }
+    }
+}
@@ -0,0 +1,342 @@
+use super::{Signer, SignerError};
+use crate::crypto::{self, Algorithm, PublicKey, SecretKey, Signature};
+#[cfg(feature = "pkcs11")]
+use crate::hsm::{self, Ctx};
+use crate::hsm::{KeySelector, TokenSelector};
+use crate::policy;
+use crate::seal::SealedBox;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// One key in a [`Keystore`]: the public key in the clear, the secret key
+/// sealed under a passphrase or wrapped by an HSM.
+#[derive(Debug, Serialize, Deserialize)]
+struct KeystoreEntry {
+    public_key: PublicKey,
+    secret_key: ProtectedSecret,
+}
+
+/// Untagged so entries written before HSM wrapping existed still load.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+enum ProtectedSecret {
+    Passphrase(SealedBox),
+    HsmWrapped(WrappedSecret),
+}
+
+/// A secret key encrypted under an AES key held on a PKCS#11 token, for
+/// tokens that cannot generate or use the owner's scheme natively.
+#[derive(Debug, Serialize, Deserialize)]
+struct WrappedSecret {
+    token: TokenSelector,
+    wrapping_key: KeySelector,
+    #[serde(with = "crate::bytes")]
+    wrapped: Vec<u8>,
+}
+
+/// What it takes to get at a keystore entry's secret key.
+pub enum Unlock<'a> {
+    Passphrase(&'a str),
+    #[cfg(feature = "pkcs11")]
+    Hsm {
+        ctx: &'a Ctx,
+        pin: &'a str,
+    },
+}
+
+/// How a keystore entry's secret key is protected.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Protection {
+    Passphrase,
+    HsmWrapped,
+}
+
+impl KeystoreEntry {
+    /// The public key is bound to the ciphertext so entries cannot be
+    /// mixed and matched.
+    fn aad(public_key: &PublicKey) -> Vec<u8> {
+        let mut aad = public_key.algorithm().name().as_bytes().to_vec();
+        aad.extend_from_slice(public_key.as_bytes());
+        aad
+    }
+}
+
+/// Directory of encrypted software keys, one `<name>.json` file each.
+/// Keys are named like the owners they belong to, so a name can never
+/// reach outside the directory.
+#[derive(Debug, Clone)]
+pub struct Keystore {
+    dir: PathBuf,
+}
+
+impl Keystore {
+    pub fn open(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    fn entry_path(&self, name: &str) -> Result<PathBuf, SignerError> {
+        if !policy::is_owner_name(name) {
+            return Err(SignerError::InvalidKeyName(name.to_string()));
+        }
+        Ok(self.dir.join(format!("{}.json", name)))
+    }
+
+    fn read_entry(&self, name: &str) -> Result<KeystoreEntry, SignerError> {
+        let path = self.entry_path(name)?;
+        if !path.exists() {
+            return Err(SignerError::NoSuchKey(name.to_string()));
+        }
+        Ok(serde_json::from_slice(&fs::read(path)?)?)
+    }
+
+    /// Generate a key for `name`, seal it under `passphrase` and return the
+    /// public key. Refuses to overwrite an existing entry.
+    pub fn generate(
+        &self,
+        name: &str,
+        algorithm: Algorithm,
+        passphrase: &str,
+    ) -> Result<PublicKey, SignerError> {
+        if self.entry_path(name)?.exists() {
+            return Err(SignerError::KeyExists(name.to_string()));
+        }
+        let (public_key, secret_key) = crypto::keypair(algorithm);
+        let sealed = SealedBox::seal(
+            passphrase.as_bytes(),
+            secret_key.as_bytes(),
+            &KeystoreEntry::aad(&public_key),
+        )?;
+        self.write_entry(
+            name,
+            &KeystoreEntry {
+                public_key: public_key.clone(),
+                secret_key: ProtectedSecret::Passphrase(sealed),
+            },
+        )?;
+        Ok(public_key)
+    }
+
+    /// Generate a key for `name` in software and wrap it under the AES key
+    /// `wrapping_key` on `token`, so it can only be used while the token is
+    /// present and unlocked. Refuses to overwrite an existing entry.
+    #[cfg(feature = "pkcs11")]
+    pub fn generate_wrapped(
+        &self,
+        name: &str,
+        algorithm: Algorithm,
+        ctx: &Ctx,
+        pin: &str,
+        token: &TokenSelector,
+        wrapping_key: &KeySelector,
+    ) -> Result<PublicKey, SignerError> {
+        if self.entry_path(name)?.exists() {
+            return Err(SignerError::KeyExists(name.to_string()));
+        }
+        let (public_key, secret_key) = crypto::keypair(algorithm);
+        let slot = token.find_slot(ctx)?;
+        let wrapped = hsm::with_session(ctx, slot, pin, |session| {
+            hsm::wrap_secret(ctx, session, wrapping_key, secret_key.as_bytes())
+        })?;
+        self.write_entry(
+            name,
+            &KeystoreEntry {
+                public_key: public_key.clone(),
+                secret_key: ProtectedSecret::HsmWrapped(WrappedSecret {
+                    token: token.clone(),
+                    wrapping_key: wrapping_key.clone(),
+                    wrapped,
+                }),
+            },
+        )?;
+        Ok(public_key)
+    }
+
+    /// Write `entry` readable by the owner only. The entry is written to a
+    /// temporary file and hard-linked into place, which fails rather than
+    /// replace an entry that appeared meanwhile, so a crash never leaves a
+    /// partial key behind.
+    fn write_entry(&self, name: &str, entry: &KeystoreEntry) -> Result<(), SignerError> {
+        let path = self.entry_path(name)?;
+        fs::create_dir_all(&self.dir)?;
+        let mut temp = path.clone().into_os_string();
+        temp.push(format!(".tmp.{}", std::process::id()));
+        let temp = PathBuf::from(temp);
+        match fs::remove_file(&temp) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err.into()),
+            _ => {}
+        }
+        let linked = write_private(&temp, &serde_json::to_vec_pretty(entry)?)
+            .and_then(|()| fs::hard_link(&temp, &path));
+        let _ = fs::remove_file(&temp);
+        linked.map_err(|err| match err.kind() {
+            std::io::ErrorKind::AlreadyExists => SignerError::KeyExists(name.to_string()),
+            _ => err.into(),
+        })
+    }
+
+    pub fn public_key(&self, name: &str) -> Result<PublicKey, SignerError> {
+        Ok(self.read_entry(name)?.public_key)
+    }
+
+    pub fn protection(&self, name: &str) -> Result<Protection, SignerError> {
+        Ok(match self.read_entry(name)?.secret_key {
+            ProtectedSecret::Passphrase(_) => Protection::Passphrase,
+            ProtectedSecret::HsmWrapped(_) => Protection::HsmWrapped,
+        })
+    }
+
+    /// Names of all keys in the store, sorted.
+    pub fn names(&self) -> Result<Vec<String>, SignerError> {
+        if !self.dir.exists() {
+            return Ok(Vec::new());
+        }
+        let mut names = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "json") {
+                if let Some(stem) = path.file_stem().and_then(|stem| stem.to_str()) {
+                    names.push(stem.to_string());
+                }
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    /// Signer for `name`, unlocked with `unlock`, which must match how the
+    /// entry is protected.
+    pub fn signer<'a>(
+        &self,
+        name: &str,
+        unlock: Unlock<'a>,
+    ) -> Result<KeystoreSigner<'a>, SignerError> {
+        let entry = self.read_entry(name)?;
+        match (&entry.secret_key, &unlock) {
+            (ProtectedSecret::Passphrase(_), Unlock::Passphrase(_)) => {
+                Ok(KeystoreSigner { entry, unlock })
+            }
+            #[cfg(feature = "pkcs11")]
+            (ProtectedSecret::HsmWrapped(_), Unlock::Hsm { .. }) => {
+                Ok(KeystoreSigner { entry, unlock })
+            }
+            _ => Err(SignerError::WrongUnlock(name.to_string())),
+        }
+    }
+}
+
+/// Create `path` with mode 0600 and write `contents` to disk.
+fn write_private(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(path)?;
+    file.write_all(contents)?;
+    file.sync_all()
+}
+
+/// Signer backed by a keystore entry. The secret key is decrypted for each
+/// signature and wiped straight after.
+pub struct KeystoreSigner<'a> {
+    entry: KeystoreEntry,
+    unlock: Unlock<'a>,
+}
+
+impl KeystoreSigner<'_> {
+    fn secret_key(&self) -> Result<SecretKey, SignerError> {
+        let secret = match (&self.entry.secret_key, &self.unlock) {
+            (ProtectedSecret::Passphrase(sealed), Unlock::Passphrase(passphrase)) => sealed.open(
+                passphrase.as_bytes(),
+                &KeystoreEntry::aad(&self.entry.public_key),
+            )?,
+            #[cfg(feature = "pkcs11")]
+            (ProtectedSecret::HsmWrapped(wrapped), Unlock::Hsm { ctx, pin }) => {
+                let slot = wrapped.token.find_slot(ctx)?;
+                hsm::with_session(ctx, slot, pin, |session| {
+                    hsm::unwrap_secret(ctx, session, &wrapped.wrapping_key, &wrapped.wrapped)
+                })?
+            }
+            _ => unreachable!("checked in Keystore::signer"),
+        };
+        Ok(SecretKey::from_bytes(self.algorithm(), secret))
+    }
+}
+
+impl Signer for KeystoreSigner<'_> {
+    fn algorithm(&self) -> Algorithm {
+        self.entry.public_key.algorithm()
+    }
+
+    fn public_key(&self) -> Result<PublicKey, SignerError> {
+        Ok(self.entry.public_key.clone())
+    }
+
+    fn sign(&self, payload: &[u8]) -> Result<Signature, SignerError> {
+        Ok(crypto::sign(payload, &self.secret_key()?)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("qsms-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn key_names_stay_in_the_keystore() {
+        let dir = scratch("keystore-names");
+        let keystore = Keystore::open(dir.join("keys"));
+        for name in ["../escaped", "nested/key", "", "two words"] {
+            assert!(matches!(
+                keystore.generate(name, Algorithm::MlDsa44, "passphrase"),
+                Err(SignerError::InvalidKeyName(_))
+            ));
+            assert!(matches!(
+                keystore.public_key(name),
+                Err(SignerError::InvalidKeyName(_))
+            ));
+        }
+        assert!(!dir.exists());
+    }
+
+    #[test]
+    fn entries_are_private_and_never_replaced() {
+        let dir = scratch("keystore-entries");
+        let keystore = Keystore::open(&dir);
+        let public_key = keystore
+            .generate("alice", Algorithm::MlDsa44, "passphrase")
+            .unwrap();
+        assert!(matches!(
+            keystore.generate("alice", Algorithm::MlDsa44, "passphrase"),
+            Err(SignerError::KeyExists(_))
+        ));
+        assert_eq!(keystore.public_key("alice").unwrap(), public_key);
+        assert_eq!(keystore.names().unwrap(), ["alice"]);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(dir.join("alice.json"))
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        let signer = keystore
+            .signer("alice", Unlock::Passphrase("passphrase"))
+            .unwrap();
+        let signature = signer.sign(b"payload").unwrap();
+        crypto::verify(b"payload", &signature, &public_key).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
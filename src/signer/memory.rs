@@ -0,0 +1,37 @@
+use super::{Signer, SignerError};
+use crate::crypto::{self, Algorithm, PublicKey, SecretKey, Signature};
+
+/// Signer holding its key in process memory, for tests and throwaway wallets.
+#[derive(Debug)]
+pub struct MemorySigner {
+    public_key: PublicKey,
+    secret_key: SecretKey,
+}
+
+impl MemorySigner {
+    pub fn new(public_key: PublicKey, secret_key: SecretKey) -> Self {
+        Self {
+            public_key,
+            secret_key,
+        }
+    }
+
+    pub fn generate(algorithm: Algorithm) -> Self {
+        let (public_key, secret_key) = crypto::keypair(algorithm);
+        Self::new(public_key, secret_key)
+    }
+}
+
+impl Signer for MemorySigner {
+    fn algorithm(&self) -> Algorithm {
+        self.public_key.algorithm()
+    }
+
+    fn public_key(&self) -> Result<PublicKey, SignerError> {
+        Ok(self.public_key.clone())
+    }
+
+    fn sign(&self, payload: &[u8]) -> Result<Signature, SignerError> {
+        Ok(crypto::sign(payload, &self.secret_key)?)
+    }
+}
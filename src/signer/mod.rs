@@ -0,0 +1,55 @@
+//! Signing backends.
+//!
+//! The wallet only needs something that can report its public key and sign a
+//! payload; whether the private key lives in an HSM, an encrypted file or
+//! process memory is up to the [`Signer`] implementation.
+
+mod keystore;
+mod memory;
+#[cfg(feature = "pkcs11")]
+mod pkcs11;
+
+pub use self::keystore::{Keystore, KeystoreSigner, Protection, Unlock};
+pub use self::memory::MemorySigner;
+#[cfg(feature = "pkcs11")]
+pub use self::pkcs11::Pkcs11Signer;
+
+use crate::crypto::{Algorithm, MalformedSecretKey, PublicKey, Signature};
+use crate::hsm::HsmError;
+use crate::seal::SealError;
+
+pub trait Signer {
+    /// Scheme this signer produces signatures for.
+    fn algorithm(&self) -> Algorithm;
+
+    /// Public key matching the signer's private key.
+    fn public_key(&self) -> Result<PublicKey, SignerError>;
+
+    /// Sign `payload`, which the wallet has already domain-separated.
+    fn sign(&self, payload: &[u8]) -> Result<Signature, SignerError>;
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SignerError {
+    #[cfg(feature = "pkcs11")]
+    #[error("PKCS#11 error: {0}")]
+    Pkcs11(#[from] ::pkcs11::errors::Error),
+    #[error(transparent)]
+    Hsm(#[from] HsmError),
+    #[error("keystore I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("malformed keystore entry: {0}")]
+    Format(#[from] serde_json::Error),
+    #[error("{0:?} is not a valid key name: use letters, digits, '-', '_', '.' and '@'")]
+    InvalidKeyName(String),
+    #[error("no key named {0:?}")]
+    NoSuchKey(String),
+    #[error("a key named {0:?} already exists")]
+    KeyExists(String),
+    #[error("key {0:?} is not protected that way")]
+    WrongUnlock(String),
+    #[error(transparent)]
+    Seal(#[from] SealError),
+    #[error(transparent)]
+    SecretKey(#[from] MalformedSecretKey),
+}
@@ -0,0 +1,108 @@
+use super::{Signer, SignerError};
+use crate::crypto::{Algorithm, PublicKey, Signature};
+use crate::hsm::{self, HsmKeyRef, KeySelector, Mechanism};
+use pkcs11::types::{
+    CKA_EC_POINT, CKA_VALUE, CKO_PRIVATE_KEY, CKO_PUBLIC_KEY, CK_ATTRIBUTE, CK_ATTRIBUTE_TYPE,
+    CK_OBJECT_HANDLE, CK_SESSION_HANDLE, CK_SLOT_ID,
+};
+use pkcs11::Ctx;
+use zeroize::Zeroizing;
+
+/// Signer whose private key never leaves a PKCS#11 token.
+pub struct Pkcs11Signer<'a> {
+    ctx: &'a Ctx,
+    slot: CK_SLOT_ID,
+    pin: Zeroizing<String>,
+    algorithm: Algorithm,
+    key: KeySelector,
+    mechanism: Mechanism,
+}
+
+impl<'a> Pkcs11Signer<'a> {
+    /// Resolve the token `key_ref` points at. The key itself is looked up
+    /// again in each session, since object handles are session-scoped.
+    pub fn new(
+        ctx: &'a Ctx,
+        key_ref: &HsmKeyRef,
+        pin: &str,
+        algorithm: Algorithm,
+    ) -> Result<Self, SignerError> {
+        Ok(Self {
+            ctx,
+            slot: key_ref.token.find_slot(ctx)?,
+            pin: Zeroizing::new(pin.to_string()),
+            algorithm,
+            key: key_ref.key.clone(),
+            mechanism: key_ref.mechanism,
+        })
+    }
+
+    fn with_session<T>(
+        &self,
+        f: impl FnOnce(CK_SESSION_HANDLE) -> Result<T, SignerError>,
+    ) -> Result<T, SignerError> {
+        hsm::with_session(self.ctx, self.slot, &self.pin, f)
+    }
+
+    fn read_attribute(
+        &self,
+        session: CK_SESSION_HANDLE,
+        object: CK_OBJECT_HANDLE,
+        attribute: CK_ATTRIBUTE_TYPE,
+    ) -> Result<Vec<u8>, SignerError> {
+        // First call reports the length, second fills the buffer.
+        let mut template = vec![CK_ATTRIBUTE::new(attribute)];
+        self.ctx
+            .get_attribute_value(session, object, &mut template)?;
+        let value = vec![0u8; template[0].ulValueLen as usize];
+        template[0].set_bytes(&value);
+        self.ctx
+            .get_attribute_value(session, object, &mut template)?;
+        Ok(template[0].get_bytes()?)
+    }
+}
+
+/// `CKA_EC_POINT` should be a DER OCTET STRING around the raw point, but some
+/// tokens return the raw point. Unwrap the former, pass the latter through.
+fn unwrap_ec_point(bytes: Vec<u8>) -> Vec<u8> {
+    let (header, len) = match bytes.as_slice() {
+        [0x04, 0x81, len, ..] => (3, *len as usize),
+        [0x04, len, ..] if *len < 0x80 => (2, *len as usize),
+        _ => return bytes,
+    };
+    if bytes.len() == header + len {
+        bytes[header..].to_vec()
+    } else {
+        bytes
+    }
+}
+
+impl Signer for Pkcs11Signer<'_> {
+    fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    /// Read the public key from the token rather than trusting configuration.
+    fn public_key(&self) -> Result<PublicKey, SignerError> {
+        let bytes = self.with_session(|session| {
+            let object = self.key.find(self.ctx, session, CKO_PUBLIC_KEY)?;
+            if self.algorithm.is_post_quantum() {
+                self.read_attribute(session, object, CKA_VALUE)
+            } else {
+                self.read_attribute(session, object, CKA_EC_POINT)
+                    .map(unwrap_ec_point)
+            }
+        })?;
+        Ok(PublicKey::new(self.algorithm, bytes))
+    }
+
+    fn sign(&self, payload: &[u8]) -> Result<Signature, SignerError> {
+        let signed = self.with_session(|session| {
+            let object = self.key.find(self.ctx, session, CKO_PRIVATE_KEY)?;
+            self.ctx
+                .sign_init(session, &self.mechanism.to_ck(), object)?;
+            Ok(self.ctx.sign(session, payload)?)
+        })?;
+        Ok(Signature::new(self.algorithm, signed))
+    }
+}
@@ -0,0 +1,613 @@
+//! Reading and writing wallet files.
+//!
+//! A wallet file is replaced atomically: the new contents go to a temporary
+//! file in the same directory, which is flushed to disk and renamed over the
+//! old one, so a crash leaves either the old or the new wallet, never a mix.
+//! The previous versions are kept as `<file>.1` (newest) to `<file>.N`.
+//! An advisory lock on `<file>.lock` keeps two processes from interleaving
+//! their load-modify-save cycles.
+//!
+//! Named wallets live side by side in a [`WalletDir`] as `<name>.wallet`,
+//! or `<name>.json` for wallets created before the CBOR encoding existed.
+//!
+//! A store keeps a file's [`Encoding`] across saves; new files are CBOR.
+//!
+//! Wallet files may be encrypted at rest (see [`crate::encryption`]). A store
+//! keeps a file's encryption across saves and asks its [`Unlocker`] for
+//! passphrases or token access only when it reads or writes an encrypted file.
+//! A store that [requires encryption](WalletStore::require_encryption)
+//! refuses to read or write a plaintext file, so an encrypted wallet swapped
+//! for a plaintext one is not quietly accepted. The save that first encrypts
+//! a plaintext file deletes its backups instead of rotating them, so no
+//! readable copy is left beside it.
+
+use crate::encryption::{self, Encryption, Unlocker};
+use crate::error::WalletError;
+use crate::format::{self, Encoding, MigrationPlan};
+use crate::wallet::QuantumSafeWallet;
+use fs2::FileExt;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use zeroize::Zeroizing;
+
+/// Number of previous versions kept unless configured otherwise.
+pub const DEFAULT_BACKUPS: usize = 3;
+
+/// Environment variable overriding the default wallet directory.
+pub const WALLET_DIR_ENV: &str = "QSMS_WALLET_DIR";
+
+/// Environment variable that, set to a true value, refuses plaintext wallet
+/// files.
+pub const REQUIRE_ENCRYPTION_ENV: &str = "QSMS_REQUIRE_ENCRYPTION";
+
+/// Name of the wallet used when none is given.
+pub const DEFAULT_WALLET: &str = "default";
+
+/// Extension of wallet files.
+pub const WALLET_EXTENSION: &str = "wallet";
+
+/// Extension of wallet files written before [`WALLET_EXTENSION`].
+const LEGACY_EXTENSION: &str = "json";
+
+/// A directory of named wallets.
+#[derive(Clone, Debug)]
+pub struct WalletDir {
+    path: PathBuf,
+}
+
+impl WalletDir {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// `$QSMS_WALLET_DIR` if set, otherwise `quantum_safe_multisig/wallets`
+    /// under the platform data directory (`$XDG_DATA_HOME` or
+    /// `~/.local/share` on Linux).
+    pub fn default_location() -> Result<Self, WalletError> {
+        if let Some(dir) = std::env::var_os(WALLET_DIR_ENV).filter(|dir| !dir.is_empty()) {
+            return Ok(Self::new(dir));
+        }
+        dirs::data_dir()
+            .map(|data| Self::new(data.join("quantum_safe_multisig").join("wallets")))
+            .ok_or_else(|| {
+                WalletError::Usage(format!(
+                    "no data directory on this platform; set {}",
+                    WALLET_DIR_ENV
+                ))
+            })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// File holding the wallet called `name`: `<name>.wallet`, unless only
+    /// a legacy `<name>.json` exists. Names are single path components so a
+    /// wallet can never be written outside the directory.
+    pub fn wallet_path(&self, name: &str) -> Result<PathBuf, WalletError> {
+        let valid = !name.is_empty()
+            && !name.starts_with('.')
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+        if !valid {
+            return Err(WalletError::InvalidWalletName(name.to_string()));
+        }
+        let path = self.path.join(format!("{}.{}", name, WALLET_EXTENSION));
+        let legacy = path.with_extension(LEGACY_EXTENSION);
+        if !path.exists() && legacy.exists() {
+            return Ok(legacy);
+        }
+        Ok(path)
+    }
+
+    /// Lock the wallet called `name` for loading and saving.
+    pub fn open(&self, name: &str) -> Result<WalletStore, WalletError> {
+        WalletStore::open(self.wallet_path(name)?)
+    }
+
+    /// Names of the wallets in the directory, sorted.
+    pub fn names(&self) -> Result<Vec<String>, WalletError> {
+        if !self.path.exists() {
+            return Ok(Vec::new());
+        }
+        let mut names = Vec::new();
+        for entry in fs::read_dir(&self.path)? {
+            let path = entry?.path();
+            if path
+                .extension()
+                .is_some_and(|ext| ext == WALLET_EXTENSION || ext == LEGACY_EXTENSION)
+            {
+                if let Some(stem) = path.file_stem().and_then(|stem| stem.to_str()) {
+                    names.push(stem.to_string());
+                }
+            }
+        }
+        names.sort();
+        names.dedup();
+        Ok(names)
+    }
+}
+
+/// A wallet file, locked against other processes for as long as this value
+/// lives.
+pub struct WalletStore {
+    path: PathBuf,
+    backups: usize,
+    encoding: Encoding,
+    encryption: Option<Encryption>,
+    require_encryption: bool,
+    unlocker: Option<Box<dyn Unlocker>>,
+    _lock: File,
+}
+
+impl WalletStore {
+    /// Lock the wallet at `path`, which need not exist yet. Fails straight
+    /// away if another process holds the lock.
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self, WalletError> {
+        let path = path.into();
+        if let Some(dir) = parent_dir(&path) {
+            fs::create_dir_all(dir)?;
+        }
+        let lock = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(sibling(&path, ".lock"))?;
+        lock.try_lock_exclusive()
+            .map_err(|_| WalletError::Locked(path.clone()))?;
+        let (encoding, encryption) = match fs::read(&path) {
+            Ok(contents) => (Encoding::detect(&contents), encryption::detect(&contents)?),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => (Encoding::Cbor, None),
+            Err(err) => return Err(err.into()),
+        };
+        Ok(Self {
+            path,
+            backups: DEFAULT_BACKUPS,
+            encoding,
+            encryption,
+            require_encryption: false,
+            unlocker: None,
+            _lock: lock,
+        })
+    }
+
+    /// Where to get passphrases or token access for encrypted files.
+    pub fn with_unlocker(mut self, unlocker: Box<dyn Unlocker>) -> Self {
+        self.unlocker = Some(unlocker);
+        self
+    }
+
+    /// Refuse to load the file if it is not encrypted, and to save it
+    /// without encryption.
+    pub fn require_encryption(mut self) -> Self {
+        self.require_encryption = true;
+        self
+    }
+
+    /// Encrypt the file this way from the next save on, or store it in the
+    /// clear with `None`. Defaults to how the file is encrypted now.
+    pub fn set_encryption(&mut self, encryption: Option<Encryption>) {
+        self.encryption = encryption;
+    }
+
+    pub fn encryption(&self) -> Option<&Encryption> {
+        self.encryption.as_ref()
+    }
+
+    /// Write the file in `encoding` from the next save on. Defaults to the
+    /// file's current encoding.
+    pub fn set_encoding(&mut self, encoding: Encoding) {
+        self.encoding = encoding;
+    }
+
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    fn unlocker(&self) -> Result<&dyn Unlocker, WalletError> {
+        self.unlocker
+            .as_deref()
+            .ok_or_else(|| WalletError::Encrypted(self.path.clone()))
+    }
+
+    /// Keep `backups` previous versions on each save; zero keeps none.
+    pub fn with_backups(mut self, backups: usize) -> Self {
+        self.backups = backups;
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn exists(&self) -> bool {
+        self.path.exists()
+    }
+
+    /// The plaintext wallet document, decrypted if need be.
+    fn read(&self) -> Result<Zeroizing<Vec<u8>>, WalletError> {
+        let contents = fs::read(&self.path).map_err(|err| match err.kind() {
+            std::io::ErrorKind::NotFound => WalletError::NoSuchWallet(self.path.clone()),
+            _ => err.into(),
+        })?;
+        if encryption::detect(&contents)?.is_none() {
+            if self.require_encryption {
+                return Err(WalletError::NotEncrypted(self.path.clone()));
+            }
+            return Ok(Zeroizing::new(contents));
+        }
+        encryption::decrypt(&contents, self.unlocker()?)
+    }
+
+    /// Load the wallet, upgrading older formats in memory. The file itself
+    /// is only rewritten by the next [`WalletStore::save`].
+    pub fn load(&self) -> Result<QuantumSafeWallet, WalletError> {
+        format::decode(&self.read()?)
+    }
+
+    /// The migrations loading this wallet would apply.
+    pub fn migration_plan(&self) -> Result<MigrationPlan, WalletError> {
+        format::plan(&self.read()?)
+    }
+
+    /// Replace the file with `wallet`. Encrypting a file that is plaintext
+    /// on disk deletes its backups rather than keeping them.
+    pub fn save(&self, wallet: &QuantumSafeWallet) -> Result<(), WalletError> {
+        if self.require_encryption && self.encryption.is_none() {
+            return Err(WalletError::NotEncrypted(self.path.clone()));
+        }
+        let mut serialized = format::encode(wallet, self.encoding)?;
+        if let Some(encryption) = &self.encryption {
+            serialized =
+                encryption::encrypt(&serialized, encryption, self.encoding, self.unlocker()?)?;
+        }
+        let sealing = self.encryption.is_some() && self.is_plaintext()?;
+        // Nobody else writes while we hold the lock, so a temporary file
+        // already there was left by a crashed process with the same pid.
+        let temp = sibling(&self.path, &format!(".tmp.{}", std::process::id()));
+        match fs::remove_file(&temp) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err.into()),
+            _ => {}
+        }
+        let written = write_synced(&temp, &serialized)
+            .and_then(|()| {
+                if sealing {
+                    self.remove_backups()
+                } else {
+                    self.rotate_backups()
+                }
+            })
+            .and_then(|()| fs::rename(&temp, &self.path))
+            .and_then(|()| sync_dir(&self.path));
+        if written.is_err() {
+            let _ = fs::remove_file(&temp);
+        }
+        Ok(written?)
+    }
+
+    /// Whether the file exists and is not encrypted.
+    fn is_plaintext(&self) -> Result<bool, WalletError> {
+        match fs::read(&self.path) {
+            Ok(contents) => Ok(encryption::detect(&contents)?.is_none()),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Delete every `<file>.<n>` backup, however many are kept.
+    fn remove_backups(&self) -> std::io::Result<()> {
+        let Some(name) = self.path.file_name().and_then(|name| name.to_str()) else {
+            return Ok(());
+        };
+        let prefix = format!("{}.", name);
+        for entry in fs::read_dir(parent_dir(&self.path).unwrap_or(Path::new(".")))? {
+            let path = entry?.path();
+            let generation = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.strip_prefix(&prefix));
+            if generation.is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit())) {
+                fs::remove_file(&path)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Shift `<file>.1..N-1` up by one and copy the current file to `<file>.1`.
+    /// The current file stays in place until the rename that replaces it.
+    fn rotate_backups(&self) -> std::io::Result<()> {
+        if self.backups == 0 || !self.path.exists() {
+            return Ok(());
+        }
+        for generation in (1..self.backups).rev() {
+            let from = sibling(&self.path, &format!(".{}", generation));
+            if from.exists() {
+                fs::rename(&from, sibling(&self.path, &format!(".{}", generation + 1)))?;
+            }
+        }
+        let newest = sibling(&self.path, ".1");
+        fs::copy(&self.path, &newest)?;
+        File::open(&newest)?.sync_all()
+    }
+}
+
+/// `path` with `suffix` appended to its file name.
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+fn parent_dir(path: &Path) -> Option<&Path> {
+    path.parent().filter(|dir| !dir.as_os_str().is_empty())
+}
+
+fn write_synced(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let mut file = OpenOptions::new().write(true).create_new(true).open(path)?;
+    file.write_all(contents)?;
+    file.sync_all()
+}
+
+/// Make a rename in `path`'s directory durable. Directories cannot be opened
+/// for syncing on every platform, so this is best effort outside Unix.
+fn sync_dir(path: &Path) -> std::io::Result<()> {
+    if cfg!(unix) {
+        File::open(parent_dir(path).unwrap_or(Path::new(".")))?.sync_all()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::{Algorithm, OwnerKey};
+    use crate::payload::WalletId;
+    use crate::signer::{MemorySigner, Signer};
+    use std::collections::HashMap;
+
+    fn wallet() -> QuantumSafeWallet {
+        let key = MemorySigner::generate(Algorithm::MlDsa44)
+            .public_key()
+            .unwrap();
+        let owners = HashMap::from([("alice".to_string(), OwnerKey::single(key).unwrap())]);
+        QuantumSafeWallet::new("testnet", owners, 1).unwrap()
+    }
+
+    /// A fresh directory for one test's wallet files.
+    fn scratch(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("qsms-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    struct Passphrase(&'static str);
+
+    impl Unlocker for Passphrase {
+        fn passphrase(&self) -> Result<Zeroizing<String>, WalletError> {
+            Ok(Zeroizing::new(self.0.to_string()))
+        }
+
+        #[cfg(feature = "pkcs11")]
+        fn hsm(&self) -> Result<(&crate::hsm::Ctx, crate::pin::Pin), WalletError> {
+            Err(WalletError::Usage("no token in tests".to_string()))
+        }
+    }
+
+    fn backups(path: &Path) -> Vec<PathBuf> {
+        (1..=DEFAULT_BACKUPS + 1)
+            .map(|generation| sibling(path, &format!(".{}", generation)))
+            .filter(|backup| backup.exists())
+            .collect()
+    }
+
+    #[test]
+    fn encrypting_drops_plaintext_backups() {
+        let dir = scratch("encrypt-backups");
+        let path = dir.join("default.wallet");
+        let wallet = wallet();
+        let mut store = WalletStore::open(&path)
+            .unwrap()
+            .with_unlocker(Box::new(Passphrase("correct horse")));
+        for _ in 0..3 {
+            store.save(&wallet).unwrap();
+        }
+        assert_eq!(backups(&path).len(), 2);
+
+        store.set_encryption(Some(Encryption::Passphrase));
+        store.save(&wallet).unwrap();
+        assert!(backups(&path).is_empty());
+        store.save(&wallet).unwrap();
+        let kept = backups(&path);
+        assert_eq!(kept.len(), 1);
+        for file in kept.iter().chain([&path]) {
+            let contents = fs::read(file).unwrap();
+            assert!(encryption::detect(&contents).unwrap().is_some());
+        }
+        assert_eq!(store.load().unwrap().wallet_id(), wallet.wallet_id());
+        drop(store);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn unencrypted_files_can_be_refused() {
+        let dir = scratch("require-encryption");
+        let path = dir.join("default.wallet");
+        let wallet = wallet();
+
+        let strict = WalletStore::open(&path).unwrap().require_encryption();
+        assert!(matches!(
+            strict.save(&wallet),
+            Err(WalletError::NotEncrypted(_))
+        ));
+        assert!(!path.exists());
+        drop(strict);
+
+        let lax = WalletStore::open(&path).unwrap();
+        lax.save(&wallet).unwrap();
+        assert_eq!(lax.load().unwrap().wallet_id(), wallet.wallet_id());
+        drop(lax);
+
+        let strict = WalletStore::open(&path).unwrap().require_encryption();
+        assert!(matches!(strict.load(), Err(WalletError::NotEncrypted(_))));
+        assert!(matches!(
+            strict.migration_plan(),
+            Err(WalletError::NotEncrypted(_))
+        ));
+        drop(strict);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn saved_id(path: &Path) -> WalletId {
+        *format::decode(&fs::read(path).unwrap())
+            .unwrap()
+            .wallet_id()
+    }
+
+    #[test]
+    fn saves_replace_the_file_and_rotate_backups() {
+        let dir = scratch("rotate");
+        let path = dir.join("default.wallet");
+        let store = WalletStore::open(&path).unwrap();
+        let wallets: Vec<QuantumSafeWallet> = (0..5).map(|_| wallet()).collect();
+        for wallet in &wallets {
+            store.save(wallet).unwrap();
+        }
+        assert_eq!(&saved_id(&path), wallets[4].wallet_id());
+        assert_eq!(backups(&path).len(), DEFAULT_BACKUPS);
+        for generation in 1..=DEFAULT_BACKUPS {
+            assert_eq!(
+                &saved_id(&sibling(&path, &format!(".{}", generation))),
+                wallets[4 - generation].wallet_id()
+            );
+        }
+        let mut files: Vec<String> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        files.sort();
+        assert_eq!(
+            files,
+            [
+                "default.wallet",
+                "default.wallet.1",
+                "default.wallet.2",
+                "default.wallet.3",
+                "default.wallet.lock",
+            ]
+        );
+        drop(store);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn backups_can_be_turned_off() {
+        let dir = scratch("no-backups");
+        let path = dir.join("default.wallet");
+        let store = WalletStore::open(&path).unwrap().with_backups(0);
+        store.save(&wallet()).unwrap();
+        let last = wallet();
+        store.save(&last).unwrap();
+        assert!(backups(&path).is_empty());
+        assert_eq!(&saved_id(&path), last.wallet_id());
+        drop(store);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn stale_temporary_files_are_replaced() {
+        let dir = scratch("stale-temp");
+        let path = dir.join("default.wallet");
+        let store = WalletStore::open(&path).unwrap();
+        let temp = sibling(&path, &format!(".tmp.{}", std::process::id()));
+        fs::write(&temp, b"left by a crash").unwrap();
+        let wallet = wallet();
+        store.save(&wallet).unwrap();
+        assert!(!temp.exists());
+        assert_eq!(&saved_id(&path), wallet.wallet_id());
+        drop(store);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn a_wallet_is_opened_by_one_store_at_a_time() {
+        let dir = scratch("lock");
+        let path = dir.join("default.wallet");
+        let store = WalletStore::open(&path).unwrap();
+        assert!(matches!(
+            WalletStore::open(&path),
+            Err(WalletError::Locked(locked)) if locked == path
+        ));
+        drop(store);
+        WalletStore::open(&path).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn wallet_names_are_single_path_components() {
+        let dir = WalletDir::new(scratch("wallet-names"));
+        for name in [
+            "",
+            ".hidden",
+            "../escaped",
+            "nested/wallet",
+            "two words",
+            "café",
+        ] {
+            assert!(matches!(
+                dir.wallet_path(name),
+                Err(WalletError::InvalidWalletName(_))
+            ));
+        }
+        assert_eq!(
+            dir.wallet_path("team-a_1.b").unwrap(),
+            dir.path().join("team-a_1.b.wallet")
+        );
+    }
+
+    #[test]
+    fn legacy_json_files_are_found_until_replaced() {
+        let dir = WalletDir::new(scratch("legacy"));
+        assert_eq!(dir.names().unwrap(), Vec::<String>::new());
+        fs::create_dir_all(dir.path()).unwrap();
+        let legacy = dir.path().join("old.json");
+        fs::write(&legacy, format::encode(&wallet(), Encoding::Json).unwrap()).unwrap();
+        assert_eq!(dir.wallet_path("old").unwrap(), legacy);
+
+        let store = dir.open("old").unwrap();
+        assert_eq!(store.path(), legacy);
+        assert_eq!(store.encoding(), Encoding::Json);
+        store.load().unwrap();
+        drop(store);
+
+        let current = dir.path().join("old.wallet");
+        WalletStore::open(&current)
+            .unwrap()
+            .save(&wallet())
+            .unwrap();
+        assert_eq!(dir.wallet_path("old").unwrap(), current);
+        assert_eq!(
+            dir.wallet_path("new").unwrap(),
+            dir.path().join("new.wallet")
+        );
+        fs::remove_dir_all(dir.path()).unwrap();
+    }
+
+    #[test]
+    fn names_list_wallets_only() {
+        let dir = WalletDir::new(scratch("names"));
+        for name in ["treasury", "payroll"] {
+            dir.open(name).unwrap().save(&wallet()).unwrap();
+        }
+        let store = dir.open("payroll").unwrap();
+        store.save(&wallet()).unwrap();
+        drop(store);
+        fs::write(dir.path().join("archive.json"), b"{}").unwrap();
+        fs::write(dir.path().join("payroll.json"), b"{}").unwrap();
+        fs::write(dir.path().join("notes.txt"), b"").unwrap();
+        assert!(dir.path().join("payroll.wallet.1").exists());
+        assert_eq!(dir.names().unwrap(), ["archive", "payroll", "treasury"]);
+        fs::remove_dir_all(dir.path()).unwrap();
+    }
+}
@@ -0,0 +1,241 @@
+//! Transactions and their canonical binary encoding.
+//!
+//! Owners sign the encoding, not a free-form message, so two owners can never
+//! approve semantically identical but byte-different transactions. Version 1
+//! lays fields out in this order, integers big-endian:
+//!
+//! | field       | encoding                                          |
+//! |-------------|---------------------------------------------------|
+//! | version     | `u8`, always [`ENCODING_VERSION`]                  |
+//! | `recipient` | `u32` length, UTF-8 bytes                         |
+//! | `amount`    | `u64`                                             |
+//! | `asset`     | `u32` length, ASCII bytes                         |
+//! | `memo`      | `u32` length, UTF-8 bytes                         |
+//! | `expiry`    | `u8` flag (`0` absent, `1` present), then `u64`   |
+//! | `nonce`     | `u64`                                             |
+//!
+//! [`Transaction::decode`] accepts exactly the bytes [`Transaction::encode`]
+//! produces: unknown versions, trailing bytes, flags other than `0`/`1` and
+//! field values that fail [`Transaction::validate`] are all rejected.
+
+use serde::{Deserialize, Serialize};
+
+pub const ENCODING_VERSION: u8 = 1;
+
+pub const MAX_RECIPIENT_LEN: usize = 128;
+pub const MAX_ASSET_LEN: usize = 16;
+pub const MAX_MEMO_LEN: usize = 256;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Transaction {
+    pub recipient: String,
+    /// Amount in the asset's smallest unit.
+    pub amount: u64,
+    /// Asset ticker, uppercase ASCII letters and digits.
+    pub asset: String,
+    pub memo: String,
+    /// Unix time in seconds after which the transaction may no longer execute.
+    pub expiry: Option<u64>,
+    /// Wallet nonce; assigned by the wallet when the transaction is proposed.
+    pub nonce: u64,
+}
+
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+pub enum TransactionError {
+    #[error("unsupported transaction encoding version {0}")]
+    UnsupportedVersion(u8),
+    #[error("transaction encoding is truncated")]
+    Truncated,
+    #[error("{0} trailing bytes after transaction encoding")]
+    TrailingBytes(usize),
+    #[error("invalid presence flag {0:#04x} for expiry")]
+    InvalidFlag(u8),
+    #[error("{0} is not valid UTF-8")]
+    InvalidUtf8(&'static str),
+    #[error("{field} is longer than {max} bytes")]
+    FieldTooLong { field: &'static str, max: usize },
+    #[error("recipient is empty")]
+    EmptyRecipient,
+    #[error("asset {0:?} must be 1-16 uppercase ASCII letters or digits")]
+    InvalidAsset(String),
+}
+
+impl Transaction {
+    /// Check the field constraints that every encodable transaction meets.
+    pub fn validate(&self) -> Result<(), TransactionError> {
+        if self.recipient.is_empty() {
+            return Err(TransactionError::EmptyRecipient);
+        }
+        check_len("recipient", &self.recipient, MAX_RECIPIENT_LEN)?;
+        check_len("memo", &self.memo, MAX_MEMO_LEN)?;
+        let asset_ok = (1..=MAX_ASSET_LEN).contains(&self.asset.len())
+            && self
+                .asset
+                .bytes()
+                .all(|b| b.is_ascii_uppercase() || b.is_ascii_digit());
+        if !asset_ok {
+            return Err(TransactionError::InvalidAsset(self.asset.clone()));
+        }
+        Ok(())
+    }
+
+    /// Whether the expiry has passed by `now`.
+    pub fn has_expired(&self, now: u64) -> bool {
+        self.expiry.is_some_and(|expiry| now > expiry)
+    }
+
+    /// Canonical encoding of the transaction; see the module docs for the layout.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out =
+            Vec::with_capacity(self.recipient.len() + self.asset.len() + self.memo.len() + 42);
+        out.push(ENCODING_VERSION);
+        put_str(&mut out, &self.recipient);
+        out.extend_from_slice(&self.amount.to_be_bytes());
+        put_str(&mut out, &self.asset);
+        put_str(&mut out, &self.memo);
+        match self.expiry {
+            Some(expiry) => {
+                out.push(1);
+                out.extend_from_slice(&expiry.to_be_bytes());
+            }
+            None => out.push(0),
+        }
+        out.extend_from_slice(&self.nonce.to_be_bytes());
+        out
+    }
+
+    /// Decode a canonical encoding, rejecting anything `encode` would not produce.
+    pub fn decode(bytes: &[u8]) -> Result<Self, TransactionError> {
+        let mut reader = Reader { bytes };
+        let version = reader.u8()?;
+        if version != ENCODING_VERSION {
+            return Err(TransactionError::UnsupportedVersion(version));
+        }
+        let recipient = reader.str("recipient", MAX_RECIPIENT_LEN)?;
+        let amount = reader.u64()?;
+        let asset = reader.str("asset", MAX_ASSET_LEN)?;
+        let memo = reader.str("memo", MAX_MEMO_LEN)?;
+        let expiry = match reader.u8()? {
+            0 => None,
+            1 => Some(reader.u64()?),
+            flag => return Err(TransactionError::InvalidFlag(flag)),
+        };
+        let nonce = reader.u64()?;
+        if !reader.bytes.is_empty() {
+            return Err(TransactionError::TrailingBytes(reader.bytes.len()));
+        }
+        let transaction = Transaction {
+            recipient,
+            amount,
+            asset,
+            memo,
+            expiry,
+            nonce,
+        };
+        transaction.validate()?;
+        Ok(transaction)
+    }
+}
+
+fn check_len(field: &'static str, value: &str, max: usize) -> Result<(), TransactionError> {
+    if value.len() > max {
+        return Err(TransactionError::FieldTooLong { field, max });
+    }
+    Ok(())
+}
+
+fn put_str(out: &mut Vec<u8>, value: &str) {
+    out.extend_from_slice(&(value.len() as u32).to_be_bytes());
+    out.extend_from_slice(value.as_bytes());
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], TransactionError> {
+        if self.bytes.len() < len {
+            return Err(TransactionError::Truncated);
+        }
+        let (head, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> Result<u8, TransactionError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, TransactionError> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, TransactionError> {
+        Ok(u64::from_be_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    /// Read a length-prefixed string, checking the declared length against
+    /// `max` before touching the data.
+    fn str(&mut self, field: &'static str, max: usize) -> Result<String, TransactionError> {
+        let len = self.u32()? as usize;
+        if len > max {
+            return Err(TransactionError::FieldTooLong { field, max });
+        }
+        let data = self.take(len)?;
+        String::from_utf8(data.to_vec()).map_err(|_| TransactionError::InvalidUtf8(field))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Deserialize)]
+    struct Vectors {
+        valid: Vec<ValidVector>,
+        invalid: Vec<InvalidVector>,
+    }
+
+    #[derive(Deserialize)]
+    struct ValidVector {
+        name: String,
+        transaction: Transaction,
+        encoding: String,
+    }
+
+    #[derive(Deserialize)]
+    struct InvalidVector {
+        name: String,
+        encoding: String,
+        error: String,
+    }
+
+    fn vectors() -> Vectors {
+        serde_json::from_str(include_str!("../tests/vectors/transaction_v1.json")).unwrap()
+    }
+
+    #[test]
+    fn encodes_golden_vectors() {
+        for vector in vectors().valid {
+            assert_eq!(
+                hex::encode(vector.transaction.encode()),
+                vector.encoding,
+                "{}",
+                vector.name
+            );
+            let decoded = Transaction::decode(&hex::decode(&vector.encoding).unwrap());
+            assert_eq!(decoded.as_ref(), Ok(&vector.transaction), "{}", vector.name);
+        }
+    }
+
+    #[test]
+    fn rejects_non_canonical_vectors() {
+        for vector in vectors().invalid {
+            let bytes = hex::decode(&vector.encoding).unwrap();
+            let err = Transaction::decode(&bytes).unwrap_err();
+            assert_eq!(err.to_string(), vector.error, "{}", vector.name);
+        }
+    }
+}
@@ -0,0 +1,1441 @@
+//! The multi-signature wallet: its owners, approval threshold, transfer
+//! rules, execution delay and the proposals awaiting signatures.
+
+use crate::bundle::{self, PartialSignatures, SigningRequest};
+use crate::clock::{self, Clock};
+use crate::crypto::{self, Algorithm, OwnerKey, PublicKey, Signature};
+use crate::error::WalletError;
+use crate::governance::OwnerChange;
+use crate::hash::Hash256;
+use crate::hsm::HsmKeyRef;
+use crate::payload::{SigningPayload, WalletId};
+use crate::policy::{self, Approval, Group, Policy, Threshold};
+use crate::proposal::{Action, Proposal, ProposalId, TimeWindow};
+use crate::rules::{self, Denial, Spend, TransferRules, Window, LEDGER_RETENTION};
+use crate::signer::Signer;
+use crate::transaction::Transaction;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct QuantumSafeWallet {
+    wallet_id: WalletId,
+    chain_id: String,
+    nonce: u64,
+    owners: HashMap<String, OwnerKey>,
+    /// Voting weight of each owner.
+    weights: HashMap<String, usize>,
+    threshold: Threshold,
+    /// Approval rule that replaces the flat threshold, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    policy: Option<Policy>,
+    /// Approval bands and spending limits on transfers, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    rules: Option<TransferRules>,
+    /// Transfers executed within the longest cap window, oldest first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    ledger: Vec<Spend>,
+    /// Seconds an approved proposal waits before it may execute, during
+    /// which any owner may cancel it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    delay: Option<u64>,
+    /// Nonces of cancelled proposals, skipped once the wallet reaches them.
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    cancelled: BTreeSet<u64>,
+    proposals: HashMap<ProposalId, Proposal>,
+    /// Token and key that sign for each HSM-backed owner.
+    #[serde(default)]
+    hsm_keys: HashMap<String, HsmKeyRef>,
+    #[serde(skip, default = "clock::system")]
+    clock: Arc<dyn Clock>,
+}
+
+impl QuantumSafeWallet {
+    /// Create a wallet requiring `threshold` of `owners` to approve, which
+    /// must be at least one and no more than the number of owners.
+    pub fn new(
+        chain_id: &str,
+        owners: HashMap<String, OwnerKey>,
+        threshold: usize,
+    ) -> Result<Self, WalletError> {
+        Self::weighted(chain_id, owners, HashMap::new(), threshold)
+    }
+
+    /// Create a wallet where owners carry `weights`, approving once the
+    /// signers' weights add up to `threshold`. Owners missing from `weights`
+    /// have weight 1.
+    pub fn weighted(
+        chain_id: &str,
+        owners: HashMap<String, OwnerKey>,
+        mut weights: HashMap<String, usize>,
+        threshold: usize,
+    ) -> Result<Self, WalletError> {
+        for (owner, key) in &owners {
+            check_owner_name(owner)?;
+            check_owner_key(owner, key)?;
+        }
+        for (owner, weight) in &weights {
+            if !owners.contains_key(owner) {
+                return Err(WalletError::UnknownOwner(owner.clone()));
+            }
+            if *weight == 0 {
+                return Err(WalletError::InvalidWeight {
+                    owner: owner.clone(),
+                    reason: "must be at least 1",
+                });
+            }
+        }
+        for owner in owners.keys() {
+            weights.entry(owner.clone()).or_insert(1);
+        }
+        let threshold = Threshold::new(threshold, sum_weights(&weights)?)?;
+        let created_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_nanos())
+            .unwrap_or(0);
+        let mut owner_names: Vec<&str> = owners.keys().map(String::as_str).collect();
+        owner_names.sort_unstable();
+        let wallet_id = Hash256::of(
+            "wallet",
+            &[
+                chain_id.as_bytes(),
+                owner_names.join("\n").as_bytes(),
+                &(threshold.required() as u64).to_be_bytes(),
+                &created_at.to_be_bytes(),
+            ],
+        );
+        Ok(Self {
+            wallet_id,
+            chain_id: chain_id.to_string(),
+            nonce: 0,
+            owners,
+            weights,
+            threshold,
+            policy: None,
+            rules: None,
+            ledger: Vec::new(),
+            delay: None,
+            cancelled: BTreeSet::new(),
+            proposals: HashMap::new(),
+            hsm_keys: HashMap::new(),
+            clock: clock::system(),
+        })
+    }
+
+    pub fn wallet_id(&self) -> &WalletId {
+        &self.wallet_id
+    }
+
+    pub fn chain_id(&self) -> &str {
+        &self.chain_id
+    }
+
+    pub fn owners(&self) -> impl Iterator<Item = (&str, &OwnerKey)> {
+        self.owners.iter().map(|(name, key)| (name.as_str(), key))
+    }
+
+    pub fn threshold(&self) -> Threshold {
+        self.threshold
+    }
+
+    /// Start the wallet off with `policy` deciding approvals instead of the
+    /// flat threshold. Later changes go through [`OwnerChange::SetPolicy`]
+    /// proposals.
+    pub fn with_policy(mut self, policy: Policy) -> Result<Self, WalletError> {
+        policy.validate(&self.weights)?;
+        self.policy = Some(policy);
+        Ok(self)
+    }
+
+    /// The approval policy, if one replaces the flat threshold.
+    pub fn policy(&self) -> Option<&Policy> {
+        self.policy.as_ref()
+    }
+
+    /// Read the time from `clock` instead of the system clock.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Current Unix time in seconds by the wallet's clock.
+    pub fn now(&self) -> u64 {
+        self.clock.now()
+    }
+
+    /// Start the wallet off holding approved proposals for `seconds` before
+    /// they may execute. Later changes go through [`OwnerChange::SetDelay`]
+    /// proposals.
+    pub fn with_delay(mut self, seconds: u64) -> Self {
+        self.delay = (seconds > 0).then_some(seconds);
+        self
+    }
+
+    /// Seconds approved proposals wait before they may execute, if any.
+    pub fn delay(&self) -> Option<u64> {
+        self.delay
+    }
+
+    /// Start the wallet off enforcing `rules`. Later changes go through
+    /// [`OwnerChange::SetRules`] proposals.
+    pub fn with_rules(mut self, rules: TransferRules) -> Result<Self, WalletError> {
+        rules.validate(&self.weights)?;
+        self.rules = Some(rules);
+        Ok(self)
+    }
+
+    /// The transfer rules, if any.
+    pub fn rules(&self) -> Option<&TransferRules> {
+        self.rules.as_ref()
+    }
+
+    /// Recently executed transfers that count towards spend caps.
+    pub fn ledger(&self) -> &[Spend] {
+        &self.ledger
+    }
+
+    /// How much of `asset` executed transfers moved in the last `window`.
+    pub fn spent(&self, asset: &str, window: Window) -> u64 {
+        rules::spent(&self.ledger, asset, window, self.now())
+    }
+
+    /// Voting weight of `owner`.
+    pub fn weight(&self, owner: &str) -> Option<usize> {
+        self.weights.get(owner).copied()
+    }
+
+    /// Combined weight of every owner.
+    pub fn total_weight(&self) -> usize {
+        self.weights
+            .values()
+            .fold(0, |total, weight| total.saturating_add(*weight))
+    }
+
+    pub fn owner_key(&self, owner: &str) -> Option<&OwnerKey> {
+        self.owners.get(owner)
+    }
+
+    pub fn hsm_key(&self, owner: &str) -> Option<&HsmKeyRef> {
+        self.hsm_keys.get(owner)
+    }
+
+    /// Record which token and key sign for `owner`.
+    pub fn set_hsm_key(&mut self, owner: &str, key_ref: HsmKeyRef) -> Result<(), WalletError> {
+        if !self.owners.contains_key(owner) {
+            return Err(WalletError::UnknownOwner(owner.to_string()));
+        }
+        self.hsm_keys.insert(owner.to_string(), key_ref);
+        Ok(())
+    }
+
+    /// Nonce the next executed proposal must carry.
+    pub fn nonce(&self) -> u64 {
+        self.nonce
+    }
+
+    /// Open a proposal for `transaction` on behalf of `creator` and return its ID.
+    /// Only owners may propose, and the transaction must pass validation.
+    /// The wallet assigns the transaction's nonce: the next one after those
+    /// already pending, so proposals execute in the order they were made.
+    pub fn propose(
+        &mut self,
+        creator: &str,
+        transaction: Transaction,
+    ) -> Result<ProposalId, WalletError> {
+        self.propose_within(
+            creator,
+            Action::Transfer(transaction),
+            TimeWindow::default(),
+        )
+    }
+
+    /// Propose a change to the owners or threshold. It must be valid for the
+    /// wallet as it stands, and it takes effect only once executed with the
+    /// current threshold of approvals.
+    pub fn propose_change(
+        &mut self,
+        creator: &str,
+        change: OwnerChange,
+    ) -> Result<ProposalId, WalletError> {
+        self.propose_within(
+            creator,
+            Action::OwnerChange { change, nonce: 0 },
+            TimeWindow::default(),
+        )
+    }
+
+    /// Propose `action` to execute only within `window`, which must not
+    /// have closed yet. Owners sign the window along with the action.
+    pub fn propose_within(
+        &mut self,
+        creator: &str,
+        mut action: Action,
+        window: TimeWindow,
+    ) -> Result<ProposalId, WalletError> {
+        if !self.owners.contains_key(creator) {
+            return Err(WalletError::UnknownOwner(creator.to_string()));
+        }
+        action.validate()?;
+        if let Action::OwnerChange { change, .. } = &action {
+            self.check_change(change)?;
+        }
+        if let (Some(not_before), Some(expires_at)) = (window.not_before, window.expires_at) {
+            if not_before >= expires_at {
+                return Err(WalletError::InvalidTimeWindow("it closes before it opens"));
+            }
+        }
+        if window.has_expired(self.now()) {
+            return Err(WalletError::InvalidTimeWindow("it has already closed"));
+        }
+        if let Some(transaction) = action.transaction() {
+            if transaction.has_expired(self.now()) {
+                return Err(WalletError::Usage(
+                    "the transaction has already expired".to_string(),
+                ));
+            }
+        }
+        action.set_nonce(
+            self.proposals
+                .values()
+                .map(|proposal| proposal.nonce() + 1)
+                .chain(self.cancelled.iter().map(|nonce| nonce + 1))
+                .fold(self.nonce, u64::max),
+        );
+        let proposal = Proposal::new(creator, action, window, self.now());
+        let id = *proposal.id();
+        self.proposals.entry(id).or_insert(proposal);
+        Ok(id)
+    }
+
+    /// Check that `change` keeps the wallet valid: owners to add must be
+    /// new, owners to remove, rekey or reweigh must exist, weights must be
+    /// at least one, the threshold must stay between one and the total
+    /// owner weight, and the approval policy and transfer rules must stay
+    /// valid for the owners that remain.
+    fn check_change(&self, change: &OwnerChange) -> Result<(), WalletError> {
+        let mut weights = self.weights.clone();
+        let mut required = self.threshold.required();
+        let mut policy = self.policy.as_ref();
+        let mut rules = self.rules.as_ref();
+        let known = |owner: &String| {
+            if !self.owners.contains_key(owner) {
+                return Err(WalletError::UnknownOwner(owner.clone()));
+            }
+            Ok(())
+        };
+        match change {
+            OwnerChange::AddOwner { owner, key } => {
+                if self.owners.contains_key(owner) {
+                    return Err(WalletError::OwnerExists(owner.clone()));
+                }
+                check_owner_name(owner)?;
+                check_owner_key(owner, key)?;
+                weights.insert(owner.clone(), 1);
+            }
+            OwnerChange::RemoveOwner { owner } => {
+                known(owner)?;
+                weights.remove(owner);
+            }
+            OwnerChange::ReplaceKey { owner, key } => {
+                known(owner)?;
+                check_owner_key(owner, key)?;
+            }
+            OwnerChange::SetThreshold { required: new } => required = *new,
+            OwnerChange::SetWeight { owner, weight } => {
+                known(owner)?;
+                if *weight == 0 {
+                    return Err(WalletError::InvalidWeight {
+                        owner: owner.clone(),
+                        reason: "must be at least 1",
+                    });
+                }
+                weights.insert(owner.clone(), *weight);
+            }
+            OwnerChange::SetPolicy { policy: new } => policy = new.as_ref(),
+            OwnerChange::SetRules { rules: new } => rules = new.as_ref(),
+            OwnerChange::SetDelay { .. } => {}
+        }
+        Threshold::new(required, sum_weights(&weights)?)?;
+        if let Some(policy) = policy {
+            policy.validate(&weights)?;
+        }
+        if let Some(rules) = rules {
+            rules.validate(&weights)?;
+        }
+        Ok(())
+    }
+
+    /// Apply an executed owner change. Signatures of removed owners and of
+    /// replaced keys are dropped from every pending proposal; the rest are
+    /// judged against the new owners and threshold from now on.
+    fn apply_change(&mut self, change: OwnerChange) -> Result<(), WalletError> {
+        self.check_change(&change)?;
+        match change {
+            OwnerChange::AddOwner { owner, key } => {
+                self.weights.insert(owner.clone(), 1);
+                self.owners.insert(owner, key);
+            }
+            OwnerChange::RemoveOwner { owner } => {
+                self.owners.remove(&owner);
+                self.weights.remove(&owner);
+                self.forget_signer(&owner);
+            }
+            OwnerChange::ReplaceKey { owner, key } => {
+                self.owners.insert(owner.clone(), key);
+                self.forget_signer(&owner);
+            }
+            OwnerChange::SetThreshold { required } => {
+                self.threshold = Threshold::new(required, self.total_weight())?;
+            }
+            OwnerChange::SetWeight { owner, weight } => {
+                self.weights.insert(owner, weight);
+            }
+            OwnerChange::SetPolicy { policy } => {
+                self.policy = policy;
+            }
+            OwnerChange::SetRules { rules } => {
+                self.rules = rules;
+            }
+            OwnerChange::SetDelay { seconds } => {
+                self.delay = (seconds > 0).then_some(seconds);
+            }
+        }
+        Ok(())
+    }
+
+    /// Drop `owner`'s HSM key reference and pending signatures.
+    fn forget_signer(&mut self, owner: &str) {
+        self.hsm_keys.remove(owner);
+        for proposal in self.proposals.values_mut() {
+            proposal.remove_signatures(owner);
+        }
+    }
+
+    /// Take in a proposal exported from another copy of this wallet. Its ID
+    /// must match its contents and every signature must verify; signatures
+    /// on a proposal the wallet already has are merged into it.
+    pub fn import_proposal(&mut self, mut proposal: Proposal) -> Result<ProposalId, WalletError> {
+        let id = *proposal.id();
+        let computed = Proposal::compute_id(
+            proposal.creator(),
+            proposal.action(),
+            proposal.window(),
+            proposal.created_at(),
+        );
+        if computed != id {
+            return Err(WalletError::TamperedProposal(id));
+        }
+        if !self.owners.contains_key(proposal.creator()) {
+            return Err(WalletError::UnknownOwner(proposal.creator().to_string()));
+        }
+        proposal.action().validate()?;
+        if proposal.nonce() < self.nonce {
+            return Err(WalletError::OutOfOrder {
+                id,
+                expected: self.nonce,
+                actual: proposal.nonce(),
+            });
+        }
+        for (owner, signatures) in proposal.signatures() {
+            for signature in signatures {
+                self.check_signature(&proposal, owner, signature)?;
+            }
+        }
+        let signatures = proposal.take_signatures();
+        proposal.set_approved_at(None);
+        let stored = self.proposals.entry(id).or_insert(proposal);
+        for (owner, signatures) in signatures {
+            for signature in signatures {
+                stored.add_signature(&owner, signature);
+            }
+        }
+        self.refresh_approvals();
+        Ok(id)
+    }
+
+    pub fn proposal(&self, id: &ProposalId) -> Option<&Proposal> {
+        self.proposals.get(id)
+    }
+
+    pub fn proposals(&self) -> impl Iterator<Item = &Proposal> {
+        self.proposals.values()
+    }
+
+    /// Bytes owners sign for `proposal`: the canonical action encoding
+    /// bound to this wallet, its chain and the proposal's nonce.
+    pub fn signing_payload(&self, proposal: &Proposal) -> Vec<u8> {
+        SigningPayload {
+            chain_id: &self.chain_id,
+            wallet_id: &self.wallet_id,
+            nonce: proposal.nonce(),
+            message: &proposal.action().encode(),
+            window: proposal.window(),
+        }
+        .to_bytes()
+    }
+
+    /// A portable request to sign `proposal_id` on a machine without the
+    /// wallet file.
+    pub fn signing_request(&self, proposal_id: &ProposalId) -> Result<SigningRequest, WalletError> {
+        let proposal = self
+            .proposals
+            .get(proposal_id)
+            .ok_or(WalletError::UnknownProposal(*proposal_id))?;
+        let owners: BTreeMap<String, OwnerKey> = self
+            .owners
+            .iter()
+            .map(|(name, key)| (name.clone(), key.clone()))
+            .collect();
+        Ok(SigningRequest::new(
+            self.wallet_id,
+            &self.chain_id,
+            proposal,
+            owners,
+        ))
+    }
+
+    /// Add signatures made offline from a [`SigningRequest`] to the pending
+    /// proposal they are for. The wallet ID, nonce and payload hash must
+    /// match this wallet's proposal and every signature must verify before
+    /// any is stored. Returns the proposal's ID.
+    pub fn combine(&mut self, partial: &PartialSignatures) -> Result<ProposalId, WalletError> {
+        let id = *partial.proposal_id();
+        if partial.wallet_id() != &self.wallet_id {
+            return Err(WalletError::BundleMismatch {
+                field: "wallet ID",
+                expected: self.wallet_id.to_string(),
+                found: partial.wallet_id().to_string(),
+            });
+        }
+        let proposal = self
+            .proposals
+            .get(&id)
+            .ok_or(WalletError::UnknownProposal(id))?;
+        if partial.nonce() != proposal.nonce() {
+            return Err(WalletError::BundleMismatch {
+                field: "nonce",
+                expected: proposal.nonce().to_string(),
+                found: partial.nonce().to_string(),
+            });
+        }
+        if proposal.nonce() < self.nonce {
+            return Err(WalletError::OutOfOrder {
+                id,
+                expected: self.nonce,
+                actual: proposal.nonce(),
+            });
+        }
+        let expected = bundle::payload_hash(&self.signing_payload(proposal));
+        if partial.payload_hash() != &expected {
+            return Err(WalletError::BundleMismatch {
+                field: "payload hash",
+                expected: expected.to_string(),
+                found: partial.payload_hash().to_string(),
+            });
+        }
+        for signature in partial.signatures() {
+            self.check_signature(proposal, partial.owner(), signature)?;
+        }
+        if let Some(proposal) = self.proposals.get_mut(&id) {
+            for signature in partial.signatures() {
+                proposal.add_signature(partial.owner(), signature.clone());
+            }
+        }
+        self.refresh_approvals();
+        Ok(id)
+    }
+
+    /// Registered key component of `owner` for `algorithm`.
+    fn owner_component(
+        &self,
+        owner: &str,
+        algorithm: Algorithm,
+    ) -> Result<&PublicKey, WalletError> {
+        let owner_key = self
+            .owners
+            .get(owner)
+            .ok_or_else(|| WalletError::UnknownOwner(owner.to_string()))?;
+        owner_key
+            .components()
+            .into_iter()
+            .find(|key| key.algorithm() == algorithm)
+            .ok_or_else(|| WalletError::AlgorithmMismatch {
+                owner: owner.to_string(),
+                algorithm,
+            })
+    }
+
+    /// Record a signature produced outside the wallet, such as the classical
+    /// half of a composite owner key. The signature is checked against the
+    /// owner's registered key before it is stored.
+    pub fn add_signature(
+        &mut self,
+        proposal_id: &ProposalId,
+        owner: &str,
+        signature: Signature,
+    ) -> Result<(), WalletError> {
+        let proposal = self
+            .proposals
+            .get(proposal_id)
+            .ok_or(WalletError::UnknownProposal(*proposal_id))?;
+        self.check_signature(proposal, owner, &signature)?;
+        if let Some(proposal) = self.proposals.get_mut(proposal_id) {
+            proposal.add_signature(owner, signature);
+        }
+        self.refresh_approvals();
+        Ok(())
+    }
+
+    /// Verify `owner`'s `signature` on `proposal` against their registered key.
+    fn check_signature(
+        &self,
+        proposal: &Proposal,
+        owner: &str,
+        signature: &Signature,
+    ) -> Result<(), WalletError> {
+        let key = self.owner_component(owner, signature.algorithm())?;
+        crypto::verify(&self.signing_payload(proposal), signature, key).map_err(|source| {
+            WalletError::BadSignature {
+                owner: owner.to_string(),
+                source,
+            }
+        })
+    }
+
+    /// Sign a proposal on behalf of `owner` with any signing backend. The
+    /// signer's algorithm selects which component of a composite owner key it
+    /// provides. The signer's public key must match the registered one before
+    /// anything is signed, and the signature is verified before it is stored.
+    pub fn sign_transaction(
+        &mut self,
+        proposal_id: &ProposalId,
+        owner: &str,
+        signer: &dyn Signer,
+    ) -> Result<(), WalletError> {
+        let proposal = self
+            .proposals
+            .get(proposal_id)
+            .ok_or(WalletError::UnknownProposal(*proposal_id))?;
+        let expected = self
+            .owner_component(owner, signer.algorithm())?
+            .fingerprint();
+        let actual = signer.public_key()?.fingerprint();
+        if actual != expected {
+            return Err(WalletError::KeyMismatch {
+                owner: owner.to_string(),
+                expected,
+                actual,
+            });
+        }
+        let signature = signer.sign(&self.signing_payload(proposal))?;
+        self.add_signature(proposal_id, owner, signature)
+    }
+
+    /// Owners whose signatures on a proposal are all present and valid,
+    /// sorted by name. Proposals whose nonce has already been consumed have
+    /// no approvals.
+    pub fn approvals(&self, proposal_id: &ProposalId) -> Result<Vec<&str>, WalletError> {
+        let proposal = self
+            .proposals
+            .get(proposal_id)
+            .ok_or(WalletError::UnknownProposal(*proposal_id))?;
+        if proposal.nonce() < self.nonce {
+            return Ok(Vec::new());
+        }
+        let payload = self.signing_payload(proposal);
+        let mut approved: Vec<&str> = proposal
+            .signatures()
+            .iter()
+            .filter(|(owner, sigs)| {
+                self.owners
+                    .get(*owner)
+                    .is_some_and(|owner_key| owner_key.verify(&payload, sigs).is_ok())
+            })
+            .map(|(owner, _)| owner.as_str())
+            .collect();
+        approved.sort_unstable();
+        Ok(approved)
+    }
+
+    /// Who has and has not approved a proposal, their combined weight,
+    /// whether that is enough, which further owners could make it so, and
+    /// whether the transfer rules deny it.
+    pub fn approval(&self, proposal_id: &ProposalId) -> Result<Approval, WalletError> {
+        let proposal = self
+            .proposals
+            .get(proposal_id)
+            .ok_or(WalletError::UnknownProposal(*proposal_id))?;
+        let signers = self.approvals(proposal_id)?;
+        let weight = self.weight_of(&signers);
+        let rule = self.rule(proposal.action());
+        let denial = self.denial(proposal.action()).err();
+        let mut missing: Vec<String> = self
+            .owners
+            .keys()
+            .filter(|owner| !signers.contains(&owner.as_str()))
+            .cloned()
+            .collect();
+        missing.sort_unstable();
+        let by_threshold = self.policy.is_none()
+            && self
+                .rules
+                .as_ref()
+                .and_then(|rules| rules.approval_for(proposal.action()))
+                .is_none();
+        Ok(Approval {
+            approved: rule.is_met(&signers, &self.weights),
+            denial: denial.map(|denial| denial.to_string()),
+            completions: rule.completions(&signers, &self.weights),
+            signers: signers.into_iter().map(str::to_string).collect(),
+            missing,
+            weight,
+            required: by_threshold.then(|| self.threshold.required()),
+            executable_at: self.executable_at(proposal),
+            expires_at: proposal.expires_at(),
+        })
+    }
+
+    /// Verify a proposal by checking if it could execute now; see
+    /// [`check_executable`](Self::check_executable). Errors other than the
+    /// proposal not being executable yet, or any more, are returned.
+    pub fn verify_transaction(&self, proposal_id: &ProposalId) -> Result<bool, WalletError> {
+        match self.check_executable(proposal_id) {
+            Ok(()) => Ok(true),
+            Err(
+                WalletError::Denied { .. }
+                | WalletError::NotApproved(_)
+                | WalletError::TooEarly { .. }
+                | WalletError::Expired { .. }
+                | WalletError::OutOfOrder { .. },
+            ) => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Check everything [`execute_transaction`](Self::execute_transaction)
+    /// needs before it touches the wallet: the transfer rules allow the
+    /// proposal, the owners with valid signatures meet its approval rule,
+    /// its time window and the delay let it execute now, it is next in
+    /// nonce order, and an owner change is still valid. The error says
+    /// which check failed first, in that order.
+    pub fn check_executable(&self, proposal_id: &ProposalId) -> Result<(), WalletError> {
+        let proposal = self
+            .proposals
+            .get(proposal_id)
+            .ok_or(WalletError::UnknownProposal(*proposal_id))?;
+        self.denial(proposal.action())
+            .map_err(|reason| WalletError::Denied {
+                id: *proposal_id,
+                reason,
+            })?;
+        let signers = self.approvals(proposal_id)?;
+        if !self.rule(proposal.action()).is_met(&signers, &self.weights) {
+            return Err(WalletError::NotApproved(*proposal_id));
+        }
+        self.check_time(proposal)?;
+        if proposal.nonce() != self.nonce {
+            return Err(WalletError::OutOfOrder {
+                id: *proposal_id,
+                expected: self.nonce,
+                actual: proposal.nonce(),
+            });
+        }
+        if let Action::OwnerChange { change, .. } = proposal.action() {
+            self.check_change(change)?;
+        }
+        Ok(())
+    }
+
+    /// Earliest time `proposal` may execute as far as is known now; see
+    /// [`Approval::executable_at`].
+    fn executable_at(&self, proposal: &Proposal) -> Option<u64> {
+        let delayed = self
+            .delay
+            .zip(proposal.approved_at())
+            .map(|(delay, approved_at)| approved_at.saturating_add(delay));
+        proposal.window().not_before.max(delayed)
+    }
+
+    /// Check that `proposal` has not expired and that neither its
+    /// not-before time nor the execution delay holds it back.
+    fn check_time(&self, proposal: &Proposal) -> Result<(), WalletError> {
+        let id = *proposal.id();
+        let now = self.now();
+        if let Some(at) = proposal.expires_at().filter(|&at| now >= at) {
+            return Err(WalletError::Expired { id, at });
+        }
+        let at = match (self.delay, proposal.approved_at()) {
+            (Some(delay), None) => now.saturating_add(delay),
+            _ => self.executable_at(proposal).unwrap_or(now),
+        };
+        if now < at {
+            return Err(WalletError::TooEarly { id, at });
+        }
+        Ok(())
+    }
+
+    /// Start the execution delay of proposals whose signatures now meet
+    /// their approval rule, and reset it for those that no longer do.
+    fn refresh_approvals(&mut self) {
+        let now = self.now();
+        let met: Vec<(ProposalId, bool)> = self
+            .proposals
+            .values()
+            .map(|proposal| {
+                let met = self.approvals(proposal.id()).is_ok_and(|signers| {
+                    self.rule(proposal.action()).is_met(&signers, &self.weights)
+                });
+                (*proposal.id(), met)
+            })
+            .collect();
+        for (id, met) in met {
+            if let Some(proposal) = self.proposals.get_mut(&id) {
+                match (met, proposal.approved_at()) {
+                    (true, None) => proposal.set_approved_at(Some(now)),
+                    (false, Some(_)) => proposal.set_approved_at(None),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    /// The policy `action` needs: its transfer rule band or the rule for
+    /// owner changes, else the approval policy, else the flat threshold as
+    /// a policy over every owner.
+    fn rule(&self, action: &Action) -> Policy {
+        self.rules
+            .as_ref()
+            .and_then(|rules| rules.approval_for(action))
+            .or(self.policy.as_ref())
+            .cloned()
+            .unwrap_or(Policy::Threshold {
+                required: self.threshold.required(),
+                group: Group::Everyone,
+            })
+    }
+
+    /// Check a transfer against the allow-list, limits and spend caps as of
+    /// now. Owner changes are never denied.
+    fn denial(&self, action: &Action) -> Result<(), Denial> {
+        match (&self.rules, action.transaction()) {
+            (Some(rules), Some(transaction)) => rules.check(transaction, &self.ledger, self.now()),
+            _ => Ok(()),
+        }
+    }
+
+    fn weight_of(&self, owners: &[&str]) -> usize {
+        owners
+            .iter()
+            .filter_map(|owner| self.weights.get(*owner))
+            .sum()
+    }
+
+    /// Execute an approved proposal, consuming the wallet nonce so its
+    /// signatures can never be used again. It must be within its time
+    /// window and past the execution delay. Owner changes are applied to
+    /// the wallet, and must still be valid for it. Returns the executed
+    /// proposal.
+    pub fn execute_transaction(
+        &mut self,
+        proposal_id: &ProposalId,
+    ) -> Result<Proposal, WalletError> {
+        self.check_executable(proposal_id)?;
+        self.advance_nonce();
+        let proposal = self
+            .proposals
+            .remove(proposal_id)
+            .ok_or(WalletError::UnknownProposal(*proposal_id))?;
+        match proposal.action() {
+            Action::OwnerChange { change, .. } => self.apply_change(change.clone())?,
+            Action::Transfer(transaction) => self.record_spend(transaction),
+        }
+        self.refresh_approvals();
+        Ok(proposal)
+    }
+
+    /// Withdraw a pending proposal on behalf of `owner`. With an execution
+    /// delay, an approved proposal can be cancelled until the delay runs
+    /// out; after that only if it has expired or the transfer rules deny
+    /// it. The proposal's nonce is skipped so later proposals can still
+    /// execute. Returns the cancelled proposal.
+    pub fn cancel(
+        &mut self,
+        proposal_id: &ProposalId,
+        owner: &str,
+    ) -> Result<Proposal, WalletError> {
+        if !self.owners.contains_key(owner) {
+            return Err(WalletError::UnknownOwner(owner.to_string()));
+        }
+        let proposal = self
+            .proposals
+            .get(proposal_id)
+            .ok_or(WalletError::UnknownProposal(*proposal_id))?;
+        let now = self.now();
+        if let (Some(delay), Some(approved_at)) = (self.delay, proposal.approved_at()) {
+            let at = approved_at.saturating_add(delay);
+            if now >= at && !proposal.has_expired(now) && self.denial(proposal.action()).is_ok() {
+                return Err(WalletError::CancelWindowClosed {
+                    id: *proposal_id,
+                    at,
+                });
+            }
+        }
+        let proposal = self
+            .proposals
+            .remove(proposal_id)
+            .ok_or(WalletError::UnknownProposal(*proposal_id))?;
+        let nonce = proposal.nonce();
+        let taken = self.proposals.values().any(|other| other.nonce() == nonce);
+        if nonce == self.nonce && !taken {
+            self.advance_nonce();
+        } else if nonce > self.nonce && !taken {
+            self.cancelled.insert(nonce);
+        }
+        Ok(proposal)
+    }
+
+    /// Move to the next nonce, past any whose proposals were cancelled.
+    fn advance_nonce(&mut self) {
+        self.nonce += 1;
+        while self.cancelled.remove(&self.nonce) {
+            self.nonce += 1;
+        }
+    }
+
+    /// Add an executed transfer to the ledger, dropping spends no cap can
+    /// see any more.
+    fn record_spend(&mut self, transaction: &Transaction) {
+        let at = self.now();
+        let since = at.saturating_sub(LEDGER_RETENTION);
+        self.ledger.retain(|spend| spend.at > since);
+        self.ledger.push(Spend {
+            asset: transaction.asset.clone(),
+            amount: transaction.amount,
+            at,
+        });
+    }
+}
+
+/// Total of `weights`, refusing the weight that would overflow it.
+fn sum_weights(weights: &HashMap<String, usize>) -> Result<usize, WalletError> {
+    weights.iter().try_fold(0usize, |total, (owner, weight)| {
+        total
+            .checked_add(*weight)
+            .ok_or_else(|| WalletError::InvalidWeight {
+                owner: owner.clone(),
+                reason: "takes the total owner weight out of range",
+            })
+    })
+}
+
+/// Refuse owner names that approval policies could not refer to.
+fn check_owner_name(owner: &str) -> Result<(), WalletError> {
+    if !policy::is_owner_name(owner) {
+        return Err(WalletError::InvalidOwnerName(owner.to_string()));
+    }
+    Ok(())
+}
+
+/// Refuse `key` for `owner` unless it is post-quantum or a composite with a
+/// post-quantum half.
+fn check_owner_key(owner: &str, key: &OwnerKey) -> Result<(), WalletError> {
+    key.validate()
+        .map_err(|source| WalletError::InvalidOwnerKey {
+            owner: owner.to_string(),
+            source,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::format;
+    use crate::signer::{MemorySigner, SignerError};
+
+    const START: u64 = 1_700_000_000;
+    const HOUR: u64 = 60 * 60;
+
+    fn owners(signers: &[MemorySigner]) -> HashMap<String, OwnerKey> {
+        ["alice", "bob"]
+            .iter()
+            .zip(signers)
+            .map(|(name, signer)| {
+                let key = OwnerKey::single(signer.public_key().unwrap()).unwrap();
+                (name.to_string(), key)
+            })
+            .collect()
+    }
+
+    fn wallet(delay: u64) -> (QuantumSafeWallet, Arc<ManualClock>, Vec<MemorySigner>) {
+        let signers: Vec<MemorySigner> = (0..2)
+            .map(|_| MemorySigner::generate(Algorithm::MlDsa44))
+            .collect();
+        let clock = Arc::new(ManualClock::new(START));
+        let wallet = QuantumSafeWallet::new("testnet", owners(&signers), 2)
+            .unwrap()
+            .with_clock(clock.clone())
+            .with_delay(delay);
+        (wallet, clock, signers)
+    }
+
+    fn transfer(amount: u64) -> Action {
+        Action::Transfer(Transaction {
+            recipient: "qsc1recipient".to_string(),
+            amount,
+            asset: "QSC".to_string(),
+            memo: String::new(),
+            expiry: None,
+            nonce: 0,
+        })
+    }
+
+    fn approve(wallet: &mut QuantumSafeWallet, id: &ProposalId, signers: &[MemorySigner]) {
+        for (owner, signer) in ["alice", "bob"].iter().zip(signers) {
+            wallet.sign_transaction(id, owner, signer).unwrap();
+        }
+    }
+
+    #[test]
+    fn executed_proposals_cannot_be_replayed() {
+        let (mut wallet, _, signers) = wallet(0);
+        let id = wallet
+            .propose_within("alice", transfer(5), TimeWindow::default())
+            .unwrap();
+        approve(&mut wallet, &id, &signers);
+        let executed = wallet.execute_transaction(&id).unwrap();
+        assert_eq!(wallet.nonce(), 1);
+        assert!(matches!(
+            wallet.execute_transaction(&id),
+            Err(WalletError::UnknownProposal(_))
+        ));
+        assert!(matches!(
+            wallet.import_proposal(executed),
+            Err(WalletError::OutOfOrder {
+                expected: 1,
+                actual: 0,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn imported_proposals_must_match_their_id() {
+        let (mut wallet, _, signers) = wallet(0);
+        let id = wallet
+            .propose_within("alice", transfer(5), TimeWindow::default())
+            .unwrap();
+        wallet.sign_transaction(&id, "alice", &signers[0]).unwrap();
+        let exported =
+            format::encode_proposal(wallet.proposal(&id).unwrap(), format::Encoding::Json).unwrap();
+        let copy = format::decode_proposal(&exported).unwrap();
+        assert_eq!(wallet.import_proposal(copy).unwrap(), id);
+        assert_eq!(wallet.approvals(&id).unwrap(), ["alice"]);
+
+        let mut edited: serde_json::Value = serde_json::from_slice(&exported).unwrap();
+        edited["proposal"]["action"]["amount"] = serde_json::json!(5_000);
+        let edited = format::decode_proposal(&serde_json::to_vec(&edited).unwrap()).unwrap();
+        assert_eq!(edited.id(), &id);
+        assert!(matches!(
+            wallet.import_proposal(edited),
+            Err(WalletError::TamperedProposal(tampered)) if tampered == id
+        ));
+        assert_eq!(wallet.proposals().count(), 1);
+    }
+
+    #[test]
+    fn signatures_are_bound_to_their_wallet() {
+        let (mut wallet, _, signers) = wallet(0);
+        let mut other = QuantumSafeWallet::new("testnet", owners(&signers), 1)
+            .unwrap()
+            .with_clock(Arc::new(ManualClock::new(START)));
+        assert_ne!(wallet.wallet_id(), other.wallet_id());
+        let id = wallet
+            .propose_within("alice", transfer(5), TimeWindow::default())
+            .unwrap();
+        approve(&mut wallet, &id, &signers);
+        let exported =
+            format::encode_proposal(wallet.proposal(&id).unwrap(), format::Encoding::Json).unwrap();
+        assert!(matches!(
+            other.import_proposal(format::decode_proposal(&exported).unwrap()),
+            Err(WalletError::BadSignature { .. })
+        ));
+    }
+
+    #[test]
+    fn signers_must_match_the_owner_algorithm() {
+        let (mut wallet, _, _) = wallet(0);
+        let id = wallet
+            .propose_within("alice", transfer(5), TimeWindow::default())
+            .unwrap();
+        let falcon = MemorySigner::generate(Algorithm::Falcon512);
+        assert!(matches!(
+            wallet.sign_transaction(&id, "alice", &falcon),
+            Err(WalletError::AlgorithmMismatch {
+                algorithm: Algorithm::Falcon512,
+                ..
+            })
+        ));
+        let stranger = MemorySigner::generate(Algorithm::MlDsa44);
+        assert!(matches!(
+            wallet.sign_transaction(&id, "alice", &stranger),
+            Err(WalletError::KeyMismatch { .. })
+        ));
+        assert!(wallet.approval(&id).unwrap().signers.is_empty());
+    }
+
+    /// A signer that reports one key but signs with another, like a token
+    /// holding the wrong key under the right label.
+    struct Impostor {
+        claimed: MemorySigner,
+        actual: MemorySigner,
+    }
+
+    impl Signer for Impostor {
+        fn algorithm(&self) -> Algorithm {
+            self.claimed.algorithm()
+        }
+
+        fn public_key(&self) -> Result<PublicKey, SignerError> {
+            self.claimed.public_key()
+        }
+
+        fn sign(&self, payload: &[u8]) -> Result<Signature, SignerError> {
+            self.actual.sign(payload)
+        }
+    }
+
+    #[test]
+    fn signatures_are_checked_against_the_owner_key() {
+        let (mut wallet, _, mut signers) = wallet(0);
+        let id = wallet
+            .propose_within("alice", transfer(5), TimeWindow::default())
+            .unwrap();
+        let stranger = MemorySigner::generate(Algorithm::MlDsa44);
+        let alice = signers[0].public_key().unwrap().fingerprint();
+        assert!(matches!(
+            wallet.sign_transaction(&id, "alice", &stranger),
+            Err(WalletError::KeyMismatch { owner, expected, actual })
+                if owner == "alice"
+                    && expected == alice
+                    && actual == stranger.public_key().unwrap().fingerprint()
+        ));
+
+        let impostor = Impostor {
+            claimed: signers.remove(0),
+            actual: stranger,
+        };
+        assert!(matches!(
+            wallet.sign_transaction(&id, "alice", &impostor),
+            Err(WalletError::BadSignature { owner, .. }) if owner == "alice"
+        ));
+        let forged = impostor
+            .sign(&wallet.signing_payload(wallet.proposal(&id).unwrap()))
+            .unwrap();
+        assert!(matches!(
+            wallet.add_signature(&id, "alice", forged),
+            Err(WalletError::BadSignature { .. })
+        ));
+        assert!(wallet.proposal(&id).unwrap().signatures().is_empty());
+    }
+
+    #[test]
+    fn delay_runs_from_approval() {
+        let (mut wallet, clock, signers) = wallet(HOUR);
+        let id = wallet
+            .propose_within("alice", transfer(5), TimeWindow::default())
+            .unwrap();
+        clock.advance(10 * HOUR);
+        approve(&mut wallet, &id, &signers);
+        assert_eq!(
+            wallet.approval(&id).unwrap().executable_at,
+            Some(START + 11 * HOUR)
+        );
+        clock.advance(HOUR - 1);
+        assert!(!wallet.verify_transaction(&id).unwrap());
+        assert!(matches!(
+            wallet.execute_transaction(&id),
+            Err(WalletError::TooEarly { at, .. }) if at == START + 11 * HOUR
+        ));
+        clock.advance(1);
+        assert!(wallet.verify_transaction(&id).unwrap());
+        wallet.execute_transaction(&id).unwrap();
+        assert_eq!(wallet.nonce(), 1);
+    }
+
+    #[test]
+    fn window_is_signed_and_enforced() {
+        let (mut wallet, clock, signers) = wallet(0);
+        let window = TimeWindow {
+            not_before: Some(START + HOUR),
+            expires_at: Some(START + 2 * HOUR),
+        };
+        let id = wallet.propose_within("alice", transfer(5), window).unwrap();
+        let proposal = wallet.proposal(&id).unwrap();
+        let unbounded = Proposal::new("alice", transfer(5), TimeWindow::default(), START);
+        assert_ne!(
+            wallet.signing_payload(proposal),
+            wallet.signing_payload(&unbounded)
+        );
+        approve(&mut wallet, &id, &signers);
+        assert!(matches!(
+            wallet.execute_transaction(&id),
+            Err(WalletError::TooEarly { .. })
+        ));
+        clock.set(START + 2 * HOUR);
+        assert!(matches!(
+            wallet.execute_transaction(&id),
+            Err(WalletError::Expired { .. })
+        ));
+        assert!(matches!(
+            wallet.propose_within("alice", transfer(5), window),
+            Err(WalletError::InvalidTimeWindow(_))
+        ));
+    }
+
+    #[test]
+    fn transaction_expiry_is_enforced() {
+        let (mut wallet, clock, signers) = wallet(0);
+        let mut action = transfer(5);
+        if let Action::Transfer(transaction) = &mut action {
+            transaction.expiry = Some(START + HOUR);
+        }
+        let id = wallet
+            .propose_within("alice", action.clone(), TimeWindow::default())
+            .unwrap();
+        approve(&mut wallet, &id, &signers);
+        clock.set(START + HOUR);
+        assert!(wallet.verify_transaction(&id).unwrap());
+        clock.advance(1);
+        assert!(!wallet.verify_transaction(&id).unwrap());
+        assert!(matches!(
+            wallet.execute_transaction(&id),
+            Err(WalletError::Expired { at, .. }) if at == START + HOUR + 1
+        ));
+        assert_eq!(
+            wallet.approval(&id).unwrap().expires_at,
+            Some(START + HOUR + 1)
+        );
+        assert!(matches!(
+            wallet.propose_within("alice", action, TimeWindow::default()),
+            Err(WalletError::Usage(_))
+        ));
+    }
+
+    #[test]
+    fn owner_names_must_fit_in_policies() {
+        let (mut wallet, _, signers) = wallet(0);
+        let key = OwnerKey::single(signers[0].public_key().unwrap()).unwrap();
+        let owners = HashMap::from([("Alice Smith".to_string(), key.clone())]);
+        assert!(matches!(
+            QuantumSafeWallet::new("testnet", owners, 1),
+            Err(WalletError::InvalidOwnerName(_))
+        ));
+        wallet
+            .propose_change(
+                "alice",
+                OwnerChange::AddOwner {
+                    owner: "carol@ops".to_string(),
+                    key: key.clone(),
+                },
+            )
+            .unwrap();
+        assert!(matches!(
+            wallet.propose_change(
+                "alice",
+                OwnerChange::AddOwner {
+                    owner: "Carol Jones".to_string(),
+                    key,
+                },
+            ),
+            Err(WalletError::InvalidOwnerName(_))
+        ));
+    }
+
+    #[test]
+    fn later_nonces_are_not_executable_yet() {
+        let (mut wallet, _, signers) = wallet(0);
+        let first = wallet
+            .propose_within("alice", transfer(5), TimeWindow::default())
+            .unwrap();
+        let second = wallet
+            .propose_within("alice", transfer(6), TimeWindow::default())
+            .unwrap();
+        approve(&mut wallet, &second, &signers);
+        assert!(!wallet.verify_transaction(&second).unwrap());
+        assert!(matches!(
+            wallet.check_executable(&second),
+            Err(WalletError::OutOfOrder {
+                expected: 0,
+                actual: 1,
+                ..
+            })
+        ));
+        approve(&mut wallet, &first, &signers);
+        wallet.execute_transaction(&first).unwrap();
+        assert!(wallet.verify_transaction(&second).unwrap());
+        wallet.execute_transaction(&second).unwrap();
+    }
+
+    #[test]
+    fn weight_totals_cannot_overflow() {
+        let (mut wallet, _, signers) = wallet(0);
+        let weights = HashMap::from([("alice".to_string(), usize::MAX)]);
+        assert!(matches!(
+            QuantumSafeWallet::weighted("testnet", owners(&signers), weights, 1),
+            Err(WalletError::InvalidWeight { .. })
+        ));
+        assert!(matches!(
+            wallet.propose_change(
+                "alice",
+                OwnerChange::SetWeight {
+                    owner: "bob".to_string(),
+                    weight: usize::MAX,
+                },
+            ),
+            Err(WalletError::InvalidWeight { .. })
+        ));
+    }
+
+    #[test]
+    fn owner_changes_must_leave_a_valid_wallet() {
+        let (mut wallet, _, signers) = wallet(0);
+        let key = OwnerKey::single(signers[0].public_key().unwrap()).unwrap();
+        assert!(matches!(
+            wallet.propose_change(
+                "alice",
+                OwnerChange::AddOwner {
+                    owner: "alice".to_string(),
+                    key: key.clone(),
+                },
+            ),
+            Err(WalletError::OwnerExists(_))
+        ));
+        assert!(matches!(
+            wallet.propose_change(
+                "alice",
+                OwnerChange::ReplaceKey {
+                    owner: "carol".to_string(),
+                    key,
+                },
+            ),
+            Err(WalletError::UnknownOwner(_))
+        ));
+        assert!(matches!(
+            wallet.propose_change(
+                "alice",
+                OwnerChange::RemoveOwner {
+                    owner: "bob".to_string(),
+                },
+            ),
+            Err(WalletError::InvalidThreshold { .. })
+        ));
+        for required in [0, 3] {
+            assert!(matches!(
+                wallet.propose_change("alice", OwnerChange::SetThreshold { required }),
+                Err(WalletError::InvalidThreshold { .. })
+            ));
+        }
+        assert_eq!(wallet.proposals().count(), 0);
+    }
+
+    #[test]
+    fn owner_changes_drop_stale_signatures() {
+        let signers: Vec<MemorySigner> = (0..3)
+            .map(|_| MemorySigner::generate(Algorithm::MlDsa44))
+            .collect();
+        let names = ["alice", "bob", "carol"];
+        let owners = names
+            .iter()
+            .zip(&signers)
+            .map(|(name, signer)| {
+                let key = OwnerKey::single(signer.public_key().unwrap()).unwrap();
+                (name.to_string(), key)
+            })
+            .collect();
+        let mut wallet = QuantumSafeWallet::new("testnet", owners, 2)
+            .unwrap()
+            .with_clock(Arc::new(ManualClock::new(START)));
+        let sign = |wallet: &mut QuantumSafeWallet, id: &ProposalId, owners: &[usize]| {
+            for &owner in owners {
+                wallet
+                    .sign_transaction(id, names[owner], &signers[owner])
+                    .unwrap();
+            }
+        };
+
+        let removal = wallet
+            .propose_change(
+                "alice",
+                OwnerChange::RemoveOwner {
+                    owner: "carol".to_string(),
+                },
+            )
+            .unwrap();
+        let rekey = wallet
+            .propose_change(
+                "alice",
+                OwnerChange::ReplaceKey {
+                    owner: "alice".to_string(),
+                    key: OwnerKey::single(
+                        MemorySigner::generate(Algorithm::MlDsa44)
+                            .public_key()
+                            .unwrap(),
+                    )
+                    .unwrap(),
+                },
+            )
+            .unwrap();
+        let payment = wallet
+            .propose_within("bob", transfer(5), TimeWindow::default())
+            .unwrap();
+        sign(&mut wallet, &removal, &[0, 1]);
+        sign(&mut wallet, &rekey, &[0, 1]);
+        sign(&mut wallet, &payment, &[0, 1, 2]);
+        assert_eq!(
+            wallet.approvals(&payment).unwrap(),
+            ["alice", "bob", "carol"]
+        );
+
+        wallet.execute_transaction(&removal).unwrap();
+        assert!(wallet.owner_key("carol").is_none());
+        assert_eq!(wallet.approvals(&payment).unwrap(), ["alice", "bob"]);
+        assert!(!wallet
+            .proposal(&payment)
+            .unwrap()
+            .signatures()
+            .contains_key("carol"));
+
+        wallet.execute_transaction(&rekey).unwrap();
+        assert_eq!(wallet.approvals(&payment).unwrap(), ["bob"]);
+        assert!(!wallet.verify_transaction(&payment).unwrap());
+        assert!(matches!(
+            wallet.execute_transaction(&payment),
+            Err(WalletError::NotApproved(_))
+        ));
+    }
+
+    #[test]
+    fn cancel_window_closes_when_the_delay_ends() {
+        let (mut wallet, clock, signers) = wallet(HOUR);
+        let first = wallet
+            .propose_within("alice", transfer(5), TimeWindow::default())
+            .unwrap();
+        let second = wallet
+            .propose_within("alice", transfer(6), TimeWindow::default())
+            .unwrap();
+        approve(&mut wallet, &first, &signers);
+        approve(&mut wallet, &second, &signers);
+        clock.advance(HOUR - 1);
+        wallet.cancel(&first, "bob").unwrap();
+        assert_eq!(wallet.nonce(), 1);
+        clock.advance(1);
+        assert!(matches!(
+            wallet.cancel(&second, "bob"),
+            Err(WalletError::CancelWindowClosed { .. })
+        ));
+        wallet.execute_transaction(&second).unwrap();
+    }
+}